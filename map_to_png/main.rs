@@ -0,0 +1,45 @@
+extern crate roll_play_ge;
+
+use roll_play_ge::map_image;
+use roll_play_ge::tilesheet;
+use std::path::Path;
+use std::process;
+
+// Usage: map_to_png [--walkable] [--objects] [--spawns] <map.tmx> <out.png>
+//
+// Renders every visible layer of a map into a PNG without opening a window, optionally with
+// walkability, object outlines and spawn points drawn on top.
+
+fn usage() -> ! {
+    eprintln!("usage: map_to_png [--walkable] [--objects] [--spawns] <map.tmx> <out.png>");
+    process::exit(2);
+}
+
+fn main() {
+    let mut overlays = map_image::Overlays::default();
+    let mut paths = Vec::new();
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--walkable" => overlays.walkability = true,
+            "--objects" => overlays.objects = true,
+            "--spawns" => overlays.spawns = true,
+            _ if arg.starts_with("--") => usage(),
+            _ => paths.push(arg),
+        }
+    }
+    if paths.len() != 2 {
+        usage();
+    }
+
+    let tilesheet = match tilesheet::Tilesheet::from_path(Path::new(&paths[0])) {
+        Ok(tilesheet) => tilesheet,
+        Err(e) => {
+            eprintln!("{}: failed to load: {:?}", paths[0], e);
+            process::exit(1);
+        }
+    };
+    if let Err(e) = map_image::save_png(&tilesheet, &overlays, Path::new(&paths[1])) {
+        eprintln!("{}: failed to write: {}", paths[1], e);
+        process::exit(1);
+    }
+}
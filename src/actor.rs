@@ -5,10 +5,22 @@ use std;
 use std::cell;
 use gfx_texture::ImageSize;
 use std::collections::HashMap;
+use animation::{Directions, Facing};
+use ai;
+use animation_state::{ActionState, AnimationStateMachine};
 use controller;
+use coordinates;
+use guard;
+use melee;
+use projectile;
+use stats;
 use tilesheet;
+use world;
 
-static GROUND_LAYER_INDEX: usize = 0;
+/// Whether actors may stand at `point` in `world`.
+pub fn is_walkable(world: &world::World, point: coordinates::WorldPoint) -> bool {
+    world.is_walkable(tilesheet::GROUND_LAYER_INDEX, point)
+}
 
 pub struct CharacterSheet {
     texture: std::rc::Rc<G2dTexture>,
@@ -88,26 +100,67 @@ impl CharacterSheet {
             src_rect,
         ))
     }
+
+    /// A sprite showing `src_rect` `(x,y,width,height)` of the texture, for sheets whose frames
+    /// are not laid out on a grid (e.g. packed by Aseprite).
+    /// Returns an error if `src_rect` reaches outside the texture.
+    pub fn sprite_rect(&self, src_rect: [f64; 4]) -> Result<sprite::Sprite<G2dTexture>, String> {
+        let (width, height) = self.texture.get_size();
+        if src_rect[0] < 0.0 || src_rect[1] < 0.0 ||
+            src_rect[0] + src_rect[2] > width as f64 ||
+            src_rect[1] + src_rect[3] > height as f64
+        {
+            return Err(String::from("src_rect must lie within the texture"));
+        }
+        Ok(sprite::Sprite::<G2dTexture>::from_texture_rect(
+            self.texture.clone(),
+            src_rect,
+        ))
+    }
 }
 
 pub struct ChooserArgs {
     pub vx: f32,
     pub vy: f32,
+    /// The way the actor faces. Kept while it stands still.
+    pub facing: Facing,
     pub game_time_s: f64,
 }
 
 impl ChooserArgs {
-    pub fn from_vals(vx: f32, vy: f32, game_time_s: f64) -> Self {
+    pub fn new(vx: f32, vy: f32, facing: Facing, game_time_s: f64) -> Self {
         ChooserArgs {
             vx: vx,
             vy: vy,
+            facing: facing,
             game_time_s: game_time_s,
         }
     }
+
+    /// Args for an actor facing the four-way direction of its velocity, or down if still.
+    pub fn from_vals(vx: f32, vy: f32, game_time_s: f64) -> Self {
+        let facing = Facing::from_velocity(Facing::Down, vx, vy, Directions::Four);
+        ChooserArgs::new(vx, vy, facing, game_time_s)
+    }
 }
 
 pub trait SpriteChooser {
     fn choose(&mut self, args: ChooserArgs) -> Option<&sprite::Sprite<G2dTexture>>;
+
+    /// Makes the next `choose` start the animation from its first frame.
+    fn restart(&mut self) {}
+
+    /// Names of the events on the frames playback reached since the previous call, or since
+    /// `restart`. See `animation`.
+    fn take_events(&mut self, _args: &ChooserArgs) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Seconds one pass through the animation takes, if known. One-shot actions (see
+    /// `animation_state`) last this long.
+    fn duration_s(&self) -> Option<f64> {
+        None
+    }
 }
 
 fn choose_walking_column(dt: f64, args: &ChooserArgs) -> u32 {
@@ -162,24 +215,18 @@ mod tests {
     }
 }
 
-fn choose_walking_row(previous_row: u32, args: &ChooserArgs) -> u32 {
-    if args.vx > 0.0 {
-        1 // Right
-    } else if args.vx < 0.0 {
-        2 // Left
-    } else if args.vy > 0.0 {
-        3 // Down
-    } else if args.vy < 0.0 {
-        0 // Up
-    } else {
-        previous_row
+fn choose_walking_row(args: &ChooserArgs) -> u32 {
+    match args.facing.cardinal() {
+        Facing::Up => 0,
+        Facing::Right => 1,
+        Facing::Left => 2,
+        _ => 3, // Down
     }
 }
 
 pub struct WalkingSpriteChooser {
     sprite: Vec<sprite::Sprite<G2dTexture>>,
     dt: f64, // time between sprite transitions
-    previous_row: u32, // previous row used from the sprite sheet
 }
 
 impl WalkingSpriteChooser {
@@ -204,7 +251,6 @@ impl WalkingSpriteChooser {
         Ok(WalkingSpriteChooser {
             sprite: sprite_list,
             dt: dt,
-            previous_row: 0,
         })
     }
 }
@@ -218,8 +264,8 @@ impl SpriteChooser for WalkingSpriteChooser {
             }
             _ => Some({
                 let mut column = choose_walking_column(self.dt, &args);
-                self.previous_row = choose_walking_row(self.previous_row, &args);
-                let idx = (self.previous_row * COLUMNS + column) as usize;
+                let row = choose_walking_row(&args);
+                let idx = (row * COLUMNS + column) as usize;
                 &self.sprite[idx]
             }),
         }
@@ -236,7 +282,26 @@ pub struct Actor {
     pub y: f32, // in px
     pub vx: f32, // in px per frame
     pub vy: f32, // in px per frame
+    /// The way the actor faces; kept when it stops, so idle poses face the way it walked.
+    pub facing: Facing,
+    /// Whether `facing` may be diagonal. Set by `animation::AnimationSet::animate`.
+    pub directions: Directions,
+    /// Drives the actor if it is an NPC. See `ai::update`.
+    pub brain: Option<ai::Brain>,
+    /// Hit points, walking speed and so on.
+    pub stats: stats::Stats,
+    pub melee: melee::Melee,
+    /// Blocks and parries hits while the actor defends.
+    pub guard: guard::Guard,
+    /// What the actor fires on its animation's `"spawn_projectile"` frame event, if anything.
+    /// See `projectile::fire_from_events`.
+    pub projectile: Option<projectile::ProjectileDef>,
+    /// The area `(x, y, width, height)` that attacks must hit, relative to the actor's position.
+    pub hurtbox: [f64; 4],
     chooser_map: HashMap<String, cell::RefCell<Box<SpriteChooser>>>,
+    state_machine: AnimationStateMachine,
+    /// Events emitted by the last `update_animation`.
+    events: Vec<String>,
 }
 
 impl Actor {
@@ -257,24 +322,274 @@ impl Actor {
             y: 0.0,
             vx: 0.0,
             vy: 0.0,
+            facing: Facing::Down,
+            directions: Directions::Four,
+            brain: None,
+            stats: stats::Stats::default(),
+            melee: melee::Melee::default(),
+            guard: guard::Guard::default(),
+            projectile: None,
+            hurtbox: [-12.0, -18.0, 24.0, 36.0],
             chooser_map: HashMap::<String, cell::RefCell<Box<SpriteChooser>>>::new(),
+            state_machine: AnimationStateMachine::new(),
+            events: Vec::new(),
+        }
+    }
+
+    /// The actor's position in map pixels.
+    pub fn position(&self) -> coordinates::WorldPoint {
+        coordinates::WorldPoint::new(self.x as f64, self.y as f64)
+    }
+
+    pub fn state(&self) -> ActionState {
+        self.state_machine.state()
+    }
+
+    /// The chooser for the current state's animation, restarted if the state just changed.
+    /// States without an animation of their own borrow the walk animation, so sheets that only
+    /// have that still draw in every state.
+    fn state_chooser(&mut self) -> Option<&cell::RefCell<Box<SpriteChooser>>> {
+        let restart = self.state_machine.take_entered();
+        let name = self.state_machine.state().animation_name();
+        let chooser_map = &self.chooser_map;
+        let chooser = match chooser_map.get(name) {
+            Some(chooser) => Some(chooser),
+            None => chooser_map.get(ActionState::Walk.animation_name()),
+        };
+        if let Some(chooser) = chooser {
+            if restart {
+                chooser.borrow_mut().restart();
+            }
         }
+        chooser
     }
 
+    /// Starts one-shot action `state` (attack, hurt or die), lasting as long as its animation.
+    /// Returns false if a more important action is already playing.
+    /// See `animation_state::AnimationStateMachine::trigger`.
+    pub fn trigger(&mut self, state: ActionState) -> bool {
+        let duration_s = match self.chooser_map.get(state.animation_name()) {
+            Some(chooser) => chooser.borrow().duration_s(),
+            None => None,
+        };
+        self.state_machine.trigger(state, duration_s)
+    }
+
+    /// The hurtbox in world coordinates.
+    pub fn hurtbox_rect(&self) -> [f64; 4] {
+        [
+            self.x as f64 + self.hurtbox[0],
+            self.y as f64 + self.hurtbox[1],
+            self.hurtbox[2],
+            self.hurtbox[3],
+        ]
+    }
+
+    /// Swings the melee weapon where the actor faces, if its cooldown has passed and nothing
+    /// more important is going on. Returns whether a swing started. See `melee`.
+    pub fn attack(&mut self) -> bool {
+        if !self.melee.is_ready() || !self.trigger(ActionState::Attack) {
+            return false;
+        }
+        self.melee.start();
+        true
+    }
+
+    /// Loses `amount` HP (see `stats::Stats::take_damage`), flinching, or dying if none are left.
+    pub fn take_damage(&mut self, amount: f64) -> stats::DamageResult {
+        let result = self.stats.take_damage(amount);
+        if result.died {
+            self.trigger(ActionState::Die);
+        } else if result.dealt > 0.0 {
+            self.trigger(ActionState::Hurt);
+        }
+        result
+    }
+
+    /// Takes a hit of `amount` damage coming from `source`, which the guard may block or parry.
+    /// Blocked hits do not make the actor flinch. See `guard::Guard::block`.
+    pub fn take_hit(
+        &mut self,
+        amount: f64,
+        source: coordinates::WorldPoint,
+    ) -> (guard::Block, stats::DamageResult) {
+        let (position, facing) = (self.position(), self.facing);
+        let block = self.guard.block(position, facing, source, amount);
+        let result = match block {
+            guard::Block::Unguarded => self.take_damage(amount),
+            guard::Block::Blocked { damage } => {
+                let result = self.stats.take_damage(damage);
+                if result.died {
+                    self.trigger(ActionState::Die);
+                }
+                result
+            }
+            guard::Block::Parried => {
+                stats::DamageResult {
+                    dealt: 0.0,
+                    died: false,
+                }
+            }
+        };
+        (block, result)
+    }
+
+    /// Stuns the actor for `duration_s` seconds, e.g. after its attack was parried. Returns
+    /// false if a more important action is already playing.
+    pub fn stun(&mut self, duration_s: f64) -> bool {
+        if !self.state_machine.trigger(ActionState::Stun, Some(duration_s)) {
+            return false;
+        }
+        self.melee.cancel();
+        self.guard.set_raised(false);
+        true
+    }
+
+    /// Brings a dead actor back with `hp` HP.
+    pub fn revive(&mut self, hp: f64) {
+        self.stats.revive(hp);
+        self.state_machine.revive();
+    }
+
+    pub fn is_dead(&self) -> bool {
+        self.stats.is_dead()
+    }
+
+    /// Advances one-shot actions (see `trigger`), timed stat modifiers and the guard's stamina
+    /// by `dt_s` seconds. `Game` calls this once per update event for every actor, before
+    /// steering.
+    pub fn update_state(&mut self, dt_s: f64) {
+        self.state_machine.update(dt_s);
+        self.stats.update(dt_s);
+        self.guard.update(dt_s);
+    }
+
+    /// Walks for `dt_s` seconds toward direction `(dx, dy)` (any length; zero stands still) at
+    /// `speed` px/s, the same speed diagonally as straight. The actor stays put instead if the
+    /// step would leave walkable ground, or if its current action does not allow walking.
+    /// `defending` raises the guard, which slows the actor down (see `guard`).
+    /// Returns false if the step was blocked by the ground.
+    pub fn steer(
+        &mut self,
+        dx: f32,
+        dy: f32,
+        speed: f32,
+        defending: bool,
+        dt_s: f64,
+        world: &world::World,
+    ) -> bool {
+        self.steer_on(dx, dy, speed, defending, dt_s, &|point| is_walkable(world, point))
+    }
+
+    /// Like `steer`, but on the ground that `walkable` describes.
+    pub fn steer_on(
+        &mut self,
+        dx: f32,
+        dy: f32,
+        speed: f32,
+        defending: bool,
+        dt_s: f64,
+        walkable: &Fn(coordinates::WorldPoint) -> bool,
+    ) -> bool {
+        let can_act = self.state().allows_movement();
+        let defending = self.guard.set_raised(defending && can_act);
+        let speed = if defending {
+            speed * self.guard.shield.speed_factor as f32
+        } else {
+            speed
+        };
+        let length = (dx * dx + dy * dy).sqrt();
+        if length > 0.0 && can_act {
+            self.vx = speed * dx / length;
+            self.vy = speed * dy / length;
+        } else {
+            self.vx = 0.0;
+            self.vy = 0.0;
+        }
+        self.facing = Facing::from_velocity(self.facing, self.vx, self.vy, self.directions);
+        self.state_machine.set_base(
+            self.vx != 0.0 || self.vy != 0.0,
+            defending,
+        );
+        let proposed_y = self.y + (dt_s * self.vy as f64) as f32;
+        let proposed_x = self.x + (dt_s * self.vx as f64) as f32;
+        let proposed = coordinates::WorldPoint::new(proposed_x as f64, proposed_y as f64);
+        if walkable(proposed) {
+            self.x = proposed_x;
+            self.y = proposed_y;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Advances the actor by `dt_s` seconds, following `controller`'s input. Call on update
+    /// events only, with their `dt`, after `update_state`.
     pub fn control(
         &mut self,
         controller: &controller::Controller,
-        tilesheet: &tilesheet::Tilesheet,
+        dt_s: f64,
+        world: &world::World,
     ) {
-        self.vy = controller.walk_rate *
-            ((controller.input.up as i32) - (controller.input.down as i32)) as f32;
-        self.vx = controller.walk_rate *
-            ((controller.input.left as i32) - (controller.input.right as i32)) as f32;
-        let proposed_y = self.y + (controller.dt_s * self.vy as f64) as f32;
-        let proposed_x = self.x + (controller.dt_s * self.vx as f64) as f32;
-        if tilesheet.is_walkable(GROUND_LAYER_INDEX, proposed_x, proposed_y) {
-            self.x = proposed_x;
-            self.y = proposed_y;
+        if controller.input.attack {
+            self.attack();
+        }
+
+        // Map y grows downward, so "up" is negative.
+        let dy = ((controller.input.down as i32) - (controller.input.up as i32)) as f32;
+        let dx = ((controller.input.right as i32) - (controller.input.left as i32)) as f32;
+        self.steer(
+            dx,
+            dy,
+            self.stats.speed() as f32,
+            controller.input.defend,
+            dt_s,
+            world,
+        );
+    }
+
+    fn chooser_args(&self, game_time_s: f64) -> ChooserArgs {
+        ChooserArgs::new(self.vx, self.vy, self.facing, game_time_s)
+    }
+
+    /// Advances the current state's animation to `game_time_s`, replacing `events` with the
+    /// events of the frames it reached. Call once per update, after `control`.
+    pub fn update_animation(&mut self, game_time_s: f64) {
+        let args = self.chooser_args(game_time_s);
+        let events = match self.state_chooser() {
+            Some(chooser) => chooser.borrow_mut().take_events(&args),
+            None => Vec::new(),
+        };
+        self.events = events;
+    }
+
+    /// Events (e.g. `"footstep"` or `"hit"`) of the animation frames reached during the last
+    /// `update_animation`.
+    pub fn events(&self) -> &[String] {
+        &self.events
+    }
+
+    /// Draws the animation of the actor's current state. See `state`.
+    pub fn draw_state(
+        &mut self,
+        game_time_s: f64,
+        transform: Matrix2d,
+        renderer: &mut G2d,
+    ) -> Result<(), ActorDrawError> {
+        let args = self.chooser_args(game_time_s);
+        let chooser = match self.state_chooser() {
+            Some(chooser) => chooser,
+            None => {
+                return Err(ActorDrawError::NoSuchName);
+            }
+        };
+        let mut borrow = chooser.borrow_mut();
+        match borrow.choose(args) {
+            Some(sprite) => {
+                sprite.draw(transform, renderer);
+                Ok(())
+            }
+            None => Err(ActorDrawError::NothingToDraw),
         }
     }
 
@@ -286,10 +601,11 @@ impl Actor {
         renderer: &mut G2d,
     ) -> Result<(), ActorDrawError> {
         // Draw the correct frame from the correct animation.
+        let args = self.chooser_args(game_time_s);
         match self.chooser_map.get_mut(name) {
             Some(chooser) => {
                 let mut borrow = chooser.borrow_mut();
-                let choice = borrow.choose(ChooserArgs::from_vals(self.vx, self.vy, game_time_s));
+                let choice = borrow.choose(args);
                 match choice {
                     Some(sprite) => {
                         sprite.draw(transform, renderer);
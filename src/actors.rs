@@ -0,0 +1,213 @@
+//! Every actor in the game (the hero, NPCs, enemies, remote players) under a stable ID.
+
+use std::{cmp, slice};
+use std::collections::{HashMap, HashSet};
+use actor::Actor;
+use uuid::Uuid;
+
+/// Identifies one actor for as long as it is spawned. Never reused.
+pub type ActorId = Uuid;
+
+struct Entry {
+    id: ActorId,
+    tags: HashSet<String>,
+    actor: Actor,
+}
+
+/// Holds any number of actors, in the order they were spawned. That is also their update
+/// order; see `draw_order` for drawing.
+///
+/// Actors may carry any number of tags (e.g. `"hero"`, `"npc"` or `"enemy"`) to find them by.
+pub struct Actors {
+    entries: Vec<Entry>,
+    /// Position of each actor in `entries`.
+    indices: HashMap<ActorId, usize>,
+}
+
+impl Actors {
+    pub fn new() -> Self {
+        Actors {
+            entries: Vec::new(),
+            indices: HashMap::new(),
+        }
+    }
+
+    /// Adds `actor` with `tags`, returning its new ID.
+    pub fn spawn(&mut self, actor: Actor, tags: &[&str]) -> ActorId {
+        let id = Uuid::new_v4();
+        self.indices.insert(id, self.entries.len());
+        self.entries.push(Entry {
+            id: id,
+            tags: tags.iter().map(|tag| String::from(*tag)).collect(),
+            actor: actor,
+        });
+        id
+    }
+
+    /// Removes actor `id`, returning it, or `None` if there is no such actor.
+    pub fn despawn(&mut self, id: ActorId) -> Option<Actor> {
+        let index = self.indices.remove(&id)?;
+        let entry = self.entries.remove(index);
+        for later in self.entries[index..].iter() {
+            if let Some(i) = self.indices.get_mut(&later.id) {
+                *i -= 1;
+            }
+        }
+        Some(entry.actor)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn contains(&self, id: ActorId) -> bool {
+        self.indices.contains_key(&id)
+    }
+
+    pub fn get(&self, id: ActorId) -> Option<&Actor> {
+        match self.indices.get(&id) {
+            Some(&index) => Some(&self.entries[index].actor),
+            None => None,
+        }
+    }
+
+    pub fn get_mut(&mut self, id: ActorId) -> Option<&mut Actor> {
+        match self.indices.get(&id) {
+            Some(&index) => Some(&mut self.entries[index].actor),
+            None => None,
+        }
+    }
+
+    /// Adds `tag` to actor `id`. Returns false if there is no such actor.
+    pub fn add_tag(&mut self, id: ActorId, tag: &str) -> bool {
+        match self.indices.get(&id) {
+            Some(&index) => {
+                self.entries[index].tags.insert(String::from(tag));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Removes `tag` from actor `id`. Returns false if the actor did not have it.
+    pub fn remove_tag(&mut self, id: ActorId, tag: &str) -> bool {
+        match self.indices.get(&id) {
+            Some(&index) => self.entries[index].tags.remove(tag),
+            None => false,
+        }
+    }
+
+    pub fn has_tag(&self, id: ActorId, tag: &str) -> bool {
+        match self.indices.get(&id) {
+            Some(&index) => self.entries[index].tags.contains(tag),
+            None => false,
+        }
+    }
+
+    /// IDs of every actor tagged `tag`, in update order.
+    pub fn with_tag(&self, tag: &str) -> Vec<ActorId> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.tags.contains(tag))
+            .map(|entry| entry.id)
+            .collect()
+    }
+
+    /// IDs of every actor, in update order. Actors may be spawned or despawned while going
+    /// through the list; look each one up with `get` or `get_mut`.
+    pub fn ids(&self) -> Vec<ActorId> {
+        self.entries.iter().map(|entry| entry.id).collect()
+    }
+
+    /// Every actor, in update order.
+    pub fn iter(&self) -> Iter {
+        Iter { entries: self.entries.iter() }
+    }
+
+    /// Every actor, in update order. Use `ids` instead to spawn or despawn along the way.
+    pub fn iter_mut(&mut self) -> IterMut {
+        IterMut { entries: self.entries.iter_mut() }
+    }
+
+    /// IDs of every actor in drawing order: from the top of the world down, so that actors
+    /// further down overlap those behind them. Actors level with each other keep update order.
+    pub fn draw_order(&self) -> Vec<ActorId> {
+        let mut entries: Vec<&Entry> = self.entries.iter().collect();
+        entries.sort_by(|a, b| {
+            a.actor.y.partial_cmp(&b.actor.y).unwrap_or(cmp::Ordering::Equal)
+        });
+        entries.iter().map(|entry| entry.id).collect()
+    }
+}
+
+/// Iterator over `(id, actor)` pairs; see `Actors::iter`.
+pub struct Iter<'a> {
+    entries: slice::Iter<'a, Entry>,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (ActorId, &'a Actor);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.entries.next().map(|entry| (entry.id, &entry.actor))
+    }
+}
+
+/// Iterator over `(id, actor)` pairs; see `Actors::iter_mut`.
+pub struct IterMut<'a> {
+    entries: slice::IterMut<'a, Entry>,
+}
+
+impl<'a> Iterator for IterMut<'a> {
+    type Item = (ActorId, &'a mut Actor);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.entries.next().map(|entry| (entry.id, &mut entry.actor))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn actor_at(y: f32) -> Actor {
+        let mut actor = Actor::new();
+        actor.y = y;
+        actor
+    }
+
+    #[test]
+    fn spawn_lookup_despawn() {
+        let mut actors = Actors::new();
+        let hero = actors.spawn(actor_at(0.0), &["hero"]);
+        let a = actors.spawn(actor_at(30.0), &["npc"]);
+        let b = actors.spawn(actor_at(10.0), &["npc", "enemy"]);
+        assert_eq!(3, actors.len());
+        assert_eq!(vec![a, b], actors.with_tag("npc"));
+        assert!(actors.has_tag(b, "enemy"));
+        assert_eq!(vec![hero, b, a], actors.draw_order());
+
+        assert_eq!(Some(30.0), actors.despawn(a).map(|actor| actor.y));
+        assert!(!actors.contains(a));
+        assert!(actors.despawn(a).is_none());
+        assert_eq!(vec![hero, b], actors.ids());
+        assert_eq!(Some(10.0), actors.get(b).map(|actor| actor.y));
+
+        assert!(actors.add_tag(hero, "player"));
+        assert_eq!(vec![hero], actors.with_tag("player"));
+        assert!(actors.remove_tag(hero, "player"));
+        assert!(actors.with_tag("player").is_empty());
+
+        for (id, actor) in actors.iter_mut() {
+            if id == b {
+                actor.y = 20.0;
+            }
+        }
+        let ys: Vec<(ActorId, f32)> = actors.iter().map(|(id, actor)| (id, actor.y)).collect();
+        assert_eq!(vec![(hero, 0.0), (b, 20.0)], ys);
+    }
+}
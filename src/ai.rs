@@ -0,0 +1,335 @@
+//! NPC behavior, driven by `ai_behavior` behavior trees.
+//!
+//! An actor with a `Brain` (see `actor::Actor::brain`) runs its tree on every event. The
+//! leaves are `NpcAction`s, which walk the actor with `actor::Actor::steer`, so NPCs collide
+//! with the map exactly like the hero. The rest of the tree (sequences, waits, loops, ...) is
+//! plain `ai_behavior`, e.g. wandering with a short rest at every stop:
+//!
+//! ```ignore
+//! forever(Sequence(vec![Action(NpcAction::Wander { radius: 64.0 }), Wait(2.0)]))
+//! ```
+//!
+//! A failing action fails the whole tree unless something like `Select` catches it.
+
+use std::*;
+use std::collections::HashMap;
+use ai_behavior::{Action, Behavior, Select, Sequence, State, Status, Wait, WaitForever, While};
+use piston_window::GenericEvent;
+use rand;
+use actor;
+use actors::{ActorId, Actors};
+use coordinates::WorldPoint;
+use world;
+
+/// How close, in px, an NPC must get to a point to have reached it.
+static ARRIVE_DISTANCE: f64 = 2.0;
+
+/// Seconds NPCs wait between checks that can succeed at once (e.g. "is anyone close?"). A
+/// loop must spend some time every round, or `ai_behavior` would run it forever in one update.
+pub static REACTION_S: f64 = 0.1;
+
+/// The built-in actions NPC behavior trees are made of.
+#[derive(Debug, Clone, PartialEq)]
+pub enum NpcAction {
+    /// Stands still. Succeeds at once; follow it with a `Wait` to idle for a while.
+    Idle,
+    /// Walks to a random point within `radius` px of the brain's home. Succeeds on arrival;
+    /// fails if a wall is in the way.
+    Wander { radius: f64 },
+    /// Walks to a point. Succeeds on arrival; fails if a wall is in the way.
+    MoveTo(WorldPoint),
+    /// Keeps within `distance` px of `target`. Never finishes; fails if the target is gone.
+    Follow { target: ActorId, distance: f64 },
+    /// Runs from `target` until more than `distance` px away, then succeeds. Also succeeds if
+    /// the target is gone; fails if a wall is in the way.
+    Flee { target: ActorId, distance: f64 },
+}
+
+/// What a running action remembers between events. Patrols are whole trees of actions; see
+/// `patrol::Route::behavior`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Progress {
+    /// `Wander`'s destination.
+    Destination(WorldPoint),
+}
+
+/// A behavior tree and the state it runs in. NPCs walk at the speed in their stats.
+pub struct Brain {
+    state: State<NpcAction, Progress>,
+    /// The point `Wander` stays around; usually where the NPC spawned.
+    pub home: WorldPoint,
+}
+
+impl Brain {
+    pub fn new(behavior: Behavior<NpcAction>, home: WorldPoint) -> Self {
+        Brain {
+            state: State::new(behavior),
+            home: home,
+        }
+    }
+
+    /// A brain that stands still at `home` forever.
+    pub fn idle(home: WorldPoint) -> Self {
+        Brain::new(Sequence(vec![Action(NpcAction::Idle), WaitForever]), home)
+    }
+
+    /// A brain that wanders within `radius` px of `home`, resting `rest_s` seconds at each stop.
+    pub fn wander(home: WorldPoint, radius: f64, rest_s: f64) -> Self {
+        Brain::new(
+            forever(Sequence(vec![
+                // Walking into a wall just ends the walk early.
+                Select(vec![
+                    Action(NpcAction::Wander { radius: radius }),
+                    Action(NpcAction::Idle),
+                ]),
+                Action(NpcAction::Idle),
+                Wait(rest_s.max(REACTION_S)),
+            ])),
+            home,
+        )
+    }
+
+    /// A brain that keeps within `distance` px of `target`.
+    pub fn follow(home: WorldPoint, target: ActorId, distance: f64) -> Self {
+        Brain::new(
+            forever(Action(NpcAction::Follow {
+                target: target,
+                distance: distance,
+            })),
+            home,
+        )
+    }
+
+    /// A brain that runs whenever `target` comes within `distance` px.
+    pub fn flee(home: WorldPoint, target: ActorId, distance: f64) -> Self {
+        Brain::new(
+            forever(Sequence(vec![
+                Select(vec![
+                    Action(NpcAction::Flee {
+                        target: target,
+                        distance: distance,
+                    }),
+                    // Cornered.
+                    Action(NpcAction::Idle),
+                ]),
+                Wait(REACTION_S),
+            ])),
+            home,
+        )
+    }
+}
+
+/// Repeats `behavior` for as long as the game runs. Each round must take some time; see
+/// `REACTION_S`.
+pub fn forever(behavior: Behavior<NpcAction>) -> Behavior<NpcAction> {
+    While(Box::new(WaitForever), vec![behavior])
+}
+
+/// A random point within `radius` of `center`, uniformly distributed over the disc.
+fn random_point(center: WorldPoint, radius: f64) -> WorldPoint {
+    let angle = rand::random::<f64>() * 2.0 * f64::consts::PI;
+    let distance = radius * rand::random::<f64>().sqrt();
+    WorldPoint::new(
+        center.x + distance * angle.cos(),
+        center.y + distance * angle.sin(),
+    )
+}
+
+/// Steps `actor` toward `target`. Returns the status of a walk that ends at `target`.
+fn walk_to(
+    actor: &mut actor::Actor,
+    target: WorldPoint,
+    speed: f32,
+    dt_s: f64,
+    walkable: &Fn(WorldPoint) -> bool,
+) -> Status {
+    let position = actor.position();
+    if position.distance(target) <= ARRIVE_DISTANCE {
+        actor.steer_on(0.0, 0.0, speed, false, dt_s, walkable);
+        return Status::Success;
+    }
+    // Do not overshoot the target on the last step.
+    let step = (speed as f64 * dt_s).max(f64::EPSILON);
+    let scale = (position.distance(target) / step).min(1.0) as f32;
+    let dx = (target.x - position.x) as f32;
+    let dy = (target.y - position.y) as f32;
+    if actor.steer_on(dx, dy, speed * scale, false, dt_s, walkable) {
+        Status::Running
+    } else {
+        Status::Failure
+    }
+}
+
+/// What actions see of the world around the actor running them.
+struct Surroundings<'a> {
+    /// Whether the actor may stand at a point; see `actor::is_walkable`.
+    walkable: &'a Fn(WorldPoint) -> bool,
+    /// Every actor's position, for targets.
+    positions: HashMap<ActorId, WorldPoint>,
+}
+
+/// Runs one action for one event, `dt_s` seconds after the previous one.
+fn run_action(
+    action: &NpcAction,
+    progress: &mut Option<Progress>,
+    actor: &mut actor::Actor,
+    home: WorldPoint,
+    speed: f32,
+    dt_s: f64,
+    surroundings: &Surroundings,
+) -> Status {
+    let (walkable, positions) = (surroundings.walkable, &surroundings.positions);
+    match *action {
+        NpcAction::Idle => {
+            actor.steer_on(0.0, 0.0, speed, false, dt_s, walkable);
+            Status::Success
+        }
+        NpcAction::Wander { radius } => {
+            let destination = match *progress {
+                Some(Progress::Destination(point)) => point,
+                _ => random_point(home, radius),
+            };
+            *progress = Some(Progress::Destination(destination));
+            walk_to(actor, destination, speed, dt_s, walkable)
+        }
+        NpcAction::MoveTo(point) => walk_to(actor, point, speed, dt_s, walkable),
+        NpcAction::Follow { target, distance } => {
+            let target = match positions.get(&target) {
+                Some(&target) => target,
+                None => {
+                    return Status::Failure;
+                }
+            };
+            if actor.position().distance(target) > distance {
+                walk_to(actor, target, speed, dt_s, walkable);
+            } else {
+                actor.steer_on(0.0, 0.0, speed, false, dt_s, walkable);
+            }
+            Status::Running
+        }
+        NpcAction::Flee { target, distance } => {
+            let target = match positions.get(&target) {
+                Some(&target) => target,
+                None => {
+                    return Status::Success;
+                }
+            };
+            let position = actor.position();
+            if position.distance(target) > distance {
+                return Status::Success;
+            }
+            let dx = (position.x - target.x) as f32;
+            let dy = (position.y - target.y) as f32;
+            // Standing right on the target, any direction will do.
+            let (dx, dy) = if dx == 0.0 && dy == 0.0 { (1.0, 0.0) } else { (dx, dy) };
+            if actor.steer_on(dx, dy, speed, false, dt_s, walkable) {
+                Status::Running
+            } else {
+                Status::Failure
+            }
+        }
+    }
+}
+
+/// Runs the brain of every actor that has one for `event`.
+pub fn update<E: GenericEvent>(event: &E, actors: &mut Actors, world: &world::World) {
+    let walkable = |point| actor::is_walkable(world, point);
+    let surroundings = Surroundings {
+        walkable: &walkable,
+        positions: actors.iter().map(|(id, actor)| (id, actor.position())).collect(),
+    };
+    for (_, actor) in actors.iter_mut() {
+        let mut brain = match actor.brain.take() {
+            Some(brain) => brain,
+            None => continue,
+        };
+        let (home, speed) = (brain.home, actor.stats.speed() as f32);
+        brain.state.event(event, &mut |args| {
+            let status = run_action(
+                args.action,
+                args.state,
+                actor,
+                home,
+                speed,
+                args.dt,
+                &surroundings,
+            );
+            match status {
+                Status::Running => (Status::Running, 0.0),
+                status => (status, args.dt),
+            }
+        });
+        actor.brain = Some(brain);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn random_points_stay_within_radius() {
+        let center = WorldPoint::new(100.0, 50.0);
+        for _ in 0..100 {
+            assert!(random_point(center, 30.0).distance(center) <= 30.0 + 1e-9);
+        }
+    }
+
+    fn run(
+        action: &NpcAction,
+        progress: &mut Option<Progress>,
+        actor: &mut actor::Actor,
+        walkable: &Fn(WorldPoint) -> bool,
+        positions: &[(ActorId, WorldPoint)],
+    ) -> Status {
+        let surroundings = Surroundings {
+            walkable: walkable,
+            positions: positions.iter().cloned().collect(),
+        };
+        let home = WorldPoint::new(0.0, 0.0);
+        run_action(action, progress, actor, home, 100.0, 0.1, &surroundings)
+    }
+
+    #[test]
+    fn wander_keeps_its_destination() {
+        let mut actor = actor::Actor::new();
+        let mut progress = None;
+        let wander = NpcAction::Wander { radius: 50.0 };
+        run(&wander, &mut progress, &mut actor, &|_| true, &[]);
+        let destination = progress.clone();
+        assert!(destination.is_some());
+        run(&wander, &mut progress, &mut actor, &|_| true, &[]);
+        assert_eq!(destination, progress);
+    }
+
+    #[test]
+    fn follow_and_flee() {
+        let mut actor = actor::Actor::new();
+        let target = ActorId::new_v4();
+        let near = [(target, WorldPoint::new(5.0, 0.0))];
+        let far = [(target, WorldPoint::new(100.0, 0.0))];
+        let follow = NpcAction::Follow {
+            target: target,
+            distance: 20.0,
+        };
+        let flee = NpcAction::Flee {
+            target: target,
+            distance: 20.0,
+        };
+        let (open, walled): (&Fn(WorldPoint) -> bool, &Fn(WorldPoint) -> bool) =
+            (&|_| true, &|_| false);
+
+        assert_eq!(Status::Failure, run(&follow, &mut None, &mut actor, open, &[]));
+        assert_eq!(Status::Running, run(&follow, &mut None, &mut actor, open, &far));
+        assert!(actor.vx > 0.0 && actor.position().x > 0.0);
+        assert_eq!(Status::Running, run(&follow, &mut None, &mut actor, open, &near));
+        assert_eq!(0.0, actor.vx);
+
+        assert_eq!(Status::Success, run(&flee, &mut None, &mut actor, open, &[]));
+        assert_eq!(Status::Success, run(&flee, &mut None, &mut actor, open, &far));
+        // Following left the actor just right of the target, so it flees further right.
+        assert_eq!(Status::Running, run(&flee, &mut None, &mut actor, open, &near));
+        assert!(actor.vx > 0.0);
+        assert_eq!(Status::Failure, run(&flee, &mut None, &mut actor, walled, &near));
+    }
+}
@@ -0,0 +1,680 @@
+//! Character animations described in a JSON file instead of code, so sheets of any layout can
+//! be animated. A file looks like:
+//!
+//! ```json
+//! {
+//!   "image": "hero_walk.png",
+//!   "columns": 3,
+//!   "rows": 4,
+//!   "animations": {
+//!     "walk": {
+//!       "loop": "ping_pong",
+//!       "frame_duration": 0.2,
+//!       "stopped_frame": 1,
+//!       "facings": {
+//!         "up": [{"column": 0, "row": 0}, {"column": 1, "row": 0}, {"column": 2, "row": 0}],
+//!         "down": [{"column": 0, "row": 3}, {"column": 1, "row": 3, "duration": 0.4}]
+//!       }
+//!     },
+//!     "spin": {"loop": "once", "frames": [{"column": 0, "row": 0}, {"column": 0, "row": 1}]}
+//!   }
+//! }
+//! ```
+//!
+//! `image` is relative to the JSON file. An animation lists either `frames`, played whatever the
+//! actor faces, or `facings`, one frame list per direction; a direction without a list falls back
+//! to `frames`, then to `down`. A frame's `duration` (seconds) defaults to the animation's
+//! `frame_duration`. `stopped_frame`, if set, is the frame shown while the actor stands still.
+//!
+//! A frame may list `events`, e.g. `{"column": 1, "row": 3, "events": ["footstep"]}`, that the
+//! actor emits each time playback reaches the frame (see `actor::Actor::events`), so game code can
+//! land a hit on the swing frame or play footsteps in step with the feet.
+//!
+//! `directions` (4 by default, or 8) is how many ways the character can face. Eight-direction
+//! characters may list `up_left`, `up_right`, `down_left` and `down_right` facings; a diagonal
+//! without a list plays the left or right one.
+
+use std::*;
+use std::collections::HashMap;
+use actor;
+use piston_window::G2dTexture;
+use serde_json;
+use sprite;
+
+#[derive(Debug)]
+pub enum AnimationError {
+    IoError(io::Error),
+    JsonError(serde_json::Error),
+    NoParentPath,
+    /// An animation has no frames at all. Holds the animation name.
+    NoFrames(String),
+    /// A frame lies outside the sheet. Holds (animation name, column, row).
+    FrameOutOfSheet(String, u32, u32),
+    /// `directions` is neither 4 nor 8.
+    BadDirections(u32),
+    /// `stopped_frame` is past the end of a facing's frames. Holds (animation name, facing,
+    /// stopped frame).
+    StoppedFrameOutOfRange(String, Facing, usize),
+}
+
+/// How many directions a character can face. Four-direction characters face the dominant axis
+/// of their movement; eight-direction ones also face diagonals.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Directions {
+    Four,
+    Eight,
+}
+
+/// The direction an actor faces.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Facing {
+    Up,
+    Down,
+    Left,
+    Right,
+    UpLeft,
+    UpRight,
+    DownLeft,
+    DownRight,
+}
+
+impl Facing {
+    pub fn all() -> [Facing; 8] {
+        [
+            Facing::Up,
+            Facing::Down,
+            Facing::Left,
+            Facing::Right,
+            Facing::UpLeft,
+            Facing::UpRight,
+            Facing::DownLeft,
+            Facing::DownRight,
+        ]
+    }
+
+    pub fn is_diagonal(&self) -> bool {
+        match *self {
+            Facing::Up | Facing::Down | Facing::Left | Facing::Right => false,
+            Facing::UpLeft | Facing::UpRight | Facing::DownLeft | Facing::DownRight => true,
+        }
+    }
+
+    /// The nearest of the four main directions. Diagonals turn sideways, which is what sheets
+    /// without diagonal rows look best with.
+    pub fn cardinal(&self) -> Facing {
+        match *self {
+            Facing::UpLeft | Facing::DownLeft => Facing::Left,
+            Facing::UpRight | Facing::DownRight => Facing::Right,
+            facing => facing,
+        }
+    }
+
+    /// Unit vector pointing this way (map y grows downward).
+    pub fn vector(&self) -> (f32, f32) {
+        let d = f32::consts::FRAC_1_SQRT_2;
+        match *self {
+            Facing::Up => (0.0, -1.0),
+            Facing::Down => (0.0, 1.0),
+            Facing::Left => (-1.0, 0.0),
+            Facing::Right => (1.0, 0.0),
+            Facing::UpLeft => (-d, -d),
+            Facing::UpRight => (d, -d),
+            Facing::DownLeft => (-d, d),
+            Facing::DownRight => (d, d),
+        }
+    }
+
+    /// The direction of travel for velocity `(vx, vy)`, or `previous` when standing still, so
+    /// an actor keeps facing the way it last walked.
+    ///
+    /// With four directions the faster axis wins. On an exact diagonal the actor keeps
+    /// `previous` if it is one of the two candidates, and otherwise faces sideways.
+    pub fn from_velocity(previous: Facing, vx: f32, vy: f32, directions: Directions) -> Facing {
+        if vx == 0.0 && vy == 0.0 {
+            return previous;
+        }
+        let horizontal = if vx > 0.0 { Facing::Right } else { Facing::Left };
+        let vertical = if vy > 0.0 { Facing::Down } else { Facing::Up };
+        match directions {
+            Directions::Four => {
+                if vx.abs() > vy.abs() {
+                    horizontal
+                } else if vy.abs() > vx.abs() {
+                    vertical
+                } else if previous == vertical {
+                    vertical
+                } else {
+                    horizontal
+                }
+            }
+            Directions::Eight => {
+                // Octants of 45° centered on each direction, counted clockwise from right.
+                let octant = ((vy as f64).atan2(vx as f64) / f64::consts::FRAC_PI_4).round();
+                match (octant as i32 + 8) % 8 {
+                    0 => Facing::Right,
+                    1 => Facing::DownRight,
+                    2 => Facing::Down,
+                    3 => Facing::DownLeft,
+                    4 => Facing::Left,
+                    5 => Facing::UpLeft,
+                    6 => Facing::Up,
+                    _ => Facing::UpRight,
+                }
+            }
+        }
+    }
+}
+
+/// What happens after the last frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LoopMode {
+    /// Start over from the first frame.
+    Loop,
+    /// Play backwards to the first frame, then forwards again: 0, 1, 2, 1, 0, 1, ...
+    PingPong,
+    /// Hold the last frame.
+    Once,
+}
+
+fn default_loop_mode() -> LoopMode {
+    LoopMode::Loop
+}
+
+fn default_frame_duration() -> f64 {
+    0.1
+}
+
+fn default_directions() -> u32 {
+    4
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct FrameDef {
+    pub column: u32,
+    pub row: u32,
+    /// Seconds; the animation's `frame_duration` if unset.
+    #[serde(default)]
+    pub duration: Option<f64>,
+    /// Names of the events emitted when playback reaches this frame.
+    #[serde(default)]
+    pub events: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct AnimationDef {
+    #[serde(rename = "loop", default = "default_loop_mode")]
+    pub loop_mode: LoopMode,
+    #[serde(default = "default_frame_duration")]
+    pub frame_duration: f64,
+    #[serde(default)]
+    pub frames: Vec<FrameDef>,
+    #[serde(default)]
+    pub facings: HashMap<Facing, Vec<FrameDef>>,
+    #[serde(default)]
+    pub stopped_frame: Option<usize>,
+}
+
+impl AnimationDef {
+    /// The frames played while facing `facing`. See the module docs for the fallback order.
+    pub fn frames_for(&self, facing: Facing) -> &Vec<FrameDef> {
+        match self.facings.get(&facing) {
+            Some(frames) => frames,
+            None if facing.is_diagonal() => self.frames_for(facing.cardinal()),
+            None if !self.frames.is_empty() => &self.frames,
+            None => self.facings.get(&Facing::Down).unwrap_or(&self.frames),
+        }
+    }
+
+    /// Every frame's duration, for `frames_for(facing)`.
+    fn durations(&self, facing: Facing) -> Vec<f64> {
+        self.frames_for(facing)
+            .iter()
+            .map(|frame| frame.duration.unwrap_or(self.frame_duration))
+            .collect()
+    }
+}
+
+/// The contents of an animation file. See the module docs.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct AnimationSet {
+    /// The sheet image, relative to the animation file.
+    pub image: String,
+    /// Number of tile columns in the sheet image.
+    pub columns: u32,
+    /// Number of tile rows in the sheet image.
+    pub rows: u32,
+    /// 4 or 8. See `Directions`.
+    #[serde(default = "default_directions")]
+    pub directions: u32,
+    pub animations: HashMap<String, AnimationDef>,
+
+    /// The directory of the animation file, for resolving `image`.
+    #[serde(skip)]
+    base_path: path::PathBuf,
+}
+
+impl AnimationSet {
+    pub fn from_path(json_path: &path::Path) -> Result<Self, AnimationError> {
+        let json_file = fs::File::open(json_path).map_err(|e| AnimationError::IoError(e))?;
+        let mut set = AnimationSet::from_reader(io::BufReader::new(json_file))?;
+        set.base_path = json_path.parent().ok_or(AnimationError::NoParentPath)?.to_path_buf();
+        Ok(set)
+    }
+
+    /// Reads an animation file. `image` is left relative to the current directory.
+    pub fn from_reader<R: io::Read>(reader: R) -> Result<Self, AnimationError> {
+        let set: AnimationSet = serde_json::from_reader(reader).map_err(
+            |e| AnimationError::JsonError(e),
+        )?;
+        set.check()?;
+        Ok(set)
+    }
+
+    /// Checks `directions`, that every animation has frames, that they all lie on the sheet and
+    /// that `stopped_frame` exists whichever way the actor faces.
+    fn check(&self) -> Result<(), AnimationError> {
+        if self.directions != 4 && self.directions != 8 {
+            return Err(AnimationError::BadDirections(self.directions));
+        }
+        for (name, animation) in self.animations.iter() {
+            if animation.frames.is_empty() && animation.facings.values().all(|f| f.is_empty()) {
+                return Err(AnimationError::NoFrames(name.clone()));
+            }
+            let facings = animation.facings.values().flat_map(|frames| frames.iter());
+            for frame in animation.frames.iter().chain(facings) {
+                if frame.column >= self.columns || frame.row >= self.rows {
+                    return Err(AnimationError::FrameOutOfSheet(
+                        name.clone(),
+                        frame.column,
+                        frame.row,
+                    ));
+                }
+            }
+            if let Some(stopped_frame) = animation.stopped_frame {
+                for &facing in Facing::all().iter() {
+                    if facing.is_diagonal() && self.directions() == Directions::Four {
+                        continue;
+                    }
+                    if stopped_frame >= animation.frames_for(facing).len() {
+                        return Err(AnimationError::StoppedFrameOutOfRange(
+                            name.clone(),
+                            facing,
+                            stopped_frame,
+                        ));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn image_path(&self) -> path::PathBuf {
+        self.base_path.join(&self.image)
+    }
+
+    pub fn directions(&self) -> Directions {
+        if self.directions == 8 {
+            Directions::Eight
+        } else {
+            Directions::Four
+        }
+    }
+
+    /// Builds a chooser for every animation, keyed by animation name, for
+    /// `actor::Actor::insert_chooser`. `sheet` must be the sheet `image` was loaded into.
+    pub fn choosers(
+        &self,
+        sheet: &actor::CharacterSheet,
+    ) -> Result<Vec<(String, AnimationChooser)>, String> {
+        let mut choosers = Vec::new();
+        for (name, animation) in self.animations.iter() {
+            choosers.push((name.clone(), AnimationChooser::new(animation, sheet)?));
+        }
+        Ok(choosers)
+    }
+
+    /// Gives `actor` a chooser for every animation, under the animation's name, and sets the
+    /// directions it can face.
+    pub fn animate(
+        &self,
+        actor: &mut actor::Actor,
+        sheet: &actor::CharacterSheet,
+    ) -> Result<(), String> {
+        actor.directions = self.directions();
+        for (name, chooser) in self.choosers(sheet)? {
+            actor.insert_chooser(
+                name,
+                cell::RefCell::<Box<actor::SpriteChooser>>::new(Box::new(chooser)),
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Frame positions of one pass through an animation of `count` frames.
+fn play_order(count: usize, loop_mode: LoopMode) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..count).collect();
+    if loop_mode == LoopMode::PingPong && count > 2 {
+        order.extend((1..count - 1).rev());
+    }
+    order
+}
+
+/// Seconds one pass through an animation takes.
+pub fn cycle_duration(durations: &[f64], loop_mode: LoopMode) -> f64 {
+    play_order(durations.len(), loop_mode)
+        .iter()
+        .map(|&i| durations[i])
+        .sum()
+}
+
+/// The positions in `0..durations.len()` of the frames that start after `from_s` and no later
+/// than `to_s` seconds into an animation, in play order. `from_s` of `None` means the animation
+/// has just started, so its first frame is included.
+pub fn frames_reached(
+    durations: &[f64],
+    loop_mode: LoopMode,
+    from_s: Option<f64>,
+    to_s: f64,
+) -> Vec<usize> {
+    let order = play_order(durations.len(), loop_mode);
+    let total = cycle_duration(durations, loop_mode);
+    let mut reached = Vec::new();
+    if order.is_empty() || total <= 0.0 {
+        return reached;
+    }
+    let after = |start_s: f64| match from_s {
+        Some(from_s) => start_s > from_s,
+        None => start_s >= 0.0,
+    };
+    let (first_cycle, last_cycle) = match loop_mode {
+        LoopMode::Once => (0.0, 0.0),
+        _ => ((from_s.unwrap_or(0.0).max(0.0) / total).floor(), (to_s / total).floor()),
+    };
+    let mut cycle = first_cycle;
+    while cycle <= last_cycle {
+        let mut start_s = cycle * total;
+        for &i in order.iter() {
+            if start_s > to_s {
+                break;
+            }
+            if after(start_s) {
+                reached.push(i);
+            }
+            start_s += durations[i];
+        }
+        cycle += 1.0;
+    }
+    reached
+}
+
+/// The position in `0..durations.len()` of the frame showing `time_s` seconds into an
+/// animation.
+pub fn frame_index(durations: &[f64], loop_mode: LoopMode, time_s: f64) -> usize {
+    if durations.is_empty() {
+        return 0;
+    }
+    let count = durations.len();
+    let order = play_order(count, loop_mode);
+    let total = cycle_duration(durations, loop_mode);
+    if total <= 0.0 {
+        return 0;
+    }
+
+    let mut t = match loop_mode {
+        LoopMode::Once if time_s >= total => return count - 1,
+        LoopMode::Once => time_s.max(0.0),
+        _ => ((time_s % total) + total) % total,
+    };
+    for &i in order.iter() {
+        if t < durations[i] {
+            return i;
+        }
+        t -= durations[i];
+    }
+    order[order.len() - 1]
+}
+
+/// The frames an `AnimationChooser` plays for one facing.
+pub struct FacingFrames {
+    pub sprites: Vec<sprite::Sprite<G2dTexture>>,
+    /// Seconds each of `sprites` is shown.
+    pub durations: Vec<f64>,
+    /// Events emitted when each of `sprites` is reached. May be empty if there are none.
+    pub events: Vec<Vec<String>>,
+}
+
+/// Plays one animation, picking frames by facing and elapsed time.
+pub struct AnimationChooser {
+    frames: HashMap<Facing, FacingFrames>,
+    loop_mode: LoopMode,
+    stopped_frame: Option<usize>,
+    facing: Facing,
+
+    /// Game time at which the animation (re)started, or `None` to start at the next frame.
+    start_s: Option<f64>,
+
+    /// Seconds into the animation at which events were last taken, or `None` if not since
+    /// it (re)started.
+    events_s: Option<f64>,
+}
+
+impl AnimationChooser {
+    pub fn new(animation: &AnimationDef, sheet: &actor::CharacterSheet) -> Result<Self, String> {
+        let mut frames = HashMap::new();
+        for &facing in Facing::all().iter() {
+            let mut sprites = Vec::new();
+            for frame in animation.frames_for(facing).iter() {
+                sprites.push(sheet.sprite(frame.column, frame.row)?);
+            }
+            frames.insert(
+                facing,
+                FacingFrames {
+                    sprites: sprites,
+                    durations: animation.durations(facing),
+                    events: animation
+                        .frames_for(facing)
+                        .iter()
+                        .map(|frame| frame.events.clone())
+                        .collect(),
+                },
+            );
+        }
+        Ok(AnimationChooser::from_frames(
+            frames,
+            animation.loop_mode,
+            animation.stopped_frame,
+        ))
+    }
+
+    /// A chooser playing already built frames. A facing without frames plays those of its
+    /// `Facing::cardinal`, then of `Facing::Down`, then of any facing.
+    pub fn from_frames(
+        frames: HashMap<Facing, FacingFrames>,
+        loop_mode: LoopMode,
+        stopped_frame: Option<usize>,
+    ) -> Self {
+        AnimationChooser {
+            frames: frames,
+            loop_mode: loop_mode,
+            stopped_frame: stopped_frame,
+            facing: Facing::Down,
+            start_s: None,
+            events_s: None,
+        }
+    }
+
+    fn current_frames(&self) -> Option<&FacingFrames> {
+        self.frames
+            .get(&self.facing)
+            .or_else(|| self.frames.get(&self.facing.cardinal()))
+            .or_else(|| self.frames.get(&Facing::Down))
+            .or_else(|| self.frames.values().next())
+    }
+}
+
+impl actor::SpriteChooser for AnimationChooser {
+    fn choose(&mut self, args: actor::ChooserArgs) -> Option<&sprite::Sprite<G2dTexture>> {
+        self.facing = args.facing;
+        let start_s = *self.start_s.get_or_insert(args.game_time_s);
+        let frames = self.current_frames()?;
+        let stopped = args.vx == 0.0 && args.vy == 0.0;
+        let index = match self.stopped_frame {
+            Some(index) if stopped => index,
+            _ => frame_index(&frames.durations, self.loop_mode, args.game_time_s - start_s),
+        };
+        frames.sprites.get(index)
+    }
+
+    /// Plays the animation from its first frame again.
+    fn restart(&mut self) {
+        self.start_s = None;
+        self.events_s = None;
+    }
+
+    fn take_events(&mut self, args: &actor::ChooserArgs) -> Vec<String> {
+        self.facing = args.facing;
+        let start_s = *self.start_s.get_or_insert(args.game_time_s);
+        let time_s = args.game_time_s - start_s;
+        let from_s = mem::replace(&mut self.events_s, Some(time_s));
+        let stopped = args.vx == 0.0 && args.vy == 0.0;
+        if stopped && self.stopped_frame.is_some() {
+            return Vec::new();
+        }
+        let frames = match self.current_frames() {
+            Some(frames) => frames,
+            None => {
+                return Vec::new();
+            }
+        };
+        let mut events = Vec::new();
+        for i in frames_reached(&frames.durations, self.loop_mode, from_s, time_s) {
+            if let Some(names) = frames.events.get(i) {
+                events.extend(names.iter().cloned());
+            }
+        }
+        events
+    }
+
+    fn duration_s(&self) -> Option<f64> {
+        self.current_frames().map(|frames| {
+            cycle_duration(&frames.durations, self.loop_mode)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ping_pong_matches_walking_sheet() {
+        let durations = [1.0, 1.0, 1.0];
+        let expected = [0, 1, 2, 1, 0, 1, 2, 1, 0];
+        for (step, &index) in expected.iter().enumerate() {
+            let time_s = step as f64 * 1.1;
+            assert_eq!(index, frame_index(&durations, LoopMode::PingPong, time_s));
+        }
+    }
+
+    #[test]
+    fn loop_and_once() {
+        let durations = [0.5, 1.0, 0.5];
+        assert_eq!(0, frame_index(&durations, LoopMode::Loop, 0.4));
+        assert_eq!(1, frame_index(&durations, LoopMode::Loop, 1.4));
+        assert_eq!(2, frame_index(&durations, LoopMode::Loop, 1.6));
+        assert_eq!(0, frame_index(&durations, LoopMode::Loop, 2.1));
+        assert_eq!(2, frame_index(&durations, LoopMode::Once, 2.1));
+        assert_eq!(2, frame_index(&durations, LoopMode::Once, 100.0));
+    }
+
+    #[test]
+    fn reached_frames() {
+        let durations = [1.0, 1.0, 1.0];
+        assert_eq!(vec![0], frames_reached(&durations, LoopMode::Loop, None, 0.5));
+        assert_eq!(vec![1], frames_reached(&durations, LoopMode::Loop, Some(0.5), 1.0));
+        assert_eq!(
+            Vec::<usize>::new(),
+            frames_reached(&durations, LoopMode::Loop, Some(1.0), 1.5)
+        );
+        // Crossing into the next cycle, and skipping frames on a long step.
+        assert_eq!(vec![0, 1], frames_reached(&durations, LoopMode::Loop, Some(2.5), 4.0));
+        assert_eq!(vec![1, 2, 1], frames_reached(&durations, LoopMode::PingPong, Some(0.5), 3.5));
+        assert_eq!(
+            Vec::<usize>::new(),
+            frames_reached(&durations, LoopMode::Once, Some(2.5), 10.0)
+        );
+    }
+
+    #[test]
+    fn facing_from_velocity() {
+        let four = Directions::Four;
+        assert_eq!(Facing::Up, Facing::from_velocity(Facing::Left, 1.0, -3.0, four));
+        assert_eq!(Facing::Right, Facing::from_velocity(Facing::Up, 3.0, -1.0, four));
+        // Exact diagonals keep a matching facing instead of snapping sideways.
+        assert_eq!(Facing::Up, Facing::from_velocity(Facing::Up, 1.0, -1.0, four));
+        assert_eq!(Facing::Right, Facing::from_velocity(Facing::Down, 1.0, -1.0, four));
+        assert_eq!(Facing::Left, Facing::from_velocity(Facing::Left, 0.0, 0.0, four));
+
+        let eight = Directions::Eight;
+        assert_eq!(Facing::UpRight, Facing::from_velocity(Facing::Up, 1.0, -1.0, eight));
+        assert_eq!(Facing::DownLeft, Facing::from_velocity(Facing::Up, -1.0, 1.2, eight));
+        assert_eq!(Facing::Down, Facing::from_velocity(Facing::Up, 0.1, 1.0, eight));
+        assert_eq!(Facing::UpLeft, Facing::from_velocity(Facing::UpLeft, 0.0, 0.0, eight));
+    }
+
+    #[test]
+    fn parses_and_checks_files() {
+        let json = r#"{
+            "image": "sheet.png", "columns": 2, "rows": 2,
+            "animations": {
+                "walk": {
+                    "loop": "ping_pong",
+                    "frame_duration": 0.2,
+                    "facings": {"up": [{"column": 0, "row": 0}, {"column": 1, "row": 0}]},
+                    "frames": [{"column": 0, "row": 1, "duration": 0.5}]
+                }
+            }
+        }"#;
+        let set = AnimationSet::from_reader(json.as_bytes()).unwrap();
+        let walk = &set.animations["walk"];
+        assert_eq!(LoopMode::PingPong, walk.loop_mode);
+        assert_eq!(2, walk.frames_for(Facing::Up).len());
+        assert_eq!(1, walk.frames_for(Facing::Left).len());
+        assert_eq!(vec![0.2, 0.2], walk.durations(Facing::Up));
+        assert_eq!(vec![0.5], walk.durations(Facing::Right));
+        assert_eq!(Directions::Four, set.directions());
+
+        let out_of_sheet = json.replace("\"column\": 1", "\"column\": 2");
+        match AnimationSet::from_reader(out_of_sheet.as_bytes()) {
+            Err(AnimationError::FrameOutOfSheet(ref name, 2, 0)) if name == "walk" => {}
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let six_way = json.replace("\"rows\": 2,", "\"rows\": 2, \"directions\": 6,");
+        match AnimationSet::from_reader(six_way.as_bytes()) {
+            Err(AnimationError::BadDirections(6)) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+
+        // Up has two frames, but the others fall back to the single `frames` entry.
+        let stopped = json.replace(r#""frame_duration": 0.2,"#, r#""stopped_frame": 1,"#);
+        match AnimationSet::from_reader(stopped.as_bytes()) {
+            Err(AnimationError::StoppedFrameOutOfRange(ref name, Facing::Down, 1))
+                if name == "walk" => {}
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn demo_hero_animations_load() {
+        let set = AnimationSet::from_path(path::Path::new("assets/hero_walk.json")).unwrap();
+        assert_eq!(path::Path::new("assets/hero_walk.png"), set.image_path());
+        assert_eq!(Some(1), set.animations["walk"].stopped_frame);
+        let attack = set.animations["attack"].frames_for(Facing::Left);
+        assert_eq!(vec![String::from("hit")], attack[1].events);
+        assert_eq!(1, set.animations["defend"].frames_for(Facing::Up).len());
+    }
+}
@@ -0,0 +1,207 @@
+//! Which animation an actor plays, as a small state machine.
+//!
+//! An actor is always in one `ActionState`. Idle, walk and defend are *base* states that follow
+//! the actor's ongoing input (moving, holding defend). Attack, hurt, stun and die are *one-shots*
+//! that gameplay triggers; they play once and then return to the current base state, except die,
+//! which lasts until `revive`. A one-shot only starts if it outranks the one already playing
+//! (see `ActionState::priority`), so e.g. getting hurt cancels a swing but not the reverse.
+
+/// Seconds a one-shot lasts when its animation does not say.
+static DEFAULT_ONE_SHOT_S: f64 = 0.4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ActionState {
+    Idle,
+    Walk,
+    Defend,
+    Attack,
+    Hurt,
+    /// Reeling from a parried attack; see `guard`.
+    Stun,
+    Die,
+}
+
+impl ActionState {
+    /// A one-shot may interrupt a running one-shot of lower priority.
+    pub fn priority(&self) -> u8 {
+        match *self {
+            ActionState::Idle | ActionState::Walk => 0,
+            ActionState::Defend => 1,
+            ActionState::Attack => 2,
+            ActionState::Hurt | ActionState::Stun => 3,
+            ActionState::Die => 4,
+        }
+    }
+
+    pub fn is_one_shot(&self) -> bool {
+        match *self {
+            ActionState::Attack | ActionState::Hurt | ActionState::Stun | ActionState::Die => true,
+            ActionState::Idle | ActionState::Walk | ActionState::Defend => false,
+        }
+    }
+
+    /// Whether the actor may walk while in this state.
+    pub fn allows_movement(&self) -> bool {
+        !self.is_one_shot()
+    }
+
+    /// The name of the animation (see `actor::Actor::insert_chooser`) played in this state.
+    pub fn animation_name(&self) -> &'static str {
+        match *self {
+            ActionState::Idle => "idle",
+            ActionState::Walk => "walk",
+            ActionState::Defend => "defend",
+            ActionState::Attack => "attack",
+            ActionState::Hurt => "hurt",
+            ActionState::Stun => "stun",
+            ActionState::Die => "die",
+        }
+    }
+}
+
+pub struct AnimationStateMachine {
+    state: ActionState,
+
+    /// The base state to return to when the current one-shot ends.
+    base: ActionState,
+
+    /// Seconds left in the current one-shot. Infinite while dead.
+    remaining_s: f64,
+
+    /// Set on every state change, so the new animation starts from its first frame.
+    entered: bool,
+}
+
+impl AnimationStateMachine {
+    pub fn new() -> Self {
+        AnimationStateMachine {
+            state: ActionState::Idle,
+            base: ActionState::Idle,
+            remaining_s: 0.0,
+            entered: false,
+        }
+    }
+
+    pub fn state(&self) -> ActionState {
+        self.state
+    }
+
+    fn enter(&mut self, state: ActionState) {
+        if state != self.state {
+            self.state = state;
+            self.entered = true;
+        }
+    }
+
+    /// Sets the base state from the actor's ongoing input. Takes effect immediately unless a
+    /// one-shot is playing.
+    pub fn set_base(&mut self, moving: bool, defending: bool) {
+        self.base = if defending {
+            ActionState::Defend
+        } else if moving {
+            ActionState::Walk
+        } else {
+            ActionState::Idle
+        };
+        if !self.state.is_one_shot() {
+            let base = self.base;
+            self.enter(base);
+        }
+    }
+
+    /// Starts one-shot `state`, lasting `duration_s` seconds (or a default if `None`).
+    /// Returns false, changing nothing, if `state` is not a one-shot or does not outrank the
+    /// one-shot already playing. Starting a base state this way is a no-op; see `set_base`.
+    pub fn trigger(&mut self, state: ActionState, duration_s: Option<f64>) -> bool {
+        if !state.is_one_shot() {
+            return false;
+        }
+        if self.state.is_one_shot() && state.priority() <= self.state.priority() {
+            return false;
+        }
+        self.enter(state);
+        // Re-entering the same state (e.g. a second hit) restarts its animation too.
+        self.entered = true;
+        self.remaining_s = if state == ActionState::Die {
+            f64::INFINITY
+        } else {
+            duration_s.unwrap_or(DEFAULT_ONE_SHOT_S)
+        };
+        true
+    }
+
+    /// Leaves the die state for the base state.
+    pub fn revive(&mut self) {
+        if self.state == ActionState::Die {
+            self.remaining_s = 0.0;
+            let base = self.base;
+            self.enter(base);
+        }
+    }
+
+    /// Advances time by `dt_s`, ending the current one-shot once it has played.
+    pub fn update(&mut self, dt_s: f64) {
+        if !self.state.is_one_shot() {
+            return;
+        }
+        self.remaining_s -= dt_s;
+        if self.remaining_s <= 0.0 {
+            let base = self.base;
+            self.enter(base);
+        }
+    }
+
+    /// Whether the state changed since the last call, i.e. its animation should restart.
+    pub fn take_entered(&mut self) -> bool {
+        let entered = self.entered;
+        self.entered = false;
+        entered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn one_shots_return_to_base() {
+        let mut machine = AnimationStateMachine::new();
+        machine.set_base(true, false);
+        assert_eq!(ActionState::Walk, machine.state());
+
+        assert!(machine.trigger(ActionState::Attack, Some(0.5)));
+        machine.set_base(false, false);
+        machine.update(0.3);
+        assert_eq!(ActionState::Attack, machine.state());
+        machine.update(0.3);
+        assert_eq!(ActionState::Idle, machine.state());
+    }
+
+    #[test]
+    fn priorities() {
+        let mut machine = AnimationStateMachine::new();
+        assert!(machine.trigger(ActionState::Attack, None));
+        assert!(!machine.trigger(ActionState::Attack, None));
+        assert!(machine.trigger(ActionState::Hurt, None));
+        assert!(!machine.trigger(ActionState::Attack, None));
+        assert!(machine.trigger(ActionState::Die, None));
+        assert!(!machine.trigger(ActionState::Hurt, None));
+        assert!(!machine.trigger(ActionState::Walk, None));
+
+        // Dead stays dead until revived.
+        machine.update(1000.0);
+        assert_eq!(ActionState::Die, machine.state());
+        machine.revive();
+        assert_eq!(ActionState::Idle, machine.state());
+    }
+
+    #[test]
+    fn state_changes_restart_animations() {
+        let mut machine = AnimationStateMachine::new();
+        assert!(!machine.take_entered());
+        machine.set_base(true, false);
+        assert!(machine.take_entered());
+        machine.set_base(true, false);
+        assert!(!machine.take_entered());
+    }
+}
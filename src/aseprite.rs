@@ -0,0 +1,466 @@
+//! Sprite sheets exported by Aseprite (File > Export Sprite Sheet, with JSON data), in either
+//! the "Array" or the "Hash" layout.
+//!
+//! Every frame tag becomes an animation of the same name. Tags named `<base>_up`,
+//! `<base>_down`, `<base>_left` and `<base>_right` are additionally combined into one animation
+//! named `<base>` that follows the actor's facing, so `walk_up` ... `walk_right` drive `walk`.
+//! Diagonal tags (`<base>_up_left`, `<base>_up_right`, `<base>_down_left`, `<base>_down_right`)
+//! join the group too, and make the actor face eight directions.
+//!
+//! A tag's `repeat` count of 1 plays it once and holds the last frame; any other count loops
+//! forever.
+//!
+//! Trimmed frames are rejected: every frame is drawn at the same place on the actor, so cutting
+//! away transparent borders would shift the sprite from frame to frame. Export without trimming.
+
+use std::*;
+use std::collections::HashMap;
+use actor;
+use animation::{AnimationChooser, Directions, Facing, FacingFrames, LoopMode};
+use serde;
+use serde_json;
+
+#[derive(Debug)]
+pub enum AsepriteError {
+    IoError(io::Error),
+    JsonError(serde_json::Error),
+    NoParentPath,
+    /// A tag refers to frames that do not exist. Holds (tag name, from, to).
+    TagOutOfRange(String, usize, usize),
+    /// A frame was trimmed on export. Holds the frame index.
+    TrimmedFrame(usize),
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+struct RectJson {
+    x: f64,
+    y: f64,
+    w: f64,
+    h: f64,
+}
+
+impl RectJson {
+    fn to_array(&self) -> [f64; 4] {
+        [self.x, self.y, self.w, self.h]
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+struct PointJson {
+    x: f64,
+    y: f64,
+}
+
+#[derive(Debug, Deserialize)]
+struct FrameJson {
+    frame: RectJson,
+    /// Milliseconds.
+    duration: f64,
+    #[serde(default)]
+    trimmed: bool,
+}
+
+/// The "Hash" layout keys frames by file name. Their order in the file is the frame order, which
+/// a map type would lose, so they are read into a list.
+struct FrameList(Vec<FrameJson>);
+
+impl<'de> serde::Deserialize<'de> for FrameList {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct FrameListVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for FrameListVisitor {
+            type Value = FrameList;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a list or map of frames")
+            }
+
+            fn visit_seq<A: serde::de::SeqAccess<'de>>(
+                self,
+                mut seq: A,
+            ) -> Result<FrameList, A::Error> {
+                let mut frames = Vec::new();
+                while let Some(frame) = seq.next_element()? {
+                    frames.push(frame);
+                }
+                Ok(FrameList(frames))
+            }
+
+            fn visit_map<A: serde::de::MapAccess<'de>>(
+                self,
+                mut map: A,
+            ) -> Result<FrameList, A::Error> {
+                let mut frames = Vec::new();
+                while let Some((_, frame)) = map.next_entry::<String, FrameJson>()? {
+                    frames.push(frame);
+                }
+                Ok(FrameList(frames))
+            }
+        }
+
+        deserializer.deserialize_any(FrameListVisitor)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct TagJson {
+    name: String,
+    from: usize,
+    to: usize,
+    #[serde(default)]
+    direction: String,
+    /// A number as a string; absent means forever.
+    #[serde(default)]
+    repeat: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SliceKeyJson {
+    frame: usize,
+    bounds: RectJson,
+    #[serde(default)]
+    pivot: Option<PointJson>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SliceJson {
+    name: String,
+    keys: Vec<SliceKeyJson>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MetaJson {
+    image: String,
+    #[serde(default, rename = "frameTags")]
+    frame_tags: Vec<TagJson>,
+    #[serde(default)]
+    slices: Vec<SliceJson>,
+}
+
+#[derive(Deserialize)]
+struct SheetJson {
+    frames: FrameList,
+    meta: MetaJson,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    /// `(x,y,width,height)` within the sheet image.
+    pub rect: [f64; 4],
+    pub duration_s: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tag {
+    pub name: String,
+    /// Indices into `AsepriteSheet::frames`, in playing order.
+    pub frames: Vec<usize>,
+    pub loop_mode: LoopMode,
+}
+
+/// A slice's bounds from frame `frame` on, until the next key.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SliceKey {
+    pub frame: usize,
+    /// `(x,y,width,height)` relative to the frame.
+    pub bounds: [f64; 4],
+    pub pivot: Option<[f64; 2]>,
+}
+
+/// The contents of an Aseprite JSON export. See the module docs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AsepriteSheet {
+    /// The sheet image, relative to the JSON file.
+    pub image: String,
+    pub frames: Vec<Frame>,
+    pub tags: Vec<Tag>,
+    /// Slice keys by slice name, ordered by frame.
+    pub slices: HashMap<String, Vec<SliceKey>>,
+
+    /// The directory of the JSON file, for resolving `image`.
+    base_path: path::PathBuf,
+}
+
+/// Frame order and loop mode for an Aseprite tag direction.
+fn tag_frames(tag: &TagJson) -> (Vec<usize>, LoopMode) {
+    let forward: Vec<usize> = (tag.from..tag.to + 1).collect();
+    let reverse: Vec<usize> = forward.iter().rev().cloned().collect();
+    let once = tag.repeat.as_ref().map_or(false, |r| r.trim() == "1");
+    let (frames, loop_mode) = match tag.direction.as_str() {
+        "reverse" => (reverse, LoopMode::Loop),
+        "pingpong" => (forward, LoopMode::PingPong),
+        "pingpong_reverse" => (reverse, LoopMode::PingPong),
+        _ => (forward, LoopMode::Loop),
+    };
+    (frames, if once { LoopMode::Once } else { loop_mode })
+}
+
+/// Splits `walk_up` into `("walk", Facing::Up)`.
+fn split_facing(tag_name: &str) -> Option<(&str, Facing)> {
+    // Diagonals first, as `_up_left` also ends in `_left`.
+    let suffixes = [
+        ("_up_left", Facing::UpLeft),
+        ("_up_right", Facing::UpRight),
+        ("_down_left", Facing::DownLeft),
+        ("_down_right", Facing::DownRight),
+        ("_up", Facing::Up),
+        ("_down", Facing::Down),
+        ("_left", Facing::Left),
+        ("_right", Facing::Right),
+    ];
+    for &(suffix, facing) in suffixes.iter() {
+        if tag_name.ends_with(suffix) && tag_name.len() > suffix.len() {
+            return Some((&tag_name[..tag_name.len() - suffix.len()], facing));
+        }
+    }
+    None
+}
+
+impl AsepriteSheet {
+    pub fn from_path(json_path: &path::Path) -> Result<Self, AsepriteError> {
+        let json_file = fs::File::open(json_path).map_err(|e| AsepriteError::IoError(e))?;
+        let mut sheet = AsepriteSheet::from_reader(io::BufReader::new(json_file))?;
+        sheet.base_path = json_path.parent().ok_or(AsepriteError::NoParentPath)?.to_path_buf();
+        Ok(sheet)
+    }
+
+    /// Reads an Aseprite JSON export. `image` is left relative to the current directory.
+    pub fn from_reader<R: io::Read>(reader: R) -> Result<Self, AsepriteError> {
+        let json: SheetJson = serde_json::from_reader(reader).map_err(
+            |e| AsepriteError::JsonError(e),
+        )?;
+
+        if let Some(index) = json.frames.0.iter().position(|frame| frame.trimmed) {
+            return Err(AsepriteError::TrimmedFrame(index));
+        }
+        let frames: Vec<Frame> = json.frames
+            .0
+            .iter()
+            .map(|frame| {
+                Frame {
+                    rect: frame.frame.to_array(),
+                    duration_s: frame.duration / 1000.0,
+                }
+            })
+            .collect();
+
+        let mut tags = Vec::new();
+        for tag in json.meta.frame_tags.iter() {
+            if tag.from > tag.to || tag.to >= frames.len() {
+                return Err(AsepriteError::TagOutOfRange(tag.name.clone(), tag.from, tag.to));
+            }
+            let (tag_frames, loop_mode) = tag_frames(tag);
+            tags.push(Tag {
+                name: tag.name.clone(),
+                frames: tag_frames,
+                loop_mode: loop_mode,
+            });
+        }
+
+        let mut slices = HashMap::new();
+        for slice in json.meta.slices.iter() {
+            let mut keys: Vec<SliceKey> = slice
+                .keys
+                .iter()
+                .map(|key| {
+                    SliceKey {
+                        frame: key.frame,
+                        bounds: key.bounds.to_array(),
+                        pivot: key.pivot.map(|p| [p.x, p.y]),
+                    }
+                })
+                .collect();
+            keys.sort_by_key(|key| key.frame);
+            slices.insert(slice.name.clone(), keys);
+        }
+
+        Ok(AsepriteSheet {
+            image: json.meta.image,
+            frames: frames,
+            tags: tags,
+            slices: slices,
+            base_path: path::PathBuf::new(),
+        })
+    }
+
+    pub fn image_path(&self) -> path::PathBuf {
+        self.base_path.join(&self.image)
+    }
+
+    pub fn tag(&self, name: &str) -> Option<&Tag> {
+        self.tags.iter().find(|tag| tag.name == name)
+    }
+
+    /// The bounds of slice `name` on frame `frame`, relative to the frame, if the slice exists
+    /// there.
+    pub fn slice_bounds(&self, name: &str, frame: usize) -> Option<[f64; 4]> {
+        let keys = self.slices.get(name)?;
+        keys.iter()
+            .take_while(|key| key.frame <= frame)
+            .last()
+            .map(|key| key.bounds)
+    }
+
+    fn facing_frames(
+        &self,
+        tag: &Tag,
+        sheet: &actor::CharacterSheet,
+    ) -> Result<FacingFrames, String> {
+        let mut sprites = Vec::new();
+        let mut durations = Vec::new();
+        for &index in tag.frames.iter() {
+            sprites.push(sheet.sprite_rect(self.frames[index].rect)?);
+            durations.push(self.frames[index].duration_s);
+        }
+        // Aseprite has no per-frame events to export.
+        Ok(FacingFrames {
+            sprites: sprites,
+            durations: durations,
+            events: Vec::new(),
+        })
+    }
+
+    /// A chooser playing tag `name` whatever the actor faces.
+    /// `sheet` must hold the sheet image, e.g. `CharacterSheet::from_texture(texture, 1, 1)`.
+    pub fn chooser(
+        &self,
+        name: &str,
+        sheet: &actor::CharacterSheet,
+    ) -> Result<AnimationChooser, String> {
+        let tag = self.tag(name).ok_or_else(|| format!("no tag named {:?}", name))?;
+        let mut frames = HashMap::new();
+        frames.insert(Facing::Down, self.facing_frames(tag, sheet)?);
+        Ok(AnimationChooser::from_frames(frames, tag.loop_mode, None))
+    }
+
+    /// Gives `actor` a chooser for every tag, and for every group of directional tags (see the
+    /// module docs), under the tag or group name. Diagonal tags make `actor` face eight
+    /// directions.
+    pub fn animate(
+        &self,
+        actor: &mut actor::Actor,
+        sheet: &actor::CharacterSheet,
+    ) -> Result<(), String> {
+        let mut groups = HashMap::<&str, (HashMap<Facing, FacingFrames>, LoopMode)>::new();
+        for tag in self.tags.iter() {
+            actor.insert_chooser(
+                tag.name.clone(),
+                cell::RefCell::<Box<actor::SpriteChooser>>::new(
+                    Box::new(self.chooser(&tag.name, sheet)?),
+                ),
+            );
+            if let Some((base, facing)) = split_facing(&tag.name) {
+                let group = groups.entry(base).or_insert_with(
+                    || (HashMap::new(), tag.loop_mode),
+                );
+                group.0.insert(facing, self.facing_frames(tag, sheet)?);
+                if facing.is_diagonal() {
+                    actor.directions = Directions::Eight;
+                }
+            }
+        }
+        for (base, (frames, loop_mode)) in groups {
+            // A tag named exactly like the group keeps its own name.
+            if self.tag(base).is_some() {
+                continue;
+            }
+            actor.insert_chooser(
+                String::from(base),
+                cell::RefCell::<Box<actor::SpriteChooser>>::new(
+                    Box::new(AnimationChooser::from_frames(frames, loop_mode, None)),
+                ),
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame_json(x: u32, duration: u32) -> String {
+        format!(
+            r#"{{"frame": {{"x": {}, "y": 0, "w": 16, "h": 16}}, "rotated": false,
+                 "trimmed": false, "duration": {}}}"#,
+            x,
+            duration
+        )
+    }
+
+    fn sheet_json(frames: &str) -> String {
+        format!(
+            r#"{{"frames": {}, "meta": {{
+                "app": "http://www.aseprite.org/", "image": "knight.png",
+                "size": {{"w": 192, "h": 16}},
+                "frameTags": [
+                    {{"name": "walk_down", "from": 0, "to": 2, "direction": "pingpong"}},
+                    {{"name": "walk_up", "from": 3, "to": 5, "direction": "forward"}},
+                    {{"name": "swing", "from": 6, "to": 11, "direction": "reverse", "repeat": "1"}}
+                ],
+                "slices": [{{"name": "hitbox", "color": "#0000ffff", "keys": [
+                    {{"frame": 8, "bounds": {{"x": 10, "y": 2, "w": 6, "h": 12}}}},
+                    {{"frame": 0, "bounds": {{"x": 4, "y": 2, "w": 8, "h": 12}},
+                      "pivot": {{"x": 8, "y": 14}}}}
+                ]}}]
+            }}}}"#,
+            frames
+        )
+    }
+
+    #[test]
+    fn reads_hash_layout_in_file_order() {
+        // Keys sort differently from frame order once there are ten or more frames.
+        let entries: Vec<String> = (0..12)
+            .map(|i| format!("\"knight {}.aseprite\": {}", i, frame_json(i * 16, 100 + i)))
+            .collect();
+        let json = sheet_json(&format!("{{{}}}", entries.join(",")));
+        let sheet = AsepriteSheet::from_reader(json.as_bytes()).unwrap();
+        assert_eq!("knight.png", sheet.image);
+        assert_eq!(12, sheet.frames.len());
+        assert_eq!([160.0, 0.0, 16.0, 16.0], sheet.frames[10].rect);
+        assert_eq!(0.11, sheet.frames[10].duration_s);
+    }
+
+    #[test]
+    fn tags_and_slices() {
+        let entries: Vec<String> = (0..12).map(|i| frame_json(i * 16, 100)).collect();
+        let json = sheet_json(&format!("[{}]", entries.join(",")));
+        let sheet = AsepriteSheet::from_reader(json.as_bytes()).unwrap();
+
+        let walk_down = sheet.tag("walk_down").unwrap();
+        assert_eq!(vec![0, 1, 2], walk_down.frames);
+        assert_eq!(LoopMode::PingPong, walk_down.loop_mode);
+        let swing = sheet.tag("swing").unwrap();
+        assert_eq!(vec![11, 10, 9, 8, 7, 6], swing.frames);
+        assert_eq!(LoopMode::Once, swing.loop_mode);
+
+        assert_eq!(Some([4.0, 2.0, 8.0, 12.0]), sheet.slice_bounds("hitbox", 7));
+        assert_eq!(Some([10.0, 2.0, 6.0, 12.0]), sheet.slice_bounds("hitbox", 9));
+        assert_eq!(None, sheet.slice_bounds("hurtbox", 0));
+        assert_eq!(Some(("walk", Facing::Up)), split_facing("walk_up"));
+        assert_eq!(Some(("walk", Facing::UpLeft)), split_facing("walk_up_left"));
+        assert_eq!(None, split_facing("_up"));
+    }
+
+    #[test]
+    fn rejects_tags_past_the_last_frame() {
+        let entries: Vec<String> = (0..4).map(|i| frame_json(i * 16, 100)).collect();
+        let json = sheet_json(&format!("[{}]", entries.join(",")));
+        match AsepriteSheet::from_reader(json.as_bytes()) {
+            Err(AsepriteError::TagOutOfRange(ref name, 3, 5)) if name == "walk_up" => {}
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_trimmed_frames() {
+        let mut entries: Vec<String> = (0..12).map(|i| frame_json(i * 16, 100)).collect();
+        entries[7] = entries[7].replace("\"trimmed\": false", "\"trimmed\": true");
+        let json = sheet_json(&format!("[{}]", entries.join(",")));
+        match AsepriteSheet::from_reader(json.as_bytes()) {
+            Err(AsepriteError::TrimmedFrame(7)) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
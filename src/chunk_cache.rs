@@ -0,0 +1,218 @@
+use std::*;
+use coordinates::{Camera, TileIndex};
+use gfx_core;
+use gfx_device_gl;
+use image;
+use image::GenericImage;
+use piston_window;
+use piston_window::*;
+use tilesheet;
+
+/// Side length of a chunk, in tiles.
+static CHUNK_TILES: usize = 16;
+
+/// A `CHUNK_TILES` square of one tile layer, pre-rendered into a single texture.
+struct Chunk {
+    layer_index: usize,
+    /// The chunk's top-left tile.
+    origin: TileIndex,
+    /// The gids the texture was rendered from, row-major, for detecting edits.
+    gids: Vec<u32>,
+    /// `None` if every tile in the chunk is empty.
+    texture: Option<G2dTexture>,
+}
+
+/// The gids of the chunk at `origin`, row-major, clipped to the map.
+fn chunk_gids(tilesheet: &tilesheet::Tilesheet, layer_index: usize, origin: TileIndex) -> Vec<u32> {
+    let mut gids = Vec::with_capacity(CHUNK_TILES * CHUNK_TILES);
+    for y in 0..CHUNK_TILES as i64 {
+        for x in 0..CHUNK_TILES as i64 {
+            let index = TileIndex::new(origin.x + x, origin.y + y);
+            gids.push(tilesheet.tile_gid(layer_index, index).unwrap_or(0));
+        }
+    }
+    gids
+}
+
+/// Composites the tiles of one chunk into an image, one image pixel per world pixel.
+/// Returns `None` if the chunk is empty.
+pub fn compose_chunk(
+    tilesheet: &tilesheet::Tilesheet,
+    layer_index: usize,
+    origin: TileIndex,
+) -> Option<image::RgbaImage> {
+    let gids = chunk_gids(tilesheet, layer_index, origin);
+    if gids.iter().all(|&gid| gid == 0) {
+        return None;
+    }
+
+    let tile_width = tilesheet.tile_width();
+    let tile_height = tilesheet.tile_height();
+    let source = tilesheet.atlas();
+    let mut chunk = image::RgbaImage::new(
+        tile_width * CHUNK_TILES as u32,
+        tile_height * CHUNK_TILES as u32,
+    );
+    for (i, &gid) in gids.iter().enumerate() {
+        let rect = match tilesheet.tile_rect(gid) {
+            Some(rect) => rect,
+            None => continue, // empty, or no tileset provides it
+        };
+        let (x, y) = (rect[0] as u32, rect[1] as u32);
+        if x + tile_width > source.width() || y + tile_height > source.height() {
+            continue;
+        }
+        let column = (i % CHUNK_TILES) as u32;
+        let row = (i / CHUNK_TILES) as u32;
+        chunk.copy_from(
+            &source.view(x, y, tile_width, tile_height),
+            column * tile_width,
+            row * tile_height,
+        );
+    }
+    Some(chunk)
+}
+
+/// Tile layers pre-rendered into large chunk textures, so drawing the map takes a few quads per
+/// frame instead of one per tile. A chunk is only re-rendered when a tile edit changes it.
+pub struct ChunkCache {
+    chunks: Vec<Chunk>,
+
+    /// `Tilesheet::revision` the chunks are up to date with.
+    revision: u64,
+}
+
+impl ChunkCache {
+    pub fn new() -> Self {
+        ChunkCache {
+            chunks: Vec::new(),
+            revision: 0,
+        }
+    }
+
+    fn upload(
+        factory: &mut gfx_device_gl::Factory,
+        tilesheet: &tilesheet::Tilesheet,
+        layer_index: usize,
+        origin: TileIndex,
+    ) -> Result<Option<G2dTexture>, gfx_core::factory::CombinedError> {
+        match compose_chunk(tilesheet, layer_index, origin) {
+            Some(chunk) => {
+                let settings = TextureSettings::new().filter(Filter::Nearest);
+                Ok(Some(Texture::from_image(factory, &chunk, &settings)?))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn rebuild_all(
+        &mut self,
+        factory: &mut gfx_device_gl::Factory,
+        tilesheet: &tilesheet::Tilesheet,
+    ) -> Result<(), gfx_core::factory::CombinedError> {
+        self.chunks.clear();
+        let map = tilesheet.map();
+        let chunks_wide = (map.width as usize + CHUNK_TILES - 1) / CHUNK_TILES;
+        let chunks_high = (map.height as usize + CHUNK_TILES - 1) / CHUNK_TILES;
+        for layer_index in 0..tilesheet.layer_count() {
+            for cy in 0..chunks_high {
+                for cx in 0..chunks_wide {
+                    let origin = TileIndex::from_usize(cx * CHUNK_TILES, cy * CHUNK_TILES);
+                    self.chunks.push(Chunk {
+                        layer_index: layer_index,
+                        origin: origin,
+                        gids: chunk_gids(tilesheet, layer_index, origin),
+                        texture: ChunkCache::upload(factory, tilesheet, layer_index, origin)?,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Brings the chunk textures up to date with `tilesheet`, re-rendering only chunks whose
+    /// tiles changed since the last call.
+    pub fn update(
+        &mut self,
+        factory: &mut gfx_device_gl::Factory,
+        tilesheet: &tilesheet::Tilesheet,
+    ) -> Result<(), gfx_core::factory::CombinedError> {
+        if self.chunks.is_empty() {
+            self.rebuild_all(factory, tilesheet)?;
+            self.revision = tilesheet.revision();
+            return Ok(());
+        }
+        if self.revision == tilesheet.revision() {
+            return Ok(());
+        }
+
+        for chunk in self.chunks.iter_mut() {
+            let gids = chunk_gids(tilesheet, chunk.layer_index, chunk.origin);
+            if gids != chunk.gids {
+                chunk.texture = ChunkCache::upload(
+                    factory,
+                    tilesheet,
+                    chunk.layer_index,
+                    chunk.origin,
+                )?;
+                chunk.gids = gids;
+            }
+        }
+        self.revision = tilesheet.revision();
+        Ok(())
+    }
+
+    /// Draws the on-screen chunks of layer `layer_index`, as seen by `camera`.
+    pub fn render_layer(
+        &self,
+        context: piston_window::Context,
+        renderer: &mut G2d,
+        tilesheet: &tilesheet::Tilesheet,
+        camera: &Camera,
+        layer_index: usize,
+        opacity: f32,
+    ) {
+        let tile_size = tilesheet.tile_size();
+        let (first, last) = camera.visible_tiles(tile_size);
+        let image = Image::new_color([1.0, 1.0, 1.0, opacity]);
+        for chunk in self.chunks.iter().filter(|c| c.layer_index == layer_index) {
+            let texture = match chunk.texture {
+                Some(ref texture) => texture,
+                None => continue,
+            };
+            let end_x = chunk.origin.x + CHUNK_TILES as i64 - 1;
+            let end_y = chunk.origin.y + CHUNK_TILES as i64 - 1;
+            if end_x < first.x || end_y < first.y || chunk.origin.x > last.x ||
+                chunk.origin.y > last.y
+            {
+                continue;
+            }
+            // Whole pixels, so that neighbouring chunks meet exactly at any camera position.
+            let screen = camera.to_screen(chunk.origin.top_left(tile_size));
+            image.draw(
+                texture,
+                &context.draw_state,
+                context.transform.trans(screen.x.round(), screen.y.round()),
+                renderer,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunk_pixels_come_from_tileset() {
+        let tilesheet = tilesheet::demo();
+        let origin = TileIndex::new(0, 0);
+        let chunk = compose_chunk(&tilesheet, 0, origin).unwrap();
+        let gid = tilesheet.tile_gid(0, origin).unwrap();
+        let rect = tilesheet.tile_rect(gid).unwrap();
+        assert_eq!(
+            tilesheet.atlas().get_pixel(rect[0] as u32 + 5, rect[1] as u32 + 7),
+            chunk.get_pixel(5, 7)
+        );
+    }
+}
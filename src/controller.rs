@@ -25,9 +25,6 @@ pub struct Controller {
     /// Bindings between keyboard keys and input commands.
     pub keybinding: std::collections::BTreeMap<keyboard::Key, input::Command>,
 
-    /// Walk-Rate multiplier. Normal ~= 90.0.
-    pub walk_rate: f32,
-
     /// Time since last game cycle.
     pub dt_s: f64,
 }
@@ -37,7 +34,6 @@ impl Controller {
         Controller {
             input: input::Input::new(),
             keybinding: default_keybindings(),
-            walk_rate: 90.0,
             dt_s: 0.0,
         }
     }
@@ -0,0 +1,219 @@
+//! The three coordinate spaces of a tile map, kept as distinct types so they cannot be mixed up.
+//!
+//!  - `WorldPoint`: map pixels. (0, 0) is the top-left corner of tile (0, 0); y grows downward.
+//!  - `TileIndex`: a (column, row) tile. Tile (c, r) covers world x in `[c * w, (c + 1) * w)`
+//!    and world y in `[r * h, (r + 1) * h)`.
+//!  - `ScreenPoint`: window pixels, as reported by mouse events and used for drawing.
+//!
+//! Convert between world and tile space with `WorldPoint::to_tile` and `TileIndex::top_left` or
+//! `TileIndex::center`, and between world and screen space with a `Camera`.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WorldPoint {
+    pub x: f64,
+    pub y: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TileIndex {
+    /// Column. Signed, so positions off the map's left edge still have an index.
+    pub x: i64,
+    /// Row. Signed, so positions off the map's top edge still have an index.
+    pub y: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScreenPoint {
+    pub x: f64,
+    pub y: f64,
+}
+
+/// Size of one tile in world pixels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TileSize {
+    pub width: f64,
+    pub height: f64,
+}
+
+impl WorldPoint {
+    pub fn new(x: f64, y: f64) -> Self {
+        WorldPoint { x: x, y: y }
+    }
+
+    /// The tile containing this point. Rounds toward negative infinity, so a point anywhere
+    /// inside a tile maps to that tile, not its neighbour.
+    pub fn to_tile(&self, tile_size: TileSize) -> TileIndex {
+        TileIndex::new(
+            (self.x / tile_size.width).floor() as i64,
+            (self.y / tile_size.height).floor() as i64,
+        )
+    }
+
+    pub fn distance(&self, other: WorldPoint) -> f64 {
+        (self.x - other.x).hypot(self.y - other.y)
+    }
+
+    /// Distance to the nearest point of `rect` `(x, y, width, height)`; 0 inside it.
+    pub fn distance_to_rect(&self, rect: [f64; 4]) -> f64 {
+        self.distance(WorldPoint::new(
+            self.x.max(rect[0]).min(rect[0] + rect[2]),
+            self.y.max(rect[1]).min(rect[1] + rect[3]),
+        ))
+    }
+}
+
+impl TileIndex {
+    pub fn new(x: i64, y: i64) -> Self {
+        TileIndex { x: x, y: y }
+    }
+
+    /// The index of `layer.tiles[row][column]`.
+    pub fn from_usize(column: usize, row: usize) -> Self {
+        TileIndex::new(column as i64, row as i64)
+    }
+
+    /// `(column, row)` for indexing tile data, or `None` if either is negative.
+    pub fn to_usize(&self) -> Option<(usize, usize)> {
+        if self.x < 0 || self.y < 0 {
+            None
+        } else {
+            Some((self.x as usize, self.y as usize))
+        }
+    }
+
+    pub fn top_left(&self, tile_size: TileSize) -> WorldPoint {
+        WorldPoint::new(
+            self.x as f64 * tile_size.width,
+            self.y as f64 * tile_size.height,
+        )
+    }
+
+    pub fn center(&self, tile_size: TileSize) -> WorldPoint {
+        WorldPoint::new(
+            (self.x as f64 + 0.5) * tile_size.width,
+            (self.y as f64 + 0.5) * tile_size.height,
+        )
+    }
+}
+
+impl ScreenPoint {
+    pub fn new(x: f64, y: f64) -> Self {
+        ScreenPoint { x: x, y: y }
+    }
+
+    pub fn from_array(point: [f64; 2]) -> Self {
+        ScreenPoint::new(point[0], point[1])
+    }
+}
+
+/// Maps world pixels to window pixels. The camera's `center` is drawn at the middle of a
+/// window of size `viewport`, at one screen pixel per world pixel.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Camera {
+    pub center: WorldPoint,
+    /// Window size in screen pixels.
+    pub viewport: [f64; 2],
+}
+
+impl Camera {
+    pub fn new(center: WorldPoint, viewport: [f64; 2]) -> Self {
+        Camera {
+            center: center,
+            viewport: viewport,
+        }
+    }
+
+    pub fn to_screen(&self, point: WorldPoint) -> ScreenPoint {
+        ScreenPoint::new(
+            point.x - self.center.x + self.viewport[0] * 0.5,
+            point.y - self.center.y + self.viewport[1] * 0.5,
+        )
+    }
+
+    pub fn to_world(&self, point: ScreenPoint) -> WorldPoint {
+        WorldPoint::new(
+            point.x + self.center.x - self.viewport[0] * 0.5,
+            point.y + self.center.y - self.viewport[1] * 0.5,
+        )
+    }
+
+    /// The first and last (inclusive) tiles that are at least partly on screen.
+    pub fn visible_tiles(&self, tile_size: TileSize) -> (TileIndex, TileIndex) {
+        let top_left = self.to_world(ScreenPoint::new(0.0, 0.0));
+        let bottom_right = self.to_world(ScreenPoint::new(self.viewport[0], self.viewport[1]));
+        (
+            top_left.to_tile(tile_size),
+            bottom_right.to_tile(tile_size),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tile_size() -> TileSize {
+        TileSize {
+            width: 32.0,
+            height: 16.0,
+        }
+    }
+
+    #[test]
+    fn point_inside_tile_maps_to_that_tile() {
+        // Rounding instead of flooring would put these in the next tile over.
+        assert_eq!(
+            TileIndex::new(0, 0),
+            WorldPoint::new(31.9, 15.9).to_tile(tile_size())
+        );
+        assert_eq!(
+            TileIndex::new(1, 1),
+            WorldPoint::new(32.0, 16.0).to_tile(tile_size())
+        );
+        assert_eq!(
+            TileIndex::new(-1, -1),
+            WorldPoint::new(-0.1, -0.1).to_tile(tile_size())
+        );
+    }
+
+    #[test]
+    fn tile_corners_and_centers() {
+        let tile = TileIndex::new(2, 3);
+        assert_eq!(WorldPoint::new(64.0, 48.0), tile.top_left(tile_size()));
+        assert_eq!(WorldPoint::new(80.0, 56.0), tile.center(tile_size()));
+        assert_eq!(tile, tile.center(tile_size()).to_tile(tile_size()));
+        assert_eq!(None, TileIndex::new(-1, 0).to_usize());
+    }
+
+    #[test]
+    fn distance_to_rects() {
+        let rect = [0.0, 0.0, 10.0, 10.0];
+        assert_eq!(0.0, WorldPoint::new(5.0, 5.0).distance_to_rect(rect));
+        assert_eq!(2.0, WorldPoint::new(12.0, 5.0).distance_to_rect(rect));
+        assert_eq!(5.0, WorldPoint::new(13.0, 14.0).distance_to_rect(rect));
+    }
+
+    #[test]
+    fn camera_round_trip() {
+        let camera = Camera::new(WorldPoint::new(100.0, 50.0), [800.0, 600.0]);
+        assert_eq!(
+            ScreenPoint::new(400.0, 300.0),
+            camera.to_screen(WorldPoint::new(100.0, 50.0))
+        );
+        // Things to the right of and below the camera are drawn to the right and below.
+        assert_eq!(
+            ScreenPoint::new(410.0, 320.0),
+            camera.to_screen(WorldPoint::new(110.0, 70.0))
+        );
+        let point = WorldPoint::new(-12.5, 7.25);
+        assert_eq!(point, camera.to_world(camera.to_screen(point)));
+    }
+
+    #[test]
+    fn visible_tiles_cover_viewport() {
+        let camera = Camera::new(WorldPoint::new(400.0, 300.0), [800.0, 600.0]);
+        let (first, last) = camera.visible_tiles(tile_size());
+        assert_eq!(TileIndex::new(0, 0), first);
+        assert_eq!(TileIndex::new(25, 37), last);
+    }
+}
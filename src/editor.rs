@@ -0,0 +1,319 @@
+use std::path;
+use coordinates::{Camera, ScreenPoint, TileIndex, WorldPoint};
+use piston_window;
+use piston_window::*;
+use tilesheet;
+
+/// Gap in px between the right edge of the window and the tile palette.
+static PALETTE_MARGIN: f64 = 8.0;
+
+/// Tint drawn over tiles whose `walkable` property is false.
+static BLOCKED_OVERLAY_COLOR: [f32; 4] = [1.0, 0.0, 0.0, 0.35];
+
+/// Outline color for the hovered tile and the selected palette entry.
+static CURSOR_COLOR: [f32; 4] = [1.0, 1.0, 0.0, 1.0];
+
+/// Where the editor saves the map loaded from `tmx_path`: `maps/town.tmx` saves to
+/// `maps/town.edited.tmx`.
+fn edited_path(tmx_path: &path::Path) -> path::PathBuf {
+    let stem = tmx_path.file_stem().map(|stem| stem.to_string_lossy().into_owned());
+    tmx_path.with_file_name(format!("{}.edited.tmx", stem.unwrap_or_default()))
+}
+
+/// In-game tile painting mode.
+///
+/// Press Tab to toggle the editor. While active:
+///  - Left-click a tile in the palette (top right) to select it.
+///  - Left-click or drag on the map to paint the selected tile onto the current layer.
+///  - Right-click the map to pick the tile under the cursor.
+///  - Delete selects the eraser (gid 0).
+///  - PageUp/PageDown cycle through layers.
+///  - V toggles the walkability overlay.
+///  - F5 saves the map next to the TMX file it was loaded from, as `<name>.edited.tmx`, so
+///    the original asset is never overwritten.
+///
+/// The outcome of the last save or edit is reported by `status`.
+pub struct Editor {
+    active: bool,
+    show_walkable: bool,
+    painting: bool,
+    layer_index: usize,
+    selected_gid: u32,
+    cursor: ScreenPoint,
+    status: Option<String>,
+
+    /// The camera used to draw the last frame. See `Game::render`.
+    camera: Camera,
+}
+
+impl Editor {
+    pub fn new() -> Self {
+        Editor {
+            active: false,
+            show_walkable: true,
+            painting: false,
+            layer_index: 0,
+            selected_gid: 1,
+            cursor: ScreenPoint::new(0.0, 0.0),
+            status: None,
+            camera: Camera::new(WorldPoint::new(0.0, 0.0), [0.0, 0.0]),
+        }
+    }
+
+    /// A message about the last save or failed edit, for the game to show.
+    pub fn status(&self) -> Option<&str> {
+        self.status.as_ref().map(|status| status.as_str())
+    }
+
+    /// The screen-space rectangle `(x,y,width,height)` in which the tile palette, the
+    /// tilesheet's atlas, is drawn.
+    fn palette_rect(&self, tilesheet: &tilesheet::Tilesheet) -> [f64; 4] {
+        let width = tilesheet.atlas().width() as f64;
+        let height = tilesheet.atlas().height() as f64;
+        [
+            self.camera.viewport[0] - width - PALETTE_MARGIN,
+            PALETTE_MARGIN,
+            width,
+            height,
+        ]
+    }
+
+    fn palette_gid_at(&self, tilesheet: &tilesheet::Tilesheet, screen: ScreenPoint) -> Option<u32> {
+        let rect = self.palette_rect(tilesheet);
+        let px = screen.x - rect[0];
+        let py = screen.y - rect[1];
+        if px < 0.0 || py < 0.0 || px >= rect[2] || py >= rect[3] {
+            return None;
+        }
+        tilesheet.gid_at_atlas_pixel(px as u32, py as u32)
+    }
+
+    /// The on-map tile under `screen`, if any.
+    fn tile_at(&self, tilesheet: &tilesheet::Tilesheet, screen: ScreenPoint) -> Option<TileIndex> {
+        let index = self.camera.to_world(screen).to_tile(tilesheet.tile_size());
+        tilesheet.tile_gid(self.layer_index, index).map(|_| index)
+    }
+
+    fn paint(&mut self, tilesheet: &mut tilesheet::Tilesheet) {
+        if let Some(index) = self.tile_at(tilesheet, self.cursor) {
+            if let Err(e) = tilesheet.set_tile_gid(self.layer_index, index, self.selected_gid) {
+                self.status = Some(format!("Could not paint tile {:?}: {:?}", index, e));
+            }
+        }
+    }
+
+    fn on_left_press(&mut self, tilesheet: &mut tilesheet::Tilesheet) {
+        if let Some(gid) = self.palette_gid_at(tilesheet, self.cursor) {
+            self.selected_gid = gid;
+            return;
+        }
+        self.painting = true;
+        self.paint(tilesheet);
+    }
+
+    fn on_right_press(&mut self, tilesheet: &tilesheet::Tilesheet) {
+        if let Some(index) = self.tile_at(tilesheet, self.cursor) {
+            if let Some(gid) = tilesheet.tile_gid(self.layer_index, index) {
+                self.selected_gid = gid;
+            }
+        }
+    }
+
+    fn on_key_press(&mut self, key: keyboard::Key, tilesheet: &tilesheet::Tilesheet) {
+        let layer_count = tilesheet.layer_count();
+        match key {
+            keyboard::Key::Delete => self.selected_gid = 0,
+            keyboard::Key::V => self.show_walkable = !self.show_walkable,
+            keyboard::Key::PageUp if layer_count > 0 => {
+                self.layer_index = (self.layer_index + 1) % layer_count;
+            }
+            keyboard::Key::PageDown if layer_count > 0 => {
+                self.layer_index = (self.layer_index + layer_count - 1) % layer_count;
+            }
+            keyboard::Key::F5 => {
+                self.status = Some(match tilesheet.tmx_path() {
+                    Some(tmx_path) => {
+                        let edited_path = edited_path(tmx_path);
+                        match tilesheet.save(&edited_path) {
+                            Ok(()) => format!("Saved {}", edited_path.display()),
+                            Err(e) => format!("Save failed: {:?}", e),
+                        }
+                    }
+                    None => String::from("Map was not loaded from a file; nothing to save"),
+                });
+            }
+            _ => {}
+        }
+    }
+
+    /// Handles editor input. Returns `true` if the editor consumed `event`.
+    pub fn process_event(
+        &mut self,
+        event: &piston_window::Event,
+        tilesheet: &mut tilesheet::Tilesheet,
+    ) -> bool {
+        if let Some(Button::Keyboard(keyboard::Key::Tab)) = event.press_args() {
+            self.active = !self.active;
+            self.painting = false;
+            return true;
+        }
+
+        if !self.active {
+            return false;
+        }
+
+        if let Some(position) = event.mouse_cursor_args() {
+            self.cursor = ScreenPoint::from_array(position);
+            if self.painting {
+                self.paint(tilesheet);
+            }
+            return true;
+        }
+
+        match event.press_args() {
+            Some(Button::Mouse(MouseButton::Left)) => {
+                self.on_left_press(tilesheet);
+                true
+            }
+            Some(Button::Mouse(MouseButton::Right)) => {
+                self.on_right_press(tilesheet);
+                true
+            }
+            Some(Button::Keyboard(key)) => {
+                self.on_key_press(key, tilesheet);
+                false // Let the controller keep moving the hero around the map.
+            }
+            _ => {
+                if let Some(Button::Mouse(MouseButton::Left)) = event.release_args() {
+                    self.painting = false;
+                    return true;
+                }
+                false
+            }
+        }
+    }
+
+    fn draw_walkable_overlay(
+        &self,
+        context: piston_window::Context,
+        renderer: &mut G2d,
+        tilesheet: &tilesheet::Tilesheet,
+    ) {
+        let tile_size = tilesheet.tile_size();
+        let (first, last) = self.camera.visible_tiles(tile_size);
+        for (y, row) in tilesheet.layer_tile_iter(self.layer_index).enumerate() {
+            for (x, &tile) in row.iter().enumerate() {
+                let index = TileIndex::from_usize(x, y);
+                if index.x < first.x || index.y < first.y || index.x > last.x || index.y > last.y {
+                    continue;
+                }
+                if tile == 0 || tilesheet.is_gid_walkable(tile) {
+                    continue;
+                }
+                let position = self.camera.to_screen(index.top_left(tile_size));
+                rectangle(
+                    BLOCKED_OVERLAY_COLOR,
+                    [position.x, position.y, tile_size.width, tile_size.height],
+                    context.transform,
+                    renderer,
+                );
+            }
+        }
+    }
+
+    /// Draws the editor overlay on top of a map drawn with `camera`.
+    pub fn render(
+        &mut self,
+        context: piston_window::Context,
+        renderer: &mut G2d,
+        tilesheet: &tilesheet::Tilesheet,
+        map_tiles_texture: &G2dTexture,
+        camera: Camera,
+    ) {
+        self.camera = camera;
+
+        if !self.active {
+            return;
+        }
+
+        if self.show_walkable && self.layer_index < tilesheet.layer_count() {
+            self.draw_walkable_overlay(context, renderer, tilesheet);
+        }
+
+        let tile_width = tilesheet.tile_width() as f64;
+        let tile_height = tilesheet.tile_height() as f64;
+        let outline = Rectangle::new_border(CURSOR_COLOR, 1.0);
+
+        if let Some(index) = self.tile_at(tilesheet, self.cursor) {
+            let position = self.camera.to_screen(index.top_left(tilesheet.tile_size()));
+            outline.draw(
+                [position.x, position.y, tile_width, tile_height],
+                &context.draw_state,
+                context.transform,
+                renderer,
+            );
+        }
+
+        // Tile palette
+        let palette = self.palette_rect(tilesheet);
+        rectangle(
+            [0.0, 0.0, 0.0, 0.9],
+            [
+                palette[0] - 2.0,
+                palette[1] - 2.0,
+                palette[2] + 4.0,
+                palette[3] + 4.0,
+            ],
+            context.transform,
+            renderer,
+        );
+        Image::new().draw(
+            map_tiles_texture,
+            &context.draw_state,
+            context.transform.trans(palette[0], palette[1]),
+            renderer,
+        );
+        if let Some(rect) = tilesheet.tile_rect(self.selected_gid) {
+            outline.draw(
+                [palette[0] + rect[0], palette[1] + rect[1], rect[2], rect[3]],
+                &context.draw_state,
+                context.transform,
+                renderer,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saves_next_to_the_original() {
+        assert_eq!(
+            path::Path::new("assets/town.edited.tmx"),
+            edited_path(path::Path::new("assets/town.tmx"))
+        );
+    }
+
+    #[test]
+    fn picks_from_the_palette_and_paints() {
+        let mut tilesheet = tilesheet::demo();
+        let mut editor = Editor::new();
+        // Screen and world coordinates coincide.
+        editor.camera = Camera::new(WorldPoint::new(400.0, 300.0), [800.0, 600.0]);
+
+        let palette = editor.palette_rect(&tilesheet);
+        let rect = tilesheet.tile_rect(7).unwrap();
+        editor.cursor = ScreenPoint::new(palette[0] + rect[0] + 1.0, palette[1] + rect[1] + 1.0);
+        editor.on_left_press(&mut tilesheet);
+        assert_eq!(7, editor.selected_gid);
+        assert_eq!(0, tilesheet.revision());
+
+        editor.cursor = ScreenPoint::new(40.0, 250.0);
+        editor.on_left_press(&mut tilesheet);
+        assert_eq!(Some(7), tilesheet.tile_gid(0, TileIndex::new(1, 7)));
+        assert_eq!(1, tilesheet.revision());
+        assert!(editor.status().is_none());
+    }
+}
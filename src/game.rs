@@ -1,31 +1,127 @@
 use std::*;
+use std::collections::HashSet;
 use piston_window;
-use gfx_core;
 use tilesheet;
 use piston_window::*;
 use controller;
+use coordinates;
 use actor;
+use actors;
+use ai;
+use animation;
+use editor;
+use melee;
+use minimap;
+use objects;
+use patrol;
+use projectile;
+use properties;
+use roof;
+use stats;
+use world;
 use game_network;
 
+/// Tag of the hero in `Game::actors_mut`.
+pub static HERO_TAG: &'static str = "hero";
+
 #[derive(Debug)]
 pub enum NewGameError {
     TilesheetError(tilesheet::TilesheetError),
-    WindowError(gfx_core::factory::CombinedError),
     HeroError(String),
     NetworkError(game_network::msg::CommError),
+    WorldError(world::WorldError),
+    MapError(world::MapError),
+}
+
+/// The stats of an `"npc"` object, from its `hp`, `mp`, `attack`, `defense` and `speed`
+/// properties. NPCs are weaker and a little slower than the hero by default.
+fn npc_stats(properties: &properties::PropertyChain) -> stats::Stats {
+    let stat = |name, default| properties.float_or(name, default) as f64;
+    stats::Stats::new(stats::BaseStats {
+        max_hp: stat("hp", 30.0),
+        max_mp: stat("mp", 0.0),
+        attack: stat("attack", 8.0),
+        defense: stat("defense", 3.0),
+        speed: stat("speed", 60.0),
+    })
+}
+
+/// The brain of an `"npc"` object, from its `behavior` property:
+///
+/// * `idle` (the default) stands still.
+/// * `wander` walks around within `radius` px (default 48), resting `rest` seconds (default 2)
+///   at each stop.
+/// * `follow` keeps within `distance` px (default 32) of the hero.
+/// * `flee` runs from the hero whenever they come within `distance` px (default 96).
+/// * `patrol` walks the route that the `route` property refers to; see `patrol`. This is the
+///   default for NPCs that have a `route`.
+fn npc_brain(
+    context: &objects::ObjectContext,
+    hero_id: actors::ActorId,
+) -> Result<ai::Brain, String> {
+    let properties = &context.properties;
+    let home = context.center;
+    let route = patrol::Route::from_property(context.tilesheet, properties, context.map_origin);
+    let default_behavior = if route.is_some() { "patrol" } else { "idle" };
+    let behavior = properties.string_or("behavior", default_behavior);
+    let distance = |default| properties.float_or("distance", default) as f64;
+    let brain = match behavior.as_str() {
+        "idle" => ai::Brain::idle(home),
+        "patrol" => {
+            let route = route.unwrap_or_else(|| Err(String::from("patrol needs a route")))?;
+            ai::Brain::new(route.behavior(), home)
+        }
+        "wander" => {
+            ai::Brain::wander(
+                home,
+                properties.float_or("radius", 48.0) as f64,
+                properties.float_or("rest", 2.0) as f64,
+            )
+        }
+        "follow" => ai::Brain::follow(home, hero_id, distance(32.0)),
+        "flee" => ai::Brain::flee(home, hero_id, distance(96.0)),
+        other => {
+            return Err(format!("unknown behavior {:?}", other));
+        }
+    };
+    Ok(brain)
 }
 
 pub struct Game {
-    tilesheet: tilesheet::Tilesheet,
-    piston_image: piston_window::Image,
-    map_tiles_texture: piston_window::G2dTexture,
+    world: world::World,
+
+    /// Placement index of the map the hero is on, which the editor and minimap work on.
+    current_map: Option<usize>,
 
     /// Total elapsed game time.
     game_time_s: f64,
 
     controller: controller::Controller,
-    hero: actor::Actor,
+
+    /// Every actor, the hero included. Actors spawned from map objects are tagged with the
+    /// object's type.
+    actors: actors::Actors,
+    hero_id: actors::ActorId,
+    /// Arrows, fireballs and the like in flight.
+    projectiles: projectile::Projectiles,
+
+    /// Computes the damage of every hit.
+    damage_formula: stats::DamageFormula,
+
+    /// Builds actors from map objects when their map first loads.
+    objects: objects::ObjectRegistry<actor::Actor>,
+
+    /// Placement indices of maps whose objects were spawned. Objects are spawned only on the
+    /// first visit, so that maps unloaded and loaded again do not spawn duplicates.
+    spawned_maps: HashSet<usize>,
+
+    editor: editor::Editor,
+    minimap: minimap::Minimap,
+    roofs: roof::Roofs,
     glyphs: piston_window::Glyphs,
+    /// The last error loading maps, drawing the minimap or spawning objects. The game goes on
+    /// without the map or object concerned; this is shown below the frame rate.
+    status: Option<String>,
     network: game_network::client::Client,
 }
 
@@ -39,34 +135,90 @@ impl Game {
         let tilesheet = tilesheet::Tilesheet::from_path(&asset_path.join("tiled_base64_zlib.tmx"))
             .map_err(|e| NewGameError::TilesheetError(e))?;
 
+        Game::from_tilesheet(tilesheet, asset_path, window, server_address)
+    }
+
+    /// Like `from_path`, but plays on an already loaded or generated `tilesheet`.
+    /// Other assets (hero sprites, font) are still read from `asset_path`.
+    pub fn from_tilesheet(
+        tilesheet: tilesheet::Tilesheet,
+        asset_path: &path::Path,
+        window: &mut piston_window::PistonWindow,
+        server_address: net::SocketAddr,
+    ) -> Result<Self, NewGameError> {
+        Game::from_world(
+            world::World::from_tilesheet(tilesheet),
+            asset_path,
+            window,
+            server_address,
+        )
+    }
+
+    /// Like `from_path`, but plays on the maps of the Tiled `.world` file at `world_path`.
+    pub fn from_world_path(
+        world_path: &path::Path,
+        asset_path: &path::Path,
+        window: &mut piston_window::PistonWindow,
+        server_address: net::SocketAddr,
+    ) -> Result<Self, NewGameError> {
+        let world = world::World::from_path(world_path).map_err(
+            |e| NewGameError::WorldError(e),
+        )?;
+        Game::from_world(world, asset_path, window, server_address)
+    }
+
+    fn from_world(
+        mut world: world::World,
+        asset_path: &path::Path,
+        window: &mut piston_window::PistonWindow,
+        server_address: net::SocketAddr,
+    ) -> Result<Self, NewGameError> {
         let texture_settings = piston_window::TextureSettings::new();
-        let map_tiles_texture = piston_window::Texture::from_image(
-            &mut window.factory,
-            tilesheet.image(),
-            &texture_settings,
-        ).map_err(|e| NewGameError::WindowError(e))?;
 
         // Hero
+        let hero_animations =
+            animation::AnimationSet::from_path(&asset_path.join("hero_walk.json"))
+                .map_err(|e| NewGameError::HeroError(format!("{:?}", e)))?;
+
         let hero_texture = piston_window::Texture::from_path(
             &mut window.factory,
-            &asset_path.join("hero_walk.png"),
+            &hero_animations.image_path(),
             piston_window::Flip::None,
             &texture_settings,
         ).map_err(|e| NewGameError::HeroError(e))?;
 
-        let hero_sheet = actor::CharacterSheet::from_texture(hero_texture, 3, 4)
-            .map_err(|e| NewGameError::HeroError(e))?;
-
-        let hero_walk_chooser = actor::WalkingSpriteChooser::from_sheet(0.2, &hero_sheet)
-            .map_err(|e| NewGameError::HeroError(e))?;
+        let hero_sheet = rc::Rc::new(
+            actor::CharacterSheet::from_texture(
+                hero_texture,
+                hero_animations.columns,
+                hero_animations.rows,
+            ).map_err(|e| NewGameError::HeroError(e))?,
+        );
 
         let mut hero = actor::Actor::new();
-        hero.insert_chooser(
-            String::from("walk"),
-            cell::RefCell::<Box<actor::WalkingSpriteChooser>>::new(
-                Box::<actor::WalkingSpriteChooser>::new(hero_walk_chooser),
-            ),
-        );
+        hero_animations.animate(&mut hero, &hero_sheet).map_err(
+            |e| NewGameError::HeroError(e),
+        )?;
+
+        world.update(&mut window.factory, hero.position()).map_err(
+            |e| NewGameError::MapError(e),
+        )?;
+
+        let mut actors = actors::Actors::new();
+        let hero_id = actors.spawn(hero, &[HERO_TAG]);
+
+        // NPCs placed in Tiled look like the hero until they get sprites of their own.
+        let mut objects = objects::ObjectRegistry::new();
+        let npc_sheet = hero_sheet.clone();
+        objects.register("npc", move |context| {
+            let mut npc = actor::Actor::new();
+            npc.x = context.center.x as f32;
+            npc.y = context.center.y as f32;
+            hero_animations.animate(&mut npc, &npc_sheet)?;
+            npc.stats = npc_stats(&context.properties);
+            npc.brain = Some(npc_brain(context, hero_id)?);
+            Ok(npc)
+        });
 
         // Font
         let ref font = asset_path.join("yoster.ttf");
@@ -83,13 +235,21 @@ impl Game {
         ).map_err(|err| NewGameError::NetworkError(err))?;
 
         Ok(Game {
-            tilesheet: tilesheet,
-            piston_image: piston_window::Image::new(),
-            map_tiles_texture: map_tiles_texture,
+            world: world,
+            current_map: None,
             game_time_s: 0.0,
             controller: controller::Controller::new(),
-            hero: hero,
+            actors: actors,
+            hero_id: hero_id,
+            projectiles: projectile::Projectiles::new(),
+            damage_formula: stats::default_damage,
+            objects: objects,
+            spawned_maps: HashSet::new(),
+            editor: editor::Editor::new(),
+            minimap: minimap::Minimap::new(),
+            roofs: roof::Roofs::new(),
             glyphs: glyphs,
+            status: None,
             network: network,
         })
     }
@@ -102,8 +262,21 @@ impl Game {
             }
         };
 
-        self.controller.process_event(&event);
-        self.game_time_s += self.controller.dt_s;
+        let consumed = match self.current_map {
+            Some(index) => {
+                match self.world.region_mut(index) {
+                    Some(region) => self.editor.process_event(&event, region.tilesheet_mut()),
+                    None => false,
+                }
+            }
+            None => false,
+        };
+        if !consumed {
+            self.controller.process_event(&event);
+        }
+        self.minimap.process_event(&event);
+        let dt_s = event.update(|args| args.dt);
+        self.game_time_s += dt_s.unwrap_or(0.0);
 
         // Send self.controller to the server.
         if let Err(_) = self.network.send_controller_input(
@@ -115,10 +288,50 @@ impl Game {
             // TODO
         }
 
+        // Every actor's timers run, whether it is controlled, has a brain or neither.
+        if let Some(dt_s) = dt_s {
+            for (_, actor) in self.actors.iter_mut() {
+                actor.update_state(dt_s);
+            }
+        }
+
         // TODO Receive player's world context from server.
         // TODO For now, we'll let the controller directly control our visualization, but we'll
         // need to eventually negotiate their differences.
-        self.hero.control(&self.controller, &self.tilesheet);
+        if let (Some(dt_s), Some(hero)) = (dt_s, self.actors.get_mut(self.hero_id)) {
+            hero.control(&self.controller, dt_s, &self.world);
+        }
+        ai::update(&event, &mut self.actors, &self.world);
+        for (_, actor) in self.actors.iter_mut() {
+            actor.update_animation(self.game_time_s);
+        }
+        melee::update(&event, &mut self.actors, self.damage_formula);
+        projectile::fire_from_events(&self.actors, &mut self.projectiles);
+        self.projectiles.update(
+            &event,
+            &self.world,
+            &mut self.actors,
+            self.damage_formula,
+        );
+
+        let hero_position = self.hero_position();
+        if let Err(e) = self.world.update(&mut window.factory, hero_position) {
+            self.status = Some(format!("Map update failed: {:?}", e));
+        }
+        self.spawn_objects();
+        self.roofs.update(dt_s.unwrap_or(0.0), &self.world, hero_position);
+        let current_map = self.world.placement_index_at(hero_position);
+        if current_map != self.current_map {
+            self.current_map = current_map;
+            self.minimap.invalidate();
+        }
+        if let Some(index) = self.current_map {
+            if let Some(region) = self.world.region(index) {
+                if let Err(e) = self.minimap.update(&mut window.factory, region.tilesheet()) {
+                    self.status = Some(format!("Minimap update failed: {:?}", e));
+                }
+            }
+        }
 
         window.draw_2d(&event, |context, gfx| {
             self.render(context, gfx);
@@ -127,6 +340,65 @@ impl Game {
         true
     }
 
+    /// Game code registers factories for its own object types here. They apply to maps that
+    /// finish loading afterwards, including the first map if registered before the first `next`.
+    pub fn objects_mut(&mut self) -> &mut objects::ObjectRegistry<actor::Actor> {
+        &mut self.objects
+    }
+
+    /// All actors, the hero included. Game code spawns and despawns its own actors here.
+    pub fn actors_mut(&mut self) -> &mut actors::Actors {
+        &mut self.actors
+    }
+
+    /// Projectiles in flight. Game code fires its own here, e.g. from traps.
+    pub fn projectiles_mut(&mut self) -> &mut projectile::Projectiles {
+        &mut self.projectiles
+    }
+
+    /// Replaces the formula that decides how much damage attacks deal.
+    pub fn set_damage_formula(&mut self, formula: stats::DamageFormula) {
+        self.damage_formula = formula;
+    }
+
+    /// The hero's ID in `actors_mut`. It is tagged `"hero"`.
+    pub fn hero_id(&self) -> actors::ActorId {
+        self.hero_id
+    }
+
+    fn hero_position(&self) -> coordinates::WorldPoint {
+        match self.actors.get(self.hero_id) {
+            Some(hero) => hero.position(),
+            None => coordinates::WorldPoint::new(0.0, 0.0),
+        }
+    }
+
+    /// Spawns the objects of maps that just finished loading.
+    fn spawn_objects(&mut self) {
+        for map_index in self.world.take_newly_loaded() {
+            if !self.spawned_maps.insert(map_index) {
+                continue;
+            }
+            let region = match self.world.region(map_index) {
+                Some(region) => region,
+                None => continue,
+            };
+            let actors = &mut self.actors;
+            let status = &mut self.status;
+            self.objects.spawn_map_with(
+                region.tilesheet(),
+                region.origin(),
+                map_index,
+                |object, result| match result {
+                    Ok(actor) => {
+                        actors.spawn(actor, &[object.obj_type.as_str()]);
+                    }
+                    Err(e) => *status = Some(format!("Failed to spawn {}", e)),
+                },
+            );
+        }
+    }
+
     fn print(&mut self, context: piston_window::Context, renderer: &mut G2d) {
         rectangle(
             [0.0, 0.0, 0.0, 0.9],
@@ -145,10 +417,25 @@ impl Game {
                 renderer,
             )
             .unwrap();
+        let statuses = self.editor.status().into_iter().chain(
+            self.status.as_ref().map(|status| status.as_str()),
+        );
+        for (line, status) in statuses.enumerate() {
+            text::Text::new_color([1.0, 1.0, 1.0, 1.0], 8)
+                .draw(
+                    status,
+                    &mut self.glyphs,
+                    &context.draw_state,
+                    context.transform.trans(8.0, 32.0 + 16.0 * line as f64),
+                    renderer,
+                )
+                .unwrap();
+        }
     }
 
     fn render(&mut self, context: piston_window::Context, renderer: &mut G2d) {
-        piston_window::clear(self.tilesheet.background_color(), renderer);
+        let hero_position = self.hero_position();
+        piston_window::clear(self.world.background_color(hero_position), renderer);
 
         let viewport = match context.viewport {
             Some(viewport) => viewport,
@@ -157,45 +444,53 @@ impl Game {
             }
         };
 
-        let center = [
-            viewport.window_size[0] as f64 * 0.5,
-            viewport.window_size[1] as f64 * 0.5,
-        ];
-
-        for (y, row) in self.tilesheet.layer_tile_iter(0).enumerate() {
-            for (x, &tile) in row.iter().enumerate() {
-                if tile == 0 {
-                    // tiled counts from 1; 0 is invalid
-                    continue;
-                }
-
-                let trans = context.transform.trans(
-                    self.hero.x as f64 + center[0] -
-                        x as f64 * self.tilesheet.tile_width() as f64,
-                    self.hero.y as f64 + center[1] -
-                        y as f64 * self.tilesheet.tile_height() as f64,
-                );
+        let camera = coordinates::Camera::new(
+            hero_position,
+            [
+                viewport.window_size[0] as f64,
+                viewport.window_size[1] as f64,
+            ],
+        );
+        self.world.render(context, renderer, &camera, &self.roofs);
 
-                self.piston_image
-                    .src_rect(self.tilesheet.tile_rect(tile))
-                    .draw(
-                        &self.map_tiles_texture,
-                        &piston_window::DrawState::default(),
-                        trans,
-                        renderer,
-                    );
+        for id in self.actors.draw_order() {
+            let is_hero = id == self.hero_id;
+            let actor = match self.actors.get_mut(id) {
+                Some(actor) => actor,
+                None => continue,
+            };
+            let screen = camera.to_screen(actor.position());
+            let trans = context.transform.trans(screen.x, screen.y);
+            // Other actors without animations are not drawn.
+            match actor.draw_state(self.game_time_s, trans, renderer) {
+                Err(actor::ActorDrawError::NoSuchName) if is_hero => panic!(),
+                _ => (),
             }
         }
-
-        let hero_trans = context.transform.trans(center[0], center[1]);
-        match self.hero.draw(
-            "walk",
-            self.game_time_s,
-            hero_trans,
-            renderer,
-        ) {
-            Err(actor::ActorDrawError::NoSuchName) => panic!(),
-            _ => (),
+        self.projectiles.render(context, renderer, &camera, self.game_time_s);
+        if let Some(index) = self.current_map {
+            if let Some(region) = self.world.region(index) {
+                let mut markers: Vec<(coordinates::WorldPoint, [f32; 4])> = self.actors
+                    .iter()
+                    .filter(|&(id, actor)| {
+                        id != self.hero_id && !actor.is_dead() &&
+                            self.world.placement_index_at(actor.position()) == Some(index)
+                    })
+                    .map(|(_, actor)| {
+                        (region.to_local(actor.position()), minimap::ACTOR_MARKER_COLOR)
+                    })
+                    .collect();
+                // Last, so it is drawn over the others.
+                markers.push((region.to_local(hero_position), minimap::HERO_MARKER_COLOR));
+                self.minimap.render(context, renderer, &markers);
+                self.editor.render(
+                    context,
+                    renderer,
+                    region.tilesheet(),
+                    region.tiles_texture(),
+                    region.local_camera(&camera),
+                );
+            }
         }
         self.print(context, renderer);
     }
@@ -0,0 +1,588 @@
+use std::*;
+use std::collections::HashMap;
+use rand::{Rng, SeedableRng, XorShiftRng};
+use coordinates;
+use tiled;
+use tilesheet;
+
+/// The kind of terrain at a generated map cell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cell {
+    Floor,
+    Wall,
+    Water,
+    Tree,
+}
+
+/// The layout algorithm used by `generate`.
+#[derive(Debug, Clone)]
+pub enum Layout {
+    /// Rectangular rooms joined by L-shaped corridors.
+    Rooms {
+        room_count: usize,
+        min_room_size: usize,
+        max_room_size: usize,
+    },
+
+    /// Cellular-automaton caves. Only the largest open region is kept, so every floor cell is
+    /// reachable from the spawn point.
+    Caves {
+        /// Probability that a cell starts out as a wall. ~0.45 gives open, twisting caves.
+        wall_probability: f32,
+        smoothing_steps: usize,
+    },
+
+    /// Smoothed random height field, cut into water, grass and forest bands.
+    Overworld {
+        /// Heights below this (0..1) become water.
+        water_level: f32,
+        /// Heights above this (0..1) become trees.
+        tree_level: f32,
+        smoothing_steps: usize,
+    },
+}
+
+/// Which tileset gid `Dungeon::to_tilesheet` places for each kind of `Cell`. The gids'
+/// `walkable` properties decide collisions, exactly as for hand-made maps.
+#[derive(Debug, Clone)]
+pub struct TerrainGids {
+    pub floor: u32,
+    pub wall: u32,
+    pub water: u32,
+    pub tree: u32,
+}
+
+/// The smallest map `generate` makes: one open cell inside a closed border. Smaller rule sets
+/// are enlarged to it.
+pub static MIN_SIZE: usize = 3;
+
+#[derive(Debug, Clone)]
+pub struct RuleSet {
+    /// Map width in tiles, at least `MIN_SIZE`.
+    pub width: usize,
+    /// Map height in tiles, at least `MIN_SIZE`.
+    pub height: usize,
+    pub layout: Layout,
+}
+
+/// A generated map, before it is turned into a `Tilesheet`.
+pub struct Dungeon {
+    /// Row-major cells, `cells[y][x]`.
+    pub cells: Vec<Vec<Cell>>,
+    /// Tile (column, row) of a floor cell from which the whole playable area is reachable.
+    pub spawn: (usize, usize),
+}
+
+fn rng_from_seed(seed: u64) -> XorShiftRng {
+    // XorShift must not be seeded with all zeros; the constant words keep that from happening.
+    XorShiftRng::from_seed([
+        seed as u32,
+        (seed >> 32) as u32,
+        0x193a_6754,
+        0xa8a7_d469,
+    ])
+}
+
+fn filled(width: usize, height: usize, cell: Cell) -> Vec<Vec<Cell>> {
+    vec![vec![cell; width]; height]
+}
+
+/// Returns the cells of every 4-connected region of `Cell::Floor`, largest first.
+fn floor_regions(cells: &Vec<Vec<Cell>>) -> Vec<Vec<(usize, usize)>> {
+    let height = cells.len();
+    let width = if height > 0 { cells[0].len() } else { 0 };
+    let mut seen = vec![vec![false; width]; height];
+    let mut regions = Vec::new();
+
+    for y in 0..height {
+        for x in 0..width {
+            if seen[y][x] || cells[y][x] != Cell::Floor {
+                continue;
+            }
+            let mut region = Vec::new();
+            let mut stack = vec![(x, y)];
+            seen[y][x] = true;
+            while let Some((cx, cy)) = stack.pop() {
+                region.push((cx, cy));
+                let mut neighbours = Vec::with_capacity(4);
+                if cx > 0 {
+                    neighbours.push((cx - 1, cy));
+                }
+                if cy > 0 {
+                    neighbours.push((cx, cy - 1));
+                }
+                if cx + 1 < width {
+                    neighbours.push((cx + 1, cy));
+                }
+                if cy + 1 < height {
+                    neighbours.push((cx, cy + 1));
+                }
+                for (nx, ny) in neighbours {
+                    if !seen[ny][nx] && cells[ny][nx] == Cell::Floor {
+                        seen[ny][nx] = true;
+                        stack.push((nx, ny));
+                    }
+                }
+            }
+            regions.push(region);
+        }
+    }
+
+    regions.sort_by(|a, b| b.len().cmp(&a.len()));
+    regions
+}
+
+/// The cell of `region` closest to the middle of the map.
+fn most_central(region: &Vec<(usize, usize)>, width: usize, height: usize) -> (usize, usize) {
+    let (mx, my) = (width as i64 / 2, height as i64 / 2);
+    *region
+        .iter()
+        .min_by_key(|&&(x, y)| (x as i64 - mx).abs() + (y as i64 - my).abs())
+        .unwrap_or(&(mx as usize, my as usize))
+}
+
+fn carve(cells: &mut Vec<Vec<Cell>>, x: usize, y: usize) {
+    cells[y][x] = Cell::Floor;
+}
+
+/// Turns the outermost cells into walls, as off-map counts as walkable.
+fn close_border(cells: &mut Vec<Vec<Cell>>) {
+    let height = cells.len();
+    for (y, row) in cells.iter_mut().enumerate() {
+        let width = row.len();
+        for (x, cell) in row.iter_mut().enumerate() {
+            if x == 0 || y == 0 || x + 1 == width || y + 1 == height {
+                *cell = Cell::Wall;
+            }
+        }
+    }
+}
+
+fn rooms<R: Rng>(
+    rng: &mut R,
+    width: usize,
+    height: usize,
+    room_count: usize,
+    min_room_size: usize,
+    max_room_size: usize,
+) -> Dungeon {
+    let mut cells = filled(width, height, Cell::Wall);
+
+    // (x, y, w, h) of each placed room.
+    let mut placed: Vec<(usize, usize, usize, usize)> = Vec::new();
+    let min_size = cmp::max(1, min_room_size);
+    let max_size = cmp::max(min_size, max_room_size);
+    for _ in 0..room_count * 16 {
+        if placed.len() == room_count {
+            break;
+        }
+        let w = rng.gen_range(min_size, max_size + 1);
+        let h = rng.gen_range(min_size, max_size + 1);
+        if w + 2 >= width || h + 2 >= height {
+            continue;
+        }
+        let x = rng.gen_range(1, width - w - 1);
+        let y = rng.gen_range(1, height - h - 1);
+
+        // Keep at least one wall tile between rooms.
+        let overlaps = placed.iter().any(|&(ox, oy, ow, oh)| {
+            x <= ox + ow && ox <= x + w && y <= oy + oh && oy <= y + h
+        });
+        if overlaps {
+            continue;
+        }
+
+        for ry in y..y + h {
+            for rx in x..x + w {
+                carve(&mut cells, rx, ry);
+            }
+        }
+        placed.push((x, y, w, h));
+    }
+
+    let centers: Vec<(usize, usize)> = placed
+        .iter()
+        .map(|&(x, y, w, h)| (x + w / 2, y + h / 2))
+        .collect();
+    for pair in centers.windows(2) {
+        let (x0, y0) = pair[0];
+        let (x1, y1) = pair[1];
+        let horizontal_first = rng.gen::<bool>();
+        let corner = if horizontal_first { (x1, y0) } else { (x0, y1) };
+        for x in cmp::min(x0, x1)..cmp::max(x0, x1) + 1 {
+            carve(&mut cells, x, corner.1);
+        }
+        for y in cmp::min(y0, y1)..cmp::max(y0, y1) + 1 {
+            carve(&mut cells, corner.0, y);
+        }
+    }
+
+    let spawn = match centers.first() {
+        Some(&center) => center,
+        None => {
+            // Nothing fit; leave a single open cell so the map is still playable.
+            let center = (width / 2, height / 2);
+            carve(&mut cells, center.0, center.1);
+            center
+        }
+    };
+    Dungeon {
+        cells: cells,
+        spawn: spawn,
+    }
+}
+
+fn wall_neighbours(cells: &Vec<Vec<Cell>>, x: usize, y: usize) -> usize {
+    let height = cells.len() as i64;
+    let width = cells[0].len() as i64;
+    let mut count = 0;
+    for dy in -1..2 {
+        for dx in -1..2 {
+            let nx = x as i64 + dx;
+            let ny = y as i64 + dy;
+            if nx < 0 || ny < 0 || nx >= width || ny >= height ||
+                cells[ny as usize][nx as usize] == Cell::Wall
+            {
+                count += 1;
+            }
+        }
+    }
+    count
+}
+
+fn caves<R: Rng>(
+    rng: &mut R,
+    width: usize,
+    height: usize,
+    wall_probability: f32,
+    smoothing_steps: usize,
+) -> Dungeon {
+    let mut cells = filled(width, height, Cell::Wall);
+    for y in 1..height.saturating_sub(1) {
+        for x in 1..width.saturating_sub(1) {
+            if rng.gen::<f32>() >= wall_probability {
+                cells[y][x] = Cell::Floor;
+            }
+        }
+    }
+
+    for _ in 0..smoothing_steps {
+        let mut next = cells.clone();
+        for y in 1..height.saturating_sub(1) {
+            for x in 1..width.saturating_sub(1) {
+                next[y][x] = if wall_neighbours(&cells, x, y) >= 5 {
+                    Cell::Wall
+                } else {
+                    Cell::Floor
+                };
+            }
+        }
+        cells = next;
+    }
+
+    let regions = floor_regions(&cells);
+    let spawn = match regions.first() {
+        Some(largest) => {
+            for region in regions.iter().skip(1) {
+                for &(x, y) in region.iter() {
+                    cells[y][x] = Cell::Wall;
+                }
+            }
+            most_central(largest, width, height)
+        }
+        None => {
+            let center = (width / 2, height / 2);
+            carve(&mut cells, center.0, center.1);
+            center
+        }
+    };
+
+    Dungeon {
+        cells: cells,
+        spawn: spawn,
+    }
+}
+
+fn overworld<R: Rng>(
+    rng: &mut R,
+    width: usize,
+    height: usize,
+    water_level: f32,
+    tree_level: f32,
+    smoothing_steps: usize,
+) -> Dungeon {
+    let mut heights: Vec<Vec<f32>> = (0..height)
+        .map(|_| (0..width).map(|_| rng.gen::<f32>()).collect())
+        .collect();
+
+    // Box blur, then stretch back to 0..1 so the levels mean the same thing at any smoothing.
+    for _ in 0..smoothing_steps {
+        let mut next = heights.clone();
+        for y in 0..height {
+            for x in 0..width {
+                let mut sum = 0.0;
+                let mut count = 0.0;
+                for ny in y.saturating_sub(1)..cmp::min(height, y + 2) {
+                    for nx in x.saturating_sub(1)..cmp::min(width, x + 2) {
+                        sum += heights[ny][nx];
+                        count += 1.0;
+                    }
+                }
+                next[y][x] = sum / count;
+            }
+        }
+        heights = next;
+    }
+    let lowest = heights.iter().flat_map(|r| r.iter()).cloned().fold(1.0, f32::min);
+    let highest = heights.iter().flat_map(|r| r.iter()).cloned().fold(0.0, f32::max);
+    let range = if highest > lowest { highest - lowest } else { 1.0 };
+
+    let mut cells: Vec<Vec<Cell>> = heights
+        .iter()
+        .map(|row| {
+            row.iter()
+                .map(|&h| {
+                    let h = (h - lowest) / range;
+                    if h < water_level {
+                        Cell::Water
+                    } else if h > tree_level {
+                        Cell::Tree
+                    } else {
+                        Cell::Floor
+                    }
+                })
+                .collect()
+        })
+        .collect();
+    close_border(&mut cells);
+
+    let spawn = match floor_regions(&cells).first() {
+        Some(largest) => most_central(largest, width, height),
+        None => {
+            let center = (width / 2, height / 2);
+            carve(&mut cells, center.0, center.1);
+            center
+        }
+    };
+
+    Dungeon {
+        cells: cells,
+        spawn: spawn,
+    }
+}
+
+/// Generates a map from `rules`. The same `rules` and `seed` always produce the same map.
+pub fn generate(rules: &RuleSet, seed: u64) -> Dungeon {
+    let mut rng = rng_from_seed(seed);
+    let (width, height) = (cmp::max(rules.width, MIN_SIZE), cmp::max(rules.height, MIN_SIZE));
+    match rules.layout {
+        Layout::Rooms {
+            room_count,
+            min_room_size,
+            max_room_size,
+        } => {
+            rooms(
+                &mut rng,
+                width,
+                height,
+                room_count,
+                min_room_size,
+                max_room_size,
+            )
+        }
+        Layout::Caves {
+            wall_probability,
+            smoothing_steps,
+        } => caves(&mut rng, width, height, wall_probability, smoothing_steps),
+        Layout::Overworld {
+            water_level,
+            tree_level,
+            smoothing_steps,
+        } => {
+            overworld(
+                &mut rng,
+                width,
+                height,
+                water_level,
+                tree_level,
+                smoothing_steps,
+            )
+        }
+    }
+}
+
+impl Dungeon {
+    /// Builds a renderable `Tilesheet` using the tileset of `template`, so generated maps share
+    /// its image and `walkable` properties. The spawn point is stored as an object of type
+    /// `"spawn"`. Use `Tilesheet::save` to export the result as TMX.
+    pub fn to_tilesheet(
+        &self,
+        template: &tilesheet::Tilesheet,
+        gids: &TerrainGids,
+    ) -> Result<tilesheet::Tilesheet, tilesheet::TilesheetError> {
+        for &gid in [gids.floor, gids.wall, gids.water, gids.tree].iter() {
+            if gid == 0 || gid > template.tile_count() {
+                return Err(tilesheet::TilesheetError::NoSuchGid(gid));
+            }
+        }
+
+        let tiles: Vec<Vec<u32>> = self.cells
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|&cell| match cell {
+                        Cell::Floor => gids.floor,
+                        Cell::Wall => gids.wall,
+                        Cell::Water => gids.water,
+                        Cell::Tree => gids.tree,
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let spawn_position = coordinates::TileIndex::from_usize(self.spawn.0, self.spawn.1)
+            .center(template.tile_size());
+        let spawn = tiled::Object {
+            id: 1,
+            gid: 0,
+            name: String::from("spawn"),
+            obj_type: String::from("spawn"),
+            width: 0.0,
+            height: 0.0,
+            x: spawn_position.x as f32,
+            y: spawn_position.y as f32,
+            rotation: 0.0,
+            visible: true,
+            shape: tiled::ObjectShape::Rect {
+                width: 0.0,
+                height: 0.0,
+            },
+            properties: HashMap::new(),
+        };
+
+        let map = tiled::Map {
+            version: String::from("1.0"),
+            orientation: tiled::Orientation::Orthogonal,
+            width: tiles.first().map(|row| row.len()).unwrap_or(0) as u32,
+            height: tiles.len() as u32,
+            tile_width: template.tile_width(),
+            tile_height: template.tile_height(),
+            tilesets: template.map().tilesets.clone(),
+            layers: vec![
+                tiled::Layer {
+                    name: String::from("Ground"),
+                    opacity: 1.0,
+                    visible: true,
+                    tiles: tiles,
+                    properties: HashMap::new(),
+                },
+            ],
+            image_layers: Vec::new(),
+            object_groups: vec![
+                tiled::ObjectGroup {
+                    name: String::from("Objects"),
+                    opacity: 1.0,
+                    visible: true,
+                    objects: vec![spawn],
+                    colour: None,
+                },
+            ],
+            properties: HashMap::new(),
+            background_colour: template.map().background_colour.clone(),
+        };
+
+        Ok(tilesheet::Tilesheet::from_template(template, map))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(layout: Layout) -> RuleSet {
+        RuleSet {
+            width: 48,
+            height: 32,
+            layout: layout,
+        }
+    }
+
+    fn rooms_layout() -> Layout {
+        Layout::Rooms {
+            room_count: 6,
+            min_room_size: 3,
+            max_room_size: 8,
+        }
+    }
+
+    fn caves_layout() -> Layout {
+        Layout::Caves {
+            wall_probability: 0.45,
+            smoothing_steps: 4,
+        }
+    }
+
+    #[test]
+    fn same_seed_same_map() {
+        for layout in vec![rooms_layout(), caves_layout()] {
+            let rules = rules(layout);
+            assert!(generate(&rules, 42).cells == generate(&rules, 42).cells);
+            assert!(generate(&rules, 42).cells != generate(&rules, 43).cells);
+        }
+    }
+
+    #[test]
+    fn spawn_is_on_floor() {
+        for layout in vec![rooms_layout(), caves_layout(), overworld_layout()] {
+            let dungeon = generate(&rules(layout), 7);
+            let (x, y) = dungeon.spawn;
+            assert_eq!(Cell::Floor, dungeon.cells[y][x]);
+        }
+    }
+
+    #[test]
+    fn all_floor_is_connected() {
+        for layout in vec![rooms_layout(), caves_layout()] {
+            for seed in 0..8 {
+                let dungeon = generate(&rules(layout.clone()), seed);
+                assert_eq!(1, floor_regions(&dungeon.cells).len());
+            }
+        }
+    }
+
+    fn overworld_layout() -> Layout {
+        Layout::Overworld {
+            water_level: 0.3,
+            tree_level: 0.7,
+            smoothing_steps: 3,
+        }
+    }
+
+    #[test]
+    fn border_is_closed() {
+        for layout in vec![rooms_layout(), caves_layout(), overworld_layout()] {
+            let dungeon = generate(&rules(layout), 3);
+            let last = dungeon.cells.len() - 1;
+            assert!(dungeon.cells[0].iter().all(|&c| c == Cell::Wall));
+            assert!(dungeon.cells[last].iter().all(|&c| c == Cell::Wall));
+            assert!(dungeon.cells.iter().all(|row| row[0] == Cell::Wall));
+            assert!(dungeon.cells.iter().all(|row| row[row.len() - 1] == Cell::Wall));
+        }
+    }
+
+    #[test]
+    fn tiny_rule_sets_are_enlarged() {
+        for layout in vec![rooms_layout(), caves_layout(), overworld_layout()] {
+            let rules = RuleSet {
+                width: 0,
+                height: 1,
+                layout: layout,
+            };
+            let dungeon = generate(&rules, 5);
+            assert_eq!(MIN_SIZE, dungeon.cells.len());
+            assert_eq!(MIN_SIZE, dungeon.cells[0].len());
+            let (x, y) = dungeon.spawn;
+            assert_eq!(Cell::Floor, dungeon.cells[y][x]);
+        }
+    }
+}
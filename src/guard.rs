@@ -0,0 +1,221 @@
+//! Guarding: blocking attacks from the front at the cost of stamina, and parrying.
+//!
+//! While an actor holds defend (see `actor::Actor::steer`), its guard is raised: it walks at
+//! `Shield::speed_factor` of its speed, and hits from within `Shield::arc_deg` of the way it
+//! faces lose `Shield::block` of their damage. Every blocked point of damage costs stamina, as
+//! does holding the guard. Out of stamina, the guard breaks and must be raised again.
+//!
+//! A hit within `Shield::parry_window_s` of raising the guard is *parried*: it does no damage
+//! and, if it was a melee swing, stuns the attacker for `Shield::stun_s`.
+
+use coordinates::WorldPoint;
+use animation::Facing;
+
+/// How well an actor guards.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Shield {
+    /// Fraction of a blocked hit's damage that is stopped.
+    pub block: f64,
+    /// Hits from up to this many degrees off the facing direction are blocked.
+    pub arc_deg: f64,
+    /// Walking speed is multiplied by this while guarding.
+    pub speed_factor: f64,
+    /// Stamina spent per second of guarding.
+    pub drain_per_s: f64,
+    /// Stamina spent per point of damage blocked.
+    pub stamina_per_damage: f64,
+    /// Seconds after raising the guard during which hits are parried.
+    pub parry_window_s: f64,
+    /// Seconds a parried attacker is stunned.
+    pub stun_s: f64,
+}
+
+impl Default for Shield {
+    /// Bare arms: a 90 degree front arc, with a short parry window.
+    fn default() -> Self {
+        Shield {
+            block: 0.75,
+            arc_deg: 45.0,
+            speed_factor: 0.5,
+            drain_per_s: 5.0,
+            stamina_per_damage: 1.0,
+            parry_window_s: 0.15,
+            stun_s: 0.8,
+        }
+    }
+}
+
+/// What a raised guard did to a hit; see `Guard::block`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Block {
+    /// The guard was down, or the hit came from outside its arc.
+    Unguarded,
+    /// The hit was blocked, letting `damage` through.
+    Blocked { damage: f64 },
+    /// The hit was parried, doing no damage.
+    Parried,
+}
+
+/// An actor's shield and stamina.
+pub struct Guard {
+    pub shield: Shield,
+    pub max_stamina: f64,
+    /// Stamina regained per second while the guard is down.
+    pub regen_per_s: f64,
+    stamina: f64,
+    /// Seconds since the guard was raised, while it is.
+    raised_s: Option<f64>,
+    /// Whether the guard broke and defend has not been released since.
+    broken: bool,
+}
+
+impl Guard {
+    pub fn new(shield: Shield) -> Self {
+        Guard {
+            shield: shield,
+            max_stamina: 50.0,
+            regen_per_s: 15.0,
+            stamina: 50.0,
+            raised_s: None,
+            broken: false,
+        }
+    }
+
+    pub fn stamina(&self) -> f64 {
+        self.stamina
+    }
+
+    pub fn is_raised(&self) -> bool {
+        self.raised_s.is_some()
+    }
+
+    /// Raises or lowers the guard, following the defend input. The guard stays down if it is
+    /// out of stamina, or broke and `raise` was not released since. Returns whether it is up.
+    pub fn set_raised(&mut self, raise: bool) -> bool {
+        if !raise {
+            self.raised_s = None;
+            self.broken = false;
+        } else if self.raised_s.is_none() && !self.broken && self.stamina > 0.0 {
+            self.raised_s = Some(0.0);
+        }
+        self.is_raised()
+    }
+
+    fn break_guard(&mut self) {
+        self.stamina = 0.0;
+        self.raised_s = None;
+        self.broken = true;
+    }
+
+    /// Drains stamina while the guard is up and regains it while down, for `dt_s` seconds.
+    pub fn update(&mut self, dt_s: f64) {
+        match self.raised_s {
+            Some(ref mut raised_s) => {
+                *raised_s += dt_s;
+                self.stamina -= self.shield.drain_per_s * dt_s;
+            }
+            None => {
+                self.stamina = (self.stamina + self.regen_per_s * dt_s).min(self.max_stamina);
+            }
+        }
+        if self.is_raised() && self.stamina <= 0.0 {
+            self.break_guard();
+        }
+    }
+
+    /// Whether a guard at `position` facing `facing` covers a hit coming from `source`.
+    pub fn covers(&self, position: WorldPoint, facing: Facing, source: WorldPoint) -> bool {
+        let (dx, dy) = (source.x - position.x, source.y - position.y);
+        let length = (dx * dx + dy * dy).sqrt();
+        if length <= 0.0 {
+            return true;
+        }
+        let (fx, fy) = facing.vector();
+        let cos = (dx * fx as f64 + dy * fy as f64) / length;
+        cos >= self.shield.arc_deg.to_radians().cos() - 1e-9
+    }
+
+    /// Guards against a hit of `amount` damage from `source` on an actor at `position` facing
+    /// `facing`. Blocking spends stamina; a hit that empties it breaks the guard, and the
+    /// damage the stamina could not pay for gets through.
+    pub fn block(
+        &mut self,
+        position: WorldPoint,
+        facing: Facing,
+        source: WorldPoint,
+        amount: f64,
+    ) -> Block {
+        let raised_s = match self.raised_s {
+            Some(raised_s) => raised_s,
+            None => {
+                return Block::Unguarded;
+            }
+        };
+        if !self.covers(position, facing, source) {
+            return Block::Unguarded;
+        }
+        if raised_s <= self.shield.parry_window_s {
+            return Block::Parried;
+        }
+        let blocked = amount.max(0.0) * self.shield.block;
+        self.stamina -= blocked * self.shield.stamina_per_damage;
+        let mut damage = amount - blocked;
+        if self.stamina < 0.0 {
+            damage += -self.stamina / self.shield.stamina_per_damage;
+            self.break_guard();
+        }
+        Block::Blocked { damage: damage }
+    }
+}
+
+impl Default for Guard {
+    fn default() -> Self {
+        Guard::new(Shield::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blocks_from_the_front() {
+        let mut guard = Guard::default();
+        let position = WorldPoint::new(0.0, 0.0);
+        let front = WorldPoint::new(10.0, 5.0);
+        let behind = WorldPoint::new(-10.0, 0.0);
+        assert_eq!(Block::Unguarded, guard.block(position, Facing::Right, front, 20.0));
+
+        assert!(guard.set_raised(true));
+        assert_eq!(Block::Parried, guard.block(position, Facing::Right, front, 20.0));
+        guard.update(0.2);
+        assert_eq!(
+            Block::Blocked { damage: 5.0 },
+            guard.block(position, Facing::Right, front, 20.0)
+        );
+        assert_eq!(Block::Unguarded, guard.block(position, Facing::Right, behind, 20.0));
+        assert_eq!(Block::Unguarded, guard.block(position, Facing::Up, front, 20.0));
+    }
+
+    #[test]
+    fn stamina_runs_out() {
+        let mut guard = Guard::default();
+        let position = WorldPoint::new(0.0, 0.0);
+        let front = WorldPoint::new(0.0, 10.0);
+        guard.set_raised(true);
+        guard.update(2.0);
+        assert_eq!(40.0, guard.stamina());
+
+        // 60 blocked would cost 60 stamina; the 20 it lacks get through.
+        assert_eq!(
+            Block::Blocked { damage: 40.0 },
+            guard.block(position, Facing::Down, front, 80.0)
+        );
+        assert!(!guard.is_raised());
+        // Broken guards stay down until defend is released.
+        guard.update(1.0);
+        assert!(!guard.set_raised(true));
+        assert!(!guard.set_raised(false));
+        assert!(guard.set_raised(true));
+    }
+}
@@ -6,15 +6,44 @@ extern crate gfx_texture;
 extern crate graphics;
 extern crate piston;
 extern crate image;
+extern crate rand;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
 extern crate sprite;
 extern crate tiled;
 extern crate uuid;
+extern crate xml;
 
 pub extern crate piston_window;
 
-mod actor;
-mod tilesheet;
+mod chunk_cache;
 mod input;
 mod controller;
+mod editor;
+mod minimap;
+mod pixels;
+mod roof;
+mod tmx;
 
+pub mod actor;
+pub mod actors;
+pub mod ai;
+pub mod animation;
+pub mod animation_state;
+pub mod aseprite;
+pub mod coordinates;
 pub mod game;
+pub mod generator;
+pub mod guard;
+pub mod map_image;
+pub mod melee;
+pub mod objects;
+pub mod patrol;
+pub mod projectile;
+pub mod properties;
+pub mod stats;
+pub mod tilesheet;
+pub mod validate;
+pub mod world;
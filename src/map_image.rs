@@ -0,0 +1,283 @@
+//! Renders a whole map into one image on the CPU, without a window or GPU. Used by the
+//! `map_to_png` tool for design reviews and wiki pages, and by tests that compare map loading
+//! against known-good images.
+
+use std::*;
+use coordinates::{TileIndex, WorldPoint};
+use image;
+use pixels;
+use tiled;
+use tilesheet;
+
+/// Tint drawn over tiles that are not walkable.
+static BLOCKED_OVERLAY_COLOR: [u8; 4] = [255, 0, 0, 90];
+
+/// Outline color for objects in groups that have no color of their own.
+static OBJECT_COLOR: [u8; 4] = [255, 255, 0, 255];
+
+static SPAWN_COLOR: [u8; 4] = [0, 255, 0, 255];
+
+/// Half the side length in px of a spawn marker.
+static SPAWN_MARKER_RADIUS: i64 = 4;
+
+/// Extra information drawn on top of the map's tile layers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Overlays {
+    /// Tint tiles of the ground layer that are not walkable.
+    pub walkability: bool,
+    /// Outline every object, in its group's color. Rotation is ignored.
+    pub objects: bool,
+    /// Mark every object of type `"spawn"`.
+    pub spawns: bool,
+}
+
+/// Blends `color` over the pixel at `(x, y)`. Pixels off the image are ignored.
+fn blend_pixel(target: &mut image::RgbaImage, x: i64, y: i64, color: [u8; 4]) {
+    if x < 0 || y < 0 || x >= target.width() as i64 || y >= target.height() as i64 {
+        return;
+    }
+    let pixel = target.get_pixel_mut(x as u32, y as u32);
+    pixel.data = pixels::blend(pixel.data, color);
+}
+
+fn fill_rect(target: &mut image::RgbaImage, rect: [i64; 4], color: [u8; 4]) {
+    for y in rect[1]..rect[1] + rect[3] {
+        for x in rect[0]..rect[0] + rect[2] {
+            blend_pixel(target, x, y, color);
+        }
+    }
+}
+
+/// Draws a one px wide line from `from` to `to`, both included.
+fn draw_line(target: &mut image::RgbaImage, from: WorldPoint, to: WorldPoint, color: [u8; 4]) {
+    let steps = cmp::max(
+        (to.x - from.x).abs().ceil() as i64,
+        (to.y - from.y).abs().ceil() as i64,
+    );
+    let mut last = None;
+    for step in 0..steps + 1 {
+        let t = if steps == 0 {
+            0.0
+        } else {
+            step as f64 / steps as f64
+        };
+        let x = (from.x + (to.x - from.x) * t).floor() as i64;
+        let y = (from.y + (to.y - from.y) * t).floor() as i64;
+        // Blending a pixel twice would darken the line where steps land on the same pixel.
+        if last != Some((x, y)) {
+            blend_pixel(target, x, y, color);
+            last = Some((x, y));
+        }
+    }
+}
+
+fn draw_polyline(
+    target: &mut image::RgbaImage,
+    points: &Vec<WorldPoint>,
+    closed: bool,
+    color: [u8; 4],
+) {
+    for pair in points.windows(2) {
+        draw_line(target, pair[0], pair[1], color);
+    }
+    if closed && points.len() > 2 {
+        draw_line(target, points[points.len() - 1], points[0], color);
+    }
+}
+
+/// The outline of `object` as world points, and whether it is closed.
+fn object_outline(object: &tiled::Object) -> (Vec<WorldPoint>, bool) {
+    let (x, y) = (object.x as f64, object.y as f64);
+    match object.shape {
+        tiled::ObjectShape::Polyline { ref points } => {
+            let points = points
+                .iter()
+                .map(|&(px, py)| WorldPoint::new(x + px as f64, y + py as f64))
+                .collect();
+            (points, false)
+        }
+        tiled::ObjectShape::Polygon { ref points } => {
+            let points = points
+                .iter()
+                .map(|&(px, py)| WorldPoint::new(x + px as f64, y + py as f64))
+                .collect();
+            (points, true)
+        }
+        tiled::ObjectShape::Ellipse { width, height } => {
+            let (rx, ry) = (width as f64 * 0.5, height as f64 * 0.5);
+            let segments = 32;
+            let points = (0..segments)
+                .map(|i| {
+                    let angle = i as f64 / segments as f64 * 2.0 * f64::consts::PI;
+                    WorldPoint::new(x + rx + rx * angle.cos(), y + ry + ry * angle.sin())
+                })
+                .collect();
+            (points, true)
+        }
+        tiled::ObjectShape::Rect { width, height } => {
+            // Tile objects are anchored at their bottom-left corner.
+            let top = if object.gid != 0 { y - height as f64 } else { y };
+            let (right, bottom) = (x + width as f64 - 1.0, top + height as f64 - 1.0);
+            let points = vec![
+                WorldPoint::new(x, top),
+                WorldPoint::new(right, top),
+                WorldPoint::new(right, bottom),
+                WorldPoint::new(x, bottom),
+            ];
+            (points, true)
+        }
+    }
+}
+
+fn draw_tile_layers(tilesheet: &tilesheet::Tilesheet, target: &mut image::RgbaImage) {
+    let map = tilesheet.map();
+    let atlas = tilesheet.atlas();
+    let (tile_width, tile_height) = (tilesheet.tile_width(), tilesheet.tile_height());
+    for layer in map.layers.iter().filter(|layer| layer.visible) {
+        for (row, tiles) in layer.tiles.iter().enumerate() {
+            for (column, &gid) in tiles.iter().enumerate() {
+                let rect = match tilesheet.tile_rect(gid) {
+                    Some(rect) => rect,
+                    None => continue,
+                };
+                let origin = TileIndex::from_usize(column, row)
+                    .top_left(tilesheet.tile_size());
+                for y in 0..tile_height {
+                    for x in 0..tile_width {
+                        let source = atlas.get_pixel(rect[0] as u32 + x, rect[1] as u32 + y);
+                        let mut color = source.data;
+                        color[3] = (color[3] as f32 * layer.opacity) as u8;
+                        blend_pixel(
+                            target,
+                            origin.x as i64 + x as i64,
+                            origin.y as i64 + y as i64,
+                            color,
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn draw_walkability(tilesheet: &tilesheet::Tilesheet, target: &mut image::RgbaImage) {
+    let map = tilesheet.map();
+    let (tile_width, tile_height) = (tilesheet.tile_width() as i64, tilesheet.tile_height() as i64);
+    for row in 0..map.height as i64 {
+        for column in 0..map.width as i64 {
+            let index = TileIndex::new(column, row);
+            let flags = tilesheet.flags(tilesheet::GROUND_LAYER_INDEX, index);
+            if flags.map_or(true, |f| f.contains(tilesheet::TileFlags::WALKABLE)) {
+                continue;
+            }
+            fill_rect(
+                target,
+                [column * tile_width, row * tile_height, tile_width, tile_height],
+                BLOCKED_OVERLAY_COLOR,
+            );
+        }
+    }
+}
+
+fn draw_objects(map: &tiled::Map, target: &mut image::RgbaImage) {
+    for group in map.object_groups.iter() {
+        let color = match group.colour {
+            Some(ref c) => [c.red, c.green, c.blue, 255],
+            None => OBJECT_COLOR,
+        };
+        for object in group.objects.iter() {
+            let (points, closed) = object_outline(object);
+            draw_polyline(target, &points, closed, color);
+        }
+    }
+}
+
+fn draw_spawns(map: &tiled::Map, target: &mut image::RgbaImage) {
+    let r = SPAWN_MARKER_RADIUS;
+    for group in map.object_groups.iter() {
+        for object in group.objects.iter().filter(|o| o.obj_type == "spawn") {
+            let (x, y) = (object.x.floor() as i64, object.y.floor() as i64);
+            fill_rect(target, [x - r, y - r, 2 * r + 1, 2 * r + 1], SPAWN_COLOR);
+        }
+    }
+}
+
+/// Composites every visible tile layer of the map, at one image pixel per world pixel, onto the
+/// map's background color (transparent if it has none), then draws the requested `overlays`.
+pub fn render(tilesheet: &tilesheet::Tilesheet, overlays: &Overlays) -> image::RgbaImage {
+    let map = tilesheet.map();
+    let background = match map.background_colour {
+        Some(ref c) => [c.red, c.green, c.blue, 255],
+        None => [0, 0, 0, 0],
+    };
+    let mut target = image::RgbaImage::from_pixel(
+        map.width * tilesheet.tile_width(),
+        map.height * tilesheet.tile_height(),
+        image::Rgba { data: background },
+    );
+
+    draw_tile_layers(tilesheet, &mut target);
+    if overlays.walkability {
+        draw_walkability(tilesheet, &mut target);
+    }
+    if overlays.objects {
+        draw_objects(map, &mut target);
+    }
+    if overlays.spawns {
+        draw_spawns(map, &mut target);
+    }
+    target
+}
+
+/// Renders the map like `render` and writes it as a PNG file at `png_path`.
+pub fn save_png(
+    tilesheet: &tilesheet::Tilesheet,
+    overlays: &Overlays,
+    png_path: &path::Path,
+) -> io::Result<()> {
+    render(tilesheet, overlays).save(png_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tiles_match_the_tileset() {
+        let tilesheet = tilesheet::demo();
+        let image = render(&tilesheet, &Overlays::default());
+        let map = tilesheet.map();
+        assert_eq!(
+            (map.width * map.tile_width, map.height * map.tile_height),
+            image.dimensions()
+        );
+
+        // The demo map has a single, fully opaque layer over its background color.
+        let background = map.background_colour.clone().unwrap();
+        let index = TileIndex::new(3, 2);
+        let rect = tilesheet.tile_rect(tilesheet.tile_gid(0, index).unwrap()).unwrap();
+        let origin = index.top_left(tilesheet.tile_size());
+        let tile_pixel = tilesheet.atlas().get_pixel(rect[0] as u32 + 5, rect[1] as u32 + 7);
+        assert_eq!(
+            pixels::blend(
+                [background.red, background.green, background.blue, 255],
+                tile_pixel.data,
+            ),
+            image.get_pixel(origin.x as u32 + 5, origin.y as u32 + 7).data
+        );
+    }
+
+    #[test]
+    fn object_overlay_outlines_rects() {
+        let tilesheet = tilesheet::demo();
+        let plain = render(&tilesheet, &Overlays::default());
+        let overlays = Overlays {
+            objects: true,
+            ..Overlays::default()
+        };
+        let outlined = render(&tilesheet, &overlays);
+        // Object 1 is a rect at (14, 9); its corner is outlined but its inside is not.
+        assert_eq!(OBJECT_COLOR, outlined.get_pixel(14, 9).data);
+        assert_eq!(plain.get_pixel(20, 20), outlined.get_pixel(20, 20));
+    }
+}
@@ -0,0 +1,292 @@
+//! Real-time melee attacks.
+//!
+//! An attack swings the actor's `Weapon` in the direction it faces. The weapon's hitbox is only
+//! *active* for part of the swing: from the attack animation's first `"hit"` frame event (see
+//! `animation`), or after `Weapon::windup_s` if there is none by then, for `Weapon::active_s`.
+//! While active, it damages every other living actor whose hurtbox (`actor::Actor::hurtbox`) it
+//! overlaps, each at most once per swing. Targets may block or parry the hit; see `guard`.
+
+use std::collections::HashMap;
+use piston_window::{GenericEvent, UpdateEvent};
+use actors::{ActorId, Actors};
+use animation::Facing;
+use animation_state::ActionState;
+use coordinates::WorldPoint;
+use guard;
+use stats;
+
+/// The frame event that activates a swing's hitbox.
+pub static HIT_EVENT: &'static str = "hit";
+
+/// The area a weapon strikes, centered `Weapon::reach` px in front of the attacker.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HitboxShape {
+    /// `length` px along the facing direction by `width` px across it. Diagonal swings strike
+    /// a square of the average of the two.
+    Rect { length: f64, width: f64 },
+    Circle { radius: f64 },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Weapon {
+    /// Attack strength, passed to the damage formula (see `stats::DamageFormula`).
+    pub power: f64,
+    /// Distance in px from the attacker's center to the hitbox's center.
+    pub reach: f64,
+    pub shape: HitboxShape,
+    /// Seconds from the start of the swing until the hitbox activates, if no `"hit"` frame
+    /// event activates it sooner.
+    pub windup_s: f64,
+    /// Seconds the hitbox stays active.
+    pub active_s: f64,
+    /// Seconds from the start of one swing until the next may start.
+    pub cooldown_s: f64,
+}
+
+impl Default for Weapon {
+    /// Bare fists: weak, short and quick.
+    fn default() -> Self {
+        Weapon {
+            power: 10.0,
+            reach: 16.0,
+            shape: HitboxShape::Rect {
+                length: 16.0,
+                width: 24.0,
+            },
+            windup_s: 0.1,
+            active_s: 0.1,
+            cooldown_s: 0.4,
+        }
+    }
+}
+
+/// Whether rectangles `(x, y, width, height)` overlap.
+fn rects_overlap(a: [f64; 4], b: [f64; 4]) -> bool {
+    a[0] < b[0] + b[2] && b[0] < a[0] + a[2] && a[1] < b[1] + b[3] && b[1] < a[1] + a[3]
+}
+
+impl Weapon {
+    /// Whether the hitbox of a swing from `origin` toward `facing` overlaps `rect`
+    /// `(x, y, width, height)`, all in world coordinates.
+    pub fn hits(&self, origin: WorldPoint, facing: Facing, rect: [f64; 4]) -> bool {
+        let (fx, fy) = facing.vector();
+        let center = WorldPoint::new(
+            origin.x + fx as f64 * self.reach,
+            origin.y + fy as f64 * self.reach,
+        );
+        match self.shape {
+            HitboxShape::Rect { length, width } => {
+                let (w, h) = match facing {
+                    Facing::Left | Facing::Right => (length, width),
+                    Facing::Up | Facing::Down => (width, length),
+                    _ => ((length + width) * 0.5, (length + width) * 0.5),
+                };
+                rects_overlap([center.x - w * 0.5, center.y - h * 0.5, w, h], rect)
+            }
+            HitboxShape::Circle { radius } => center.distance_to_rect(rect) <= radius,
+        }
+    }
+}
+
+/// One swing in progress.
+struct Swing {
+    elapsed_s: f64,
+    /// Seconds into the swing at which the hitbox activated.
+    active_since_s: Option<f64>,
+    /// Actors this swing already hit.
+    hit: Vec<ActorId>,
+}
+
+/// An actor's weapon and the state of its swing.
+pub struct Melee {
+    pub weapon: Weapon,
+    cooldown_s: f64,
+    swing: Option<Swing>,
+}
+
+impl Melee {
+    pub fn new(weapon: Weapon) -> Self {
+        Melee {
+            weapon: weapon,
+            cooldown_s: 0.0,
+            swing: None,
+        }
+    }
+
+    /// Whether the cooldown since the previous swing has passed.
+    pub fn is_ready(&self) -> bool {
+        self.cooldown_s <= 0.0
+    }
+
+    /// Whether the hitbox is active right now.
+    pub fn is_active(&self) -> bool {
+        match self.swing {
+            Some(ref swing) => swing.active_since_s.is_some(),
+            None => false,
+        }
+    }
+
+    /// Starts a swing; see `actor::Actor::attack`.
+    pub fn start(&mut self) {
+        self.cooldown_s = self.weapon.cooldown_s;
+        self.swing = Some(Swing {
+            elapsed_s: 0.0,
+            active_since_s: None,
+            hit: Vec::new(),
+        });
+    }
+
+    /// Stops the swing early, e.g. when the attacker gets hurt.
+    pub fn cancel(&mut self) {
+        self.swing = None;
+    }
+
+    /// Advances the swing and cooldown by `dt_s` seconds. `events` are the attacker's animation
+    /// events of this update.
+    pub fn update(&mut self, dt_s: f64, events: &[String]) {
+        self.cooldown_s -= dt_s;
+        let finished = match self.swing {
+            Some(ref mut swing) => {
+                swing.elapsed_s += dt_s;
+                if swing.active_since_s.is_none() &&
+                    (events.iter().any(|event| event == HIT_EVENT) ||
+                         swing.elapsed_s >= self.weapon.windup_s)
+                {
+                    swing.active_since_s = Some(swing.elapsed_s);
+                }
+                match swing.active_since_s {
+                    Some(since_s) => swing.elapsed_s - since_s >= self.weapon.active_s,
+                    None => false,
+                }
+            }
+            None => false,
+        };
+        if finished {
+            self.swing = None;
+        }
+    }
+}
+
+impl Default for Melee {
+    fn default() -> Self {
+        Melee::new(Weapon::default())
+    }
+}
+
+/// A hit found by `update`, applied once all hits are known.
+struct Hit {
+    attacker: ActorId,
+    /// Where the attacker stands, for the target's guard.
+    origin: WorldPoint,
+    target: ActorId,
+    damage: f64,
+}
+
+/// Advances every actor's swing for `event`, and damages the actors that active hitboxes
+/// overlap using `formula`. Call after the actors' animations updated this event.
+pub fn update<E: GenericEvent>(event: &E, actors: &mut Actors, formula: stats::DamageFormula) {
+    let dt_s = event.update(|args| args.dt).unwrap_or(0.0);
+    for (_, actor) in actors.iter_mut() {
+        // Getting hurt or dying mid-swing cancels it.
+        if actor.state() != ActionState::Attack {
+            actor.melee.cancel();
+        }
+        let events = actor.events().to_vec();
+        actor.melee.update(dt_s, &events);
+    }
+
+    let mut hits = Vec::new();
+    for (attacker_id, attacker) in actors.iter() {
+        if !attacker.melee.is_active() {
+            continue;
+        }
+        let already_hit = match attacker.melee.swing {
+            Some(ref swing) => &swing.hit,
+            None => continue,
+        };
+        for (target_id, target) in actors.iter() {
+            if target_id == attacker_id || target.is_dead() ||
+                already_hit.contains(&target_id) ||
+                !attacker.melee.weapon.hits(
+                    attacker.position(),
+                    attacker.facing,
+                    target.hurtbox_rect(),
+                )
+            {
+                continue;
+            }
+            hits.push(Hit {
+                attacker: attacker_id,
+                origin: attacker.position(),
+                target: target_id,
+                damage: formula(
+                    &attacker.stats,
+                    &target.stats,
+                    attacker.melee.weapon.power,
+                ),
+            });
+        }
+    }
+
+    let mut dealt = HashMap::<ActorId, Vec<ActorId>>::new();
+    let mut parried = Vec::new();
+    for hit in hits {
+        if let Some(target) = actors.get_mut(hit.target) {
+            if let (guard::Block::Parried, _) = target.take_hit(hit.damage, hit.origin) {
+                parried.push((hit.attacker, target.guard.shield.stun_s));
+            }
+        }
+        dealt.entry(hit.attacker).or_insert_with(Vec::new).push(hit.target);
+    }
+    for (attacker_id, stun_s) in parried {
+        if let Some(attacker) = actors.get_mut(attacker_id) {
+            attacker.stun(stun_s);
+        }
+    }
+    for (attacker_id, targets) in dealt {
+        if let Some(attacker) = actors.get_mut(attacker_id) {
+            if let Some(ref mut swing) = attacker.melee.swing {
+                swing.hit.extend(targets);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hitbox_follows_facing() {
+        let weapon = Weapon::default();
+        let origin = WorldPoint::new(100.0, 100.0);
+        // A 10 px target just right of the attacker.
+        let right = [110.0, 95.0, 10.0, 10.0];
+        assert!(weapon.hits(origin, Facing::Right, right));
+        assert!(!weapon.hits(origin, Facing::Left, right));
+        assert!(!weapon.hits(origin, Facing::Up, right));
+
+        let circle = Weapon {
+            shape: HitboxShape::Circle { radius: 4.0 },
+            ..Weapon::default()
+        };
+        assert!(circle.hits(origin, Facing::Down, [95.0, 118.0, 10.0, 10.0]));
+        assert!(!circle.hits(origin, Facing::Down, [95.0, 121.0, 10.0, 10.0]));
+    }
+
+    #[test]
+    fn swing_timing() {
+        let mut melee = Melee::default();
+        melee.start();
+        assert!(!melee.is_ready());
+        melee.update(0.05, &[]);
+        assert!(!melee.is_active());
+        // The hit frame activates the hitbox before the windup is over.
+        melee.update(0.01, &[String::from(HIT_EVENT)]);
+        assert!(melee.is_active());
+        melee.update(0.15, &[]);
+        assert!(!melee.is_active());
+        melee.update(0.3, &[]);
+        assert!(melee.is_ready());
+    }
+}
@@ -0,0 +1,231 @@
+use std::*;
+use std::collections::HashMap;
+use gfx_core;
+use gfx_device_gl;
+use coordinates;
+use image;
+use piston_window;
+use piston_window::*;
+use pixels;
+use properties;
+use tilesheet;
+
+/// The longest side of the minimap, in screen px. Maps are scaled to fit.
+static MINIMAP_MAX_SIZE: f64 = 160.0;
+
+/// Gap in px between the minimap and the bottom-right corner of the window.
+static MINIMAP_MARGIN: f64 = 8.0;
+
+/// Side length in screen px of an actor marker.
+static MARKER_SIZE: f64 = 4.0;
+
+pub static HERO_MARKER_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+
+/// Marker color of every living actor other than the hero.
+pub static ACTOR_MARKER_COLOR: [f32; 4] = [0.9, 0.2, 0.2, 1.0];
+
+/// The alpha-weighted average color of `tile_gid`'s pixels in the tileset image.
+fn average_color(tilesheet: &tilesheet::Tilesheet, tile_gid: u32) -> [u8; 4] {
+    let rect = match tilesheet.tile_rect(tile_gid) {
+        Some(rect) => rect,
+        None => return [0, 0, 0, 0],
+    };
+    let image = tilesheet.atlas();
+    let mut sum = [0.0f64; 4];
+    let mut count = 0.0;
+    for y in rect[1] as u32..(rect[1] + rect[3]) as u32 {
+        for x in rect[0] as u32..(rect[0] + rect[2]) as u32 {
+            if x >= image.width() || y >= image.height() {
+                continue;
+            }
+            let pixel = image.get_pixel(x, y).data;
+            let alpha = pixel[3] as f64 / 255.0;
+            sum[0] += pixel[0] as f64 * alpha;
+            sum[1] += pixel[1] as f64 * alpha;
+            sum[2] += pixel[2] as f64 * alpha;
+            sum[3] += alpha;
+            count += 1.0;
+        }
+    }
+    if sum[3] == 0.0 {
+        return [0, 0, 0, 0];
+    }
+    [
+        (sum[0] / sum[3]) as u8,
+        (sum[1] / sum[3]) as u8,
+        (sum[2] / sum[3]) as u8,
+        (255.0 * sum[3] / count) as u8,
+    ]
+}
+
+/// A tile's `minimap_color` property if it has one, otherwise its average color.
+fn tile_color(tilesheet: &tilesheet::Tilesheet, tile_gid: u32) -> [u8; 4] {
+    // Only the tile itself is consulted; a map-wide minimap_color would paint everything alike.
+    let property = tilesheet.tile(tile_gid).and_then(|tile| {
+        properties::PropertyChain::new().then(&tile.properties).get::<properties::Color>(
+            "minimap_color",
+        )
+    });
+    match property {
+        Some(color) => color.to_rgba(),
+        None => average_color(tilesheet, tile_gid),
+    }
+}
+
+/// Renders the map's visible tile layers at one pixel per tile.
+///
+/// There is no fog of war yet; once there is, unexplored tiles should be left transparent here.
+pub fn build_image(tilesheet: &tilesheet::Tilesheet) -> image::RgbaImage {
+    let map = tilesheet.map();
+    let mut colors = HashMap::<u32, [u8; 4]>::new();
+    let mut minimap = image::RgbaImage::new(map.width, map.height);
+
+    for layer in map.layers.iter().filter(|layer| layer.visible) {
+        for (y, row) in layer.tiles.iter().enumerate() {
+            for (x, &tile) in row.iter().enumerate() {
+                if tile == 0 || x as u32 >= map.width || y as u32 >= map.height {
+                    continue;
+                }
+                let mut color = *colors.entry(tile).or_insert_with(
+                    || tile_color(tilesheet, tile),
+                );
+                color[3] = (color[3] as f32 * layer.opacity) as u8;
+                let pixel = minimap.get_pixel_mut(x as u32, y as u32);
+                pixel.data = pixels::blend(pixel.data, color);
+            }
+        }
+    }
+    minimap
+}
+
+/// A small overview of the whole map drawn in the bottom-right corner. Toggle with M.
+pub struct Minimap {
+    visible: bool,
+    texture: Option<G2dTexture>,
+
+    /// `Tilesheet::revision` that `texture` was built from.
+    revision: u64,
+    map_size: [f64; 2],
+    tile_size: coordinates::TileSize,
+}
+
+impl Minimap {
+    pub fn new() -> Self {
+        Minimap {
+            visible: true,
+            texture: None,
+            revision: 0,
+            map_size: [0.0, 0.0],
+            tile_size: coordinates::TileSize {
+                width: 1.0,
+                height: 1.0,
+            },
+        }
+    }
+
+    /// Rebuilds the minimap texture if `tilesheet` changed since the last call.
+    pub fn update(
+        &mut self,
+        factory: &mut gfx_device_gl::Factory,
+        tilesheet: &tilesheet::Tilesheet,
+    ) -> Result<(), gfx_core::factory::CombinedError> {
+        if self.texture.is_some() && self.revision == tilesheet.revision() {
+            return Ok(());
+        }
+
+        let minimap = build_image(tilesheet);
+        let settings = TextureSettings::new().filter(Filter::Nearest);
+        let texture = Texture::from_image(factory, &minimap, &settings)?;
+
+        self.texture = Some(texture);
+        self.revision = tilesheet.revision();
+        self.map_size = [minimap.width() as f64, minimap.height() as f64];
+        self.tile_size = tilesheet.tile_size();
+        Ok(())
+    }
+
+    /// Forgets the current texture, so the next `update` rebuilds it. Needed when switching to
+    /// a different map, whose revision may happen to match.
+    pub fn invalidate(&mut self) {
+        self.texture = None;
+    }
+
+    pub fn process_event(&mut self, event: &piston_window::Event) {
+        if let Some(Button::Keyboard(keyboard::Key::M)) = event.press_args() {
+            self.visible = !self.visible;
+        }
+    }
+
+    /// Draws the minimap, with a marker at each of `markers`: (position, color).
+    pub fn render(
+        &self,
+        context: piston_window::Context,
+        renderer: &mut G2d,
+        markers: &[(coordinates::WorldPoint, [f32; 4])],
+    ) {
+        let texture = match self.texture {
+            Some(ref texture) if self.visible => texture,
+            _ => {
+                return;
+            }
+        };
+        let viewport = match context.viewport {
+            Some(viewport) => viewport,
+            None => {
+                return;
+            }
+        };
+        if self.map_size[0] == 0.0 || self.map_size[1] == 0.0 {
+            return;
+        }
+
+        // Screen px per tile.
+        let scale = (MINIMAP_MAX_SIZE / self.map_size[0]).min(
+            MINIMAP_MAX_SIZE / self.map_size[1],
+        );
+        let size = [self.map_size[0] * scale, self.map_size[1] * scale];
+        let origin = [
+            viewport.window_size[0] as f64 - size[0] - MINIMAP_MARGIN,
+            viewport.window_size[1] as f64 - size[1] - MINIMAP_MARGIN,
+        ];
+
+        rectangle(
+            [0.0, 0.0, 0.0, 0.7],
+            [origin[0] - 2.0, origin[1] - 2.0, size[0] + 4.0, size[1] + 4.0],
+            context.transform,
+            renderer,
+        );
+        Image::new()
+            .rect([origin[0], origin[1], size[0], size[1]])
+            .draw(texture, &context.draw_state, context.transform, renderer);
+
+        for &(position, color) in markers.iter() {
+            let x = origin[0] + position.x / self.tile_size.width * scale;
+            let y = origin[1] + position.y / self.tile_size.height * scale;
+            rectangle(
+                color,
+                [
+                    x - MARKER_SIZE * 0.5,
+                    y - MARKER_SIZE * 0.5,
+                    MARKER_SIZE,
+                    MARKER_SIZE,
+                ],
+                context.transform,
+                renderer,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn one_pixel_per_tile() {
+        let tilesheet = tilesheet::demo();
+        let minimap = build_image(&tilesheet);
+        assert_eq!(tilesheet.map().width, minimap.width());
+        assert_eq!(tilesheet.map().height, minimap.height());
+    }
+}
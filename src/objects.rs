@@ -0,0 +1,185 @@
+use std::*;
+use std::collections::HashMap;
+use coordinates::WorldPoint;
+use properties;
+use tiled;
+use tilesheet;
+
+/// Everything a factory gets to build an entity from one map object.
+pub struct ObjectContext<'a> {
+    /// The map the object is on.
+    pub tilesheet: &'a tilesheet::Tilesheet,
+    pub object: &'a tiled::Object,
+    /// The object's properties, falling back to the map's.
+    pub properties: properties::PropertyChain<'a>,
+    /// World position of the top-left corner of the object's bounding box.
+    pub position: WorldPoint,
+    /// World position of the center of the object's bounding box. Actors are placed here.
+    pub center: WorldPoint,
+    /// World position of the top-left corner of the map.
+    pub map_origin: WorldPoint,
+    /// Placement index of the map the object is on. See `world::World`.
+    pub map_index: usize,
+}
+
+impl<'a> ObjectContext<'a> {
+    /// `map_origin` is the world position of the top-left corner of the map.
+    pub fn new(
+        tilesheet: &'a tilesheet::Tilesheet,
+        object: &'a tiled::Object,
+        map_origin: WorldPoint,
+        map_index: usize,
+    ) -> Self {
+        // Tile objects are anchored at their bottom-left corner, everything else at the top-left.
+        let top = if object.gid != 0 {
+            object.y - object.height
+        } else {
+            object.y
+        };
+        let position = WorldPoint::new(map_origin.x + object.x as f64, map_origin.y + top as f64);
+        ObjectContext {
+            tilesheet: tilesheet,
+            object: object,
+            properties: tilesheet.object_properties(object),
+            position: position,
+            center: WorldPoint::new(
+                position.x + object.width as f64 * 0.5,
+                position.y + object.height as f64 * 0.5,
+            ),
+            map_origin: map_origin,
+            map_index: map_index,
+        }
+    }
+}
+
+/// Builds an entity from a map object, or explains why it cannot.
+pub type Factory<E> = Box<Fn(&ObjectContext) -> Result<E, String>>;
+
+/// Maps Tiled object types (e.g. `"chest"`, `"npc"`, `"sign"`) to factories that build game
+/// entities of type `E` from them, so designers can place content in Tiled without code changes
+/// for each map.
+///
+/// Objects whose type has no factory are left alone; some types (e.g. `"spawn"` or
+/// `"building"`) are read directly by the engine instead.
+pub struct ObjectRegistry<E> {
+    factories: HashMap<String, Factory<E>>,
+}
+
+impl<E> ObjectRegistry<E> {
+    pub fn new() -> Self {
+        ObjectRegistry { factories: HashMap::new() }
+    }
+
+    /// Registers `factory` for objects of type `obj_type`.
+    /// If `obj_type` already had a factory, the previous one is returned.
+    pub fn register<F>(&mut self, obj_type: &str, factory: F) -> Option<Factory<E>>
+    where
+        F: Fn(&ObjectContext) -> Result<E, String> + 'static,
+    {
+        self.factories.insert(String::from(obj_type), Box::new(factory))
+    }
+
+    pub fn is_registered(&self, obj_type: &str) -> bool {
+        self.factories.contains_key(obj_type)
+    }
+
+    /// Builds the entity for one object, or returns `None` if its type is not registered.
+    pub fn spawn(&self, context: &ObjectContext) -> Option<Result<E, String>> {
+        self.factories.get(&context.object.obj_type).map(
+            |factory| factory(context),
+        )
+    }
+
+    /// Builds an entity for every object of a registered type on the map, in map order.
+    /// Errors name the object they came from.
+    ///
+    /// `map_origin` and `map_index` place the map in the world; use the origin and index 0 for
+    /// a game on a single map.
+    pub fn spawn_map(
+        &self,
+        tilesheet: &tilesheet::Tilesheet,
+        map_origin: WorldPoint,
+        map_index: usize,
+    ) -> Vec<Result<E, String>> {
+        let mut entities = Vec::new();
+        self.spawn_map_with(tilesheet, map_origin, map_index, |_, result| {
+            entities.push(result)
+        });
+        entities
+    }
+
+    /// Like `spawn_map`, but hands each result to `spawned` along with the object it came from.
+    pub fn spawn_map_with<F>(
+        &self,
+        tilesheet: &tilesheet::Tilesheet,
+        map_origin: WorldPoint,
+        map_index: usize,
+        mut spawned: F,
+    ) where
+        F: FnMut(&tiled::Object, Result<E, String>),
+    {
+        for group in tilesheet.map().object_groups.iter() {
+            for object in group.objects.iter() {
+                let context = ObjectContext::new(tilesheet, object, map_origin, map_index);
+                if let Some(result) = self.spawn(&context) {
+                    spawned(
+                        object,
+                        result.map_err(|e| {
+                            format!("object {} ({:?}): {}", object.id, object.obj_type, e)
+                        }),
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spawns_registered_types_only() {
+        let mut map = tilesheet::demo().map().clone();
+        {
+            let objects = &mut map.object_groups[0].objects;
+            objects[0].obj_type = String::from("chest");
+            objects[0].properties.insert(
+                String::from("gold"),
+                tiled::PropertyValue::IntValue(25),
+            );
+            objects[1].obj_type = String::from("sign");
+        }
+        let tilesheet = tilesheet::Tilesheet::from_template(&tilesheet::demo(), map);
+
+        let mut registry = ObjectRegistry::<(WorldPoint, i32)>::new();
+        registry.register("chest", |context| {
+            Ok((context.position, context.properties.int_or("gold", 0)))
+        });
+        assert!(registry.is_registered("chest"));
+        assert!(!registry.is_registered("sign"));
+
+        let spawned = registry.spawn_map(&tilesheet, WorldPoint::new(100.0, 200.0), 0);
+        assert_eq!(1, spawned.len());
+        // Object 1 sits at (14, 9) on the map.
+        assert_eq!(
+            &(WorldPoint::new(114.0, 209.0), 25),
+            spawned[0].as_ref().unwrap()
+        );
+    }
+
+    #[test]
+    fn errors_name_the_object() {
+        let mut map = tilesheet::demo().map().clone();
+        map.object_groups[0].objects[0].obj_type = String::from("chest");
+        let tilesheet = tilesheet::Tilesheet::from_template(&tilesheet::demo(), map);
+
+        let mut registry = ObjectRegistry::<()>::new();
+        registry.register("chest", |_| Err(String::from("no loot table")));
+        let spawned = registry.spawn_map(&tilesheet, WorldPoint::new(0.0, 0.0), 0);
+        assert_eq!(
+            Err(String::from("object 1 (\"chest\"): no loot table")),
+            spawned[0]
+        );
+    }
+}
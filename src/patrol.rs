@@ -0,0 +1,195 @@
+//! Patrol routes drawn in Tiled as polyline or polygon objects.
+//!
+//! An NPC follows a route when its `route` property names the route object, or holds its ID.
+//! The route object's properties control the walk:
+//!
+//! * `patrol`: `ping_pong` walks to the end and back, `loop` walks back to the first vertex
+//!   and around again, `once` stops at the end. Polygons loop by default, polylines ping-pong.
+//! * `wait`: seconds to stand at every vertex (default 0).
+//! * `wait_<n>`: seconds to stand at vertex `n` (counting from 0) instead.
+
+use std::*;
+use ai;
+use ai::NpcAction;
+use ai_behavior::{Action, Behavior, Select, Sequence, Wait, WaitForever};
+use coordinates::WorldPoint;
+use properties;
+use tiled;
+use tilesheet;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteMode {
+    PingPong,
+    Loop,
+    Once,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Route {
+    /// Vertices in world coordinates.
+    pub points: Vec<WorldPoint>,
+    /// Seconds to stand at each of `points`.
+    pub waits: Vec<f64>,
+    pub mode: RouteMode,
+}
+
+impl Route {
+    /// The route drawn by `object`, a polyline or polygon on the map whose top-left corner is
+    /// at `map_origin`.
+    pub fn from_object(
+        tilesheet: &tilesheet::Tilesheet,
+        object: &tiled::Object,
+        map_origin: WorldPoint,
+    ) -> Result<Self, String> {
+        let (points, default_mode) = match object.shape {
+            tiled::ObjectShape::Polyline { ref points } => (points, RouteMode::PingPong),
+            tiled::ObjectShape::Polygon { ref points } => (points, RouteMode::Loop),
+            _ => {
+                return Err(format!("route object {} is not a polyline or polygon", object.id));
+            }
+        };
+        if points.is_empty() {
+            return Err(format!("route object {} has no points", object.id));
+        }
+
+        let properties = tilesheet.object_properties(object);
+        let mode = match properties.string_or("patrol", "").as_str() {
+            "" => default_mode,
+            "ping_pong" => RouteMode::PingPong,
+            "loop" => RouteMode::Loop,
+            "once" => RouteMode::Once,
+            other => {
+                return Err(format!("route object {}: unknown patrol {:?}", object.id, other));
+            }
+        };
+        let wait = properties.float_or("wait", 0.0);
+        Ok(Route {
+            points: points
+                .iter()
+                .map(|&(x, y)| {
+                    WorldPoint::new(
+                        map_origin.x + (object.x + x) as f64,
+                        map_origin.y + (object.y + y) as f64,
+                    )
+                })
+                .collect(),
+            waits: (0..points.len())
+                .map(|i| properties.float_or(&format!("wait_{}", i), wait) as f64)
+                .collect(),
+            mode: mode,
+        })
+    }
+
+    /// The route that the `route` property in `properties` refers to, if it has one.
+    pub fn from_property(
+        tilesheet: &tilesheet::Tilesheet,
+        properties: &properties::PropertyChain,
+        map_origin: WorldPoint,
+    ) -> Option<Result<Self, String>> {
+        let reference = properties.get::<String>("route")?;
+        let object = tilesheet
+            .map()
+            .object_groups
+            .iter()
+            .flat_map(|group| group.objects.iter())
+            .find(|object| {
+                object.name == reference || object.id.to_string() == reference
+            });
+        Some(match object {
+            Some(object) => Route::from_object(tilesheet, object, map_origin),
+            None => Err(format!("no route object {:?}", reference)),
+        })
+    }
+
+    /// Vertex indices in the order they are visited during one round. Rounds repeat, except
+    /// with `RouteMode::Once`.
+    fn visits(&self) -> Vec<usize> {
+        let count = self.points.len();
+        let mut visits: Vec<usize> = (0..count).collect();
+        if self.mode == RouteMode::PingPong && count > 2 {
+            visits.extend((1..count - 1).rev());
+        }
+        visits
+    }
+
+    /// A behavior tree that walks the route. A vertex that cannot be reached, e.g. because
+    /// a wall is in the way, is skipped. Every round waits at least `ai::REACTION_S`, so a
+    /// route the NPC is already standing on, e.g. a single vertex, does not loop forever.
+    pub fn behavior(&self) -> Behavior<NpcAction> {
+        let mut legs = Vec::new();
+        for i in self.visits() {
+            legs.push(Select(vec![
+                Action(NpcAction::MoveTo(self.points[i])),
+                Sequence(vec![Action(NpcAction::Idle), Wait(ai::REACTION_S)]),
+            ]));
+            legs.push(Action(NpcAction::Idle));
+            if self.waits[i] > 0.0 {
+                legs.push(Wait(self.waits[i]));
+            }
+        }
+        match self.mode {
+            RouteMode::Once => {
+                legs.push(WaitForever);
+                Sequence(legs)
+            }
+            RouteMode::PingPong | RouteMode::Loop => {
+                legs.push(Wait(ai::REACTION_S));
+                ai::forever(Sequence(legs))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ai_behavior::While;
+
+    #[test]
+    fn routes_from_demo_objects() {
+        let mut map = tilesheet::demo().map().clone();
+        map.object_groups[0].objects[2].properties.insert(
+            String::from("wait_1"),
+            tiled::PropertyValue::FloatValue(1.5),
+        );
+        let tilesheet = tilesheet::Tilesheet::from_template(&tilesheet::demo(), map);
+        let objects = &tilesheet.map().object_groups[0].objects;
+
+        // Object 3 is a polyline at (314, 376) starting "0,0 -111,-63 ...".
+        let polyline = Route::from_object(&tilesheet, &objects[2], WorldPoint::new(0.0, 10.0))
+            .unwrap();
+        assert_eq!(RouteMode::PingPong, polyline.mode);
+        assert_eq!(WorldPoint::new(203.0, 323.0), polyline.points[1]);
+        assert_eq!(vec![0.0, 1.5, 0.0], polyline.waits[..3].to_vec());
+        assert_eq!(vec![0, 1, 2, 3, 4, 5, 4, 3, 2, 1], polyline.visits());
+
+        let polygon = Route::from_object(&tilesheet, &objects[3], WorldPoint::new(0.0, 0.0))
+            .unwrap();
+        assert_eq!(RouteMode::Loop, polygon.mode);
+        assert_eq!(vec![0, 1, 2, 3, 4, 5], polygon.visits());
+
+        assert!(Route::from_object(&tilesheet, &objects[0], WorldPoint::new(0.0, 0.0)).is_err());
+    }
+
+    #[test]
+    fn every_round_takes_time() {
+        let route = Route {
+            points: vec![WorldPoint::new(10.0, 10.0)],
+            waits: vec![0.0],
+            mode: RouteMode::Loop,
+        };
+        let legs = match route.behavior() {
+            While(_, ref body) => {
+                match body[0] {
+                    Sequence(ref legs) => legs.clone(),
+                    ref other => panic!("unexpected {:?}", other),
+                }
+            }
+            other => panic!("unexpected {:?}", other),
+        };
+        match legs.last() {
+            Some(&Wait(wait_s)) => assert!(wait_s > 0.0),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
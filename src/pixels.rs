@@ -0,0 +1,25 @@
+//! Pixel helpers shared by the CPU image renderers (`minimap`, `map_image`).
+
+use std::*;
+
+/// Composites `top` over `bottom`.
+pub fn blend(bottom: [u8; 4], top: [u8; 4]) -> [u8; 4] {
+    let alpha = top[3] as u32;
+    let mut out = [0u8; 4];
+    for i in 0..3 {
+        out[i] = ((top[i] as u32 * alpha + bottom[i] as u32 * (255 - alpha)) / 255) as u8;
+    }
+    out[3] = cmp::max(bottom[3], top[3]);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn opaque_top_replaces_bottom() {
+        assert_eq!([9, 8, 7, 255], blend([1, 2, 3, 255], [9, 8, 7, 255]));
+        assert_eq!([1, 2, 3, 255], blend([1, 2, 3, 255], [9, 8, 7, 0]));
+    }
+}
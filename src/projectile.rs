@@ -0,0 +1,504 @@
+//! Projectiles: arrows, fireballs, thrown rocks and the like.
+//!
+//! Projectiles fly in a straight line until their lifetime runs out or they hit something.
+//! On hitting an actor they deal damage and stop, unless they can still pierce. On hitting
+//! a non-walkable tile they stop, unless they can still bounce. Explosive projectiles
+//! damage every actor near where they stop, including on running out of time. Guards (see
+//! `guard`) block hits coming from the front, and a blocked projectile stops even if it could
+//! pierce.
+//!
+//! Actors fire their `actor::Actor::projectile` when their animation emits the
+//! `"spawn_projectile"` frame event; see `fire_from_events`.
+
+use std::*;
+use piston_window::{self, G2d, GenericEvent, Transformed, UpdateEvent};
+use actor;
+use actors::{ActorId, Actors};
+use animation::{Directions, Facing};
+use coordinates::{Camera, WorldPoint};
+use guard;
+use stats;
+use world;
+
+/// The frame event that fires an actor's projectile.
+pub static SPAWN_EVENT: &'static str = "spawn_projectile";
+
+/// Seconds an explosion stays visible.
+static BLAST_TIME_S: f64 = 0.3;
+
+/// Builds a fresh sprite chooser for each projectile, as choosers keep per-projectile state.
+pub type SpriteFactory = rc::Rc<Fn() -> Box<actor::SpriteChooser>>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Explosion {
+    /// Radius in px of the area damaged.
+    pub radius: f64,
+    /// Attack strength of the blast. See `stats::DamageFormula`.
+    pub power: f64,
+}
+
+/// What kind of projectile to fire.
+#[derive(Clone)]
+pub struct ProjectileDef {
+    /// px per second.
+    pub speed: f64,
+    /// Seconds until the projectile disappears (or explodes).
+    pub lifetime_s: f64,
+    /// Attack strength on a direct hit. See `stats::DamageFormula`.
+    pub power: f64,
+    /// Collision radius in px.
+    pub radius: f64,
+    /// Number of actors it passes through before stopping at the next.
+    pub pierce: u32,
+    /// Number of times it bounces off walls before stopping at the next.
+    pub bounces: u32,
+    pub explosion: Option<Explosion>,
+    /// Draws the projectile; without one it is a dot of `color`.
+    pub sprite: Option<SpriteFactory>,
+    pub color: [f32; 4],
+}
+
+impl Default for ProjectileDef {
+    /// A thrown rock.
+    fn default() -> Self {
+        ProjectileDef {
+            speed: 240.0,
+            lifetime_s: 0.8,
+            power: 8.0,
+            radius: 3.0,
+            pierce: 0,
+            bounces: 0,
+            explosion: None,
+            sprite: None,
+            color: [0.4, 0.35, 0.3, 1.0],
+        }
+    }
+}
+
+pub struct Projectile {
+    pub position: WorldPoint,
+    /// px per second.
+    pub velocity: (f64, f64),
+    /// The actor that fired it, which it never hits.
+    pub owner: Option<ActorId>,
+    def: ProjectileDef,
+    age_s: f64,
+    pierce_left: u32,
+    bounces_left: u32,
+    /// Actors it already hit while piercing.
+    hit: Vec<ActorId>,
+    sprite: Option<Box<actor::SpriteChooser>>,
+}
+
+impl Projectile {
+    /// A projectile at `position` flying toward `(dx, dy)` (any length).
+    pub fn new(
+        def: &ProjectileDef,
+        position: WorldPoint,
+        dx: f64,
+        dy: f64,
+        owner: Option<ActorId>,
+    ) -> Self {
+        let length = (dx * dx + dy * dy).sqrt();
+        let velocity = if length > 0.0 {
+            (def.speed * dx / length, def.speed * dy / length)
+        } else {
+            (0.0, 0.0)
+        };
+        Projectile {
+            position: position,
+            velocity: velocity,
+            owner: owner,
+            def: def.clone(),
+            age_s: 0.0,
+            pierce_left: def.pierce,
+            bounces_left: def.bounces,
+            hit: Vec::new(),
+            sprite: def.sprite.as_ref().map(|factory| factory()),
+        }
+    }
+
+    /// Whether the projectile's circle touches `rect` `(x, y, width, height)`.
+    fn touches(&self, rect: [f64; 4]) -> bool {
+        self.position.distance_to_rect(rect) <= self.def.radius
+    }
+}
+
+/// Moves `position` by `(vx, vy) * dt_s`, one axis at a time so that a bounce reverses only
+/// the axis that hit the wall. Returns the new position and velocity, and whether a wall was
+/// hit. `walkable` tells which points are open.
+fn step<F>(
+    position: WorldPoint,
+    velocity: (f64, f64),
+    dt_s: f64,
+    bounce: bool,
+    walkable: F,
+) -> (WorldPoint, (f64, f64), bool)
+where
+    F: Fn(WorldPoint) -> bool,
+{
+    let (mut vx, mut vy) = velocity;
+    let mut position = position;
+    let mut hit_wall = false;
+
+    let proposed = WorldPoint::new(position.x + vx * dt_s, position.y);
+    if walkable(proposed) {
+        position = proposed;
+    } else {
+        hit_wall = true;
+        if bounce {
+            vx = -vx;
+        }
+    }
+    let proposed = WorldPoint::new(position.x, position.y + vy * dt_s);
+    if walkable(proposed) {
+        position = proposed;
+    } else {
+        hit_wall = true;
+        if bounce {
+            vy = -vy;
+        }
+    }
+    (position, (vx, vy), hit_wall)
+}
+
+/// Damage one hit of `power` from `owner` does to `target`.
+fn damage(
+    actors: &Actors,
+    owner: Option<ActorId>,
+    target: &actor::Actor,
+    power: f64,
+    formula: stats::DamageFormula,
+) -> f64 {
+    match owner.and_then(|owner| actors.get(owner)) {
+        Some(owner) => formula(&owner.stats, &target.stats, power),
+        None => formula(&stats::Stats::default(), &target.stats, power),
+    }
+}
+
+/// Every projectile in flight, and explosions still on screen.
+pub struct Projectiles {
+    projectiles: Vec<Projectile>,
+    /// Where explosions happened: (center, radius, age in seconds).
+    blasts: Vec<(WorldPoint, f64, f64)>,
+}
+
+impl Projectiles {
+    pub fn new() -> Self {
+        Projectiles {
+            projectiles: Vec::new(),
+            blasts: Vec::new(),
+        }
+    }
+
+    pub fn spawn(&mut self, projectile: Projectile) {
+        self.projectiles.push(projectile);
+    }
+
+    pub fn len(&self) -> usize {
+        self.projectiles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.projectiles.is_empty()
+    }
+
+    /// Damages every living actor but `owner` within `explosion.radius` of `center`.
+    fn explode(
+        &mut self,
+        center: WorldPoint,
+        explosion: Explosion,
+        owner: Option<ActorId>,
+        actors: &mut Actors,
+        formula: stats::DamageFormula,
+    ) {
+        self.blasts.push((center, explosion.radius, 0.0));
+        let mut hits = Vec::new();
+        for (id, target) in actors.iter() {
+            if Some(id) == owner || target.is_dead() ||
+                center.distance_to_rect(target.hurtbox_rect()) > explosion.radius
+            {
+                continue;
+            }
+            hits.push((id, damage(actors, owner, target, explosion.power, formula)));
+        }
+        for (id, amount) in hits {
+            if let Some(target) = actors.get_mut(id) {
+                target.take_hit(amount, center);
+            }
+        }
+    }
+
+    /// Moves every projectile for `event`, resolving hits on walls and actors.
+    pub fn update<E: GenericEvent>(
+        &mut self,
+        event: &E,
+        world: &world::World,
+        actors: &mut Actors,
+        formula: stats::DamageFormula,
+    ) {
+        if let Some(dt_s) = event.update(|args| args.dt) {
+            let walkable = |point| actor::is_walkable(world, point);
+            self.advance(dt_s, &walkable, actors, formula);
+        }
+    }
+
+    /// Moves every projectile for `dt_s` seconds over the ground that `walkable` describes,
+    /// resolving hits on walls and actors.
+    fn advance(
+        &mut self,
+        dt_s: f64,
+        walkable: &Fn(WorldPoint) -> bool,
+        actors: &mut Actors,
+        formula: stats::DamageFormula,
+    ) {
+        for blast in self.blasts.iter_mut() {
+            blast.2 += dt_s;
+        }
+        self.blasts.retain(|blast| blast.2 < BLAST_TIME_S);
+
+        let mut flying = Vec::new();
+        for mut projectile in mem::replace(&mut self.projectiles, Vec::new()) {
+            projectile.age_s += dt_s;
+            let (position, velocity, hit_wall) = step(
+                projectile.position,
+                projectile.velocity,
+                dt_s,
+                projectile.bounces_left > 0,
+                walkable,
+            );
+            projectile.position = position;
+            projectile.velocity = velocity;
+            let mut stopped = projectile.age_s >= projectile.def.lifetime_s;
+            if hit_wall {
+                if projectile.bounces_left > 0 {
+                    projectile.bounces_left -= 1;
+                } else {
+                    stopped = true;
+                }
+            }
+
+            if !stopped {
+                let mut hits = Vec::new();
+                for (id, target) in actors.iter() {
+                    if Some(id) == projectile.owner || target.is_dead() ||
+                        projectile.hit.contains(&id) ||
+                        !projectile.touches(target.hurtbox_rect())
+                    {
+                        continue;
+                    }
+                    let amount =
+                        damage(actors, projectile.owner, target, projectile.def.power, formula);
+                    hits.push((id, amount));
+                }
+                // Where the projectile came from, for the targets' guards.
+                let source = WorldPoint::new(
+                    projectile.position.x - projectile.velocity.0,
+                    projectile.position.y - projectile.velocity.1,
+                );
+                for (id, amount) in hits {
+                    if stopped {
+                        break;
+                    }
+                    let block = match actors.get_mut(id) {
+                        Some(target) => target.take_hit(amount, source).0,
+                        None => guard::Block::Unguarded,
+                    };
+                    projectile.hit.push(id);
+                    if block != guard::Block::Unguarded {
+                        stopped = true;
+                    } else if projectile.pierce_left > 0 {
+                        projectile.pierce_left -= 1;
+                    } else {
+                        stopped = true;
+                    }
+                }
+            }
+
+            if !stopped {
+                flying.push(projectile);
+            } else if let Some(explosion) = projectile.def.explosion {
+                self.explode(
+                    projectile.position,
+                    explosion,
+                    projectile.owner,
+                    actors,
+                    formula,
+                );
+            }
+        }
+        self.projectiles = flying;
+    }
+
+    pub fn render(
+        &mut self,
+        context: piston_window::Context,
+        renderer: &mut G2d,
+        camera: &Camera,
+        game_time_s: f64,
+    ) {
+        for projectile in self.projectiles.iter_mut() {
+            let screen = camera.to_screen(projectile.position);
+            let (vx, vy) = (projectile.velocity.0 as f32, projectile.velocity.1 as f32);
+            let facing = Facing::from_velocity(Facing::Right, vx, vy, Directions::Eight);
+            let drawn = match projectile.sprite {
+                Some(ref mut sprite) => {
+                    let args = actor::ChooserArgs::new(vx, vy, facing, game_time_s);
+                    match sprite.choose(args) {
+                        Some(sprite) => {
+                            sprite.draw(
+                                context.transform.trans(screen.x, screen.y),
+                                renderer,
+                            );
+                            true
+                        }
+                        None => false,
+                    }
+                }
+                None => false,
+            };
+            if !drawn {
+                let r = projectile.def.radius;
+                piston_window::ellipse(
+                    projectile.def.color,
+                    [screen.x - r, screen.y - r, r * 2.0, r * 2.0],
+                    context.transform,
+                    renderer,
+                );
+            }
+        }
+
+        for &(center, radius, age_s) in self.blasts.iter() {
+            let screen = camera.to_screen(center);
+            let fade = (1.0 - age_s / BLAST_TIME_S) as f32;
+            let r = radius * (0.5 + 0.5 * age_s / BLAST_TIME_S);
+            piston_window::ellipse(
+                [1.0, 0.6, 0.1, 0.6 * fade],
+                [screen.x - r, screen.y - r, r * 2.0, r * 2.0],
+                context.transform,
+                renderer,
+            );
+        }
+    }
+}
+
+/// Fires the projectile of every actor whose animation just emitted `SPAWN_EVENT`, from the
+/// actor's position in the direction it faces. Call after the actors' animations updated.
+pub fn fire_from_events(actors: &Actors, projectiles: &mut Projectiles) {
+    for (id, actor) in actors.iter() {
+        let def = match actor.projectile {
+            Some(ref def) => def,
+            None => continue,
+        };
+        let count = actor.events().iter().filter(|event| *event == SPAWN_EVENT).count();
+        let (dx, dy) = actor.facing.vector();
+        for _ in 0..count {
+            projectiles.spawn(Projectile::new(
+                def,
+                actor.position(),
+                dx as f64,
+                dy as f64,
+                Some(id),
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bounces_off_walls_per_axis() {
+        // A wall along x = 10.
+        let open = |point: WorldPoint| point.x < 10.0;
+        let (position, velocity, hit_wall) =
+            step(WorldPoint::new(8.0, 0.0), (4.0, 2.0), 1.0, true, &open);
+        assert!(hit_wall);
+        assert_eq!(WorldPoint::new(8.0, 2.0), position);
+        assert_eq!((-4.0, 2.0), velocity);
+
+        let (_, velocity, hit_wall) =
+            step(WorldPoint::new(8.0, 0.0), (4.0, 2.0), 1.0, false, &open);
+        assert!(hit_wall);
+        assert_eq!((4.0, 2.0), velocity);
+    }
+
+    fn rock() -> ProjectileDef {
+        ProjectileDef {
+            speed: 100.0,
+            lifetime_s: 10.0,
+            ..ProjectileDef::default()
+        }
+    }
+
+    /// Spawns an actor at `(x, 0)`, returning its ID.
+    fn spawn_at(actors: &mut Actors, x: f32) -> ActorId {
+        let mut actor = actor::Actor::new();
+        actor.x = x;
+        actors.spawn(actor, &[])
+    }
+
+    fn is_hurt(actors: &Actors, id: ActorId) -> bool {
+        actors.get(id).unwrap().stats.hp() < actor::Actor::new().stats.hp()
+    }
+
+    #[test]
+    fn pierces_then_stops() {
+        let mut actors = Actors::new();
+        let ids = [
+            spawn_at(&mut actors, 40.0),
+            spawn_at(&mut actors, 80.0),
+            spawn_at(&mut actors, 120.0),
+        ];
+        let def = ProjectileDef { pierce: 1, ..rock() };
+        let mut projectiles = Projectiles::new();
+        projectiles.spawn(Projectile::new(&def, WorldPoint::new(0.0, 0.0), 1.0, 0.0, None));
+        for _ in 0..20 {
+            projectiles.advance(0.1, &|_| true, &mut actors, stats::default_damage);
+        }
+        assert!(projectiles.is_empty());
+        assert!(is_hurt(&actors, ids[0]));
+        assert!(is_hurt(&actors, ids[1]));
+        assert!(!is_hurt(&actors, ids[2]));
+    }
+
+    #[test]
+    fn bounces_then_stops() {
+        // Walls at x = -10 and x = 10.
+        let open = |point: WorldPoint| point.x.abs() < 10.0;
+        let def = ProjectileDef { bounces: 1, ..rock() };
+        let mut actors = Actors::new();
+        let mut projectiles = Projectiles::new();
+        projectiles.spawn(Projectile::new(&def, WorldPoint::new(5.0, 0.0), 1.0, 0.0, None));
+
+        projectiles.advance(0.1, &open, &mut actors, stats::default_damage);
+        assert_eq!(1, projectiles.len());
+        assert_eq!(-100.0, projectiles.projectiles[0].velocity.0);
+        projectiles.advance(0.1, &open, &mut actors, stats::default_damage);
+        assert_eq!(1, projectiles.len());
+        projectiles.advance(0.1, &open, &mut actors, stats::default_damage);
+        assert!(projectiles.is_empty());
+    }
+
+    #[test]
+    fn explodes_when_time_runs_out() {
+        let mut actors = Actors::new();
+        let (near, far) = (spawn_at(&mut actors, 30.0), spawn_at(&mut actors, 100.0));
+        let def = ProjectileDef {
+            lifetime_s: 0.1,
+            explosion: Some(Explosion {
+                radius: 30.0,
+                power: 10.0,
+            }),
+            ..rock()
+        };
+        let mut projectiles = Projectiles::new();
+        projectiles.spawn(Projectile::new(&def, WorldPoint::new(0.0, 0.0), 0.0, 1.0, None));
+        projectiles.advance(0.1, &|_| true, &mut actors, stats::default_damage);
+        assert!(projectiles.is_empty());
+        assert_eq!(1, projectiles.blasts.len());
+        assert!(is_hurt(&actors, near));
+        assert!(!is_hurt(&actors, far));
+    }
+}
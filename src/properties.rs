@@ -0,0 +1,355 @@
+use std::*;
+use std::collections::HashMap;
+use tiled;
+use xml;
+use xml::reader::XmlEvent;
+
+/// An RGBA color read from a `color` property.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Color {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+    pub alpha: u8,
+}
+
+impl Color {
+    pub fn to_rgba(&self) -> [u8; 4] {
+        [self.red, self.green, self.blue, self.alpha]
+    }
+
+    /// Color as used by piston's drawing functions.
+    pub fn to_f32(&self) -> [f32; 4] {
+        [
+            self.red as f32 / 255.0,
+            self.green as f32 / 255.0,
+            self.blue as f32 / 255.0,
+            self.alpha as f32 / 255.0,
+        ]
+    }
+
+    /// Parses `#rrggbb` or Tiled's `#aarrggbb`.
+    pub fn from_hex(s: &str) -> Option<Self> {
+        let digits = s.trim_left_matches('#');
+        let value = u32::from_str_radix(digits, 16).ok()?;
+        match digits.len() {
+            6 => Some(Color::from_argb(0xff00_0000 | value)),
+            8 => Some(Color::from_argb(value)),
+            _ => None,
+        }
+    }
+
+    fn from_argb(argb: u32) -> Self {
+        Color {
+            red: (argb >> 16) as u8,
+            green: (argb >> 8) as u8,
+            blue: argb as u8,
+            alpha: (argb >> 24) as u8,
+        }
+    }
+}
+
+/// Conversion from a Tiled property value. Conversions are lenient where the intent is clear,
+/// e.g. an int property of `0` reads as `false` and a string `"12"` reads as `12`, since older
+/// maps often leave every property typed as a string.
+pub trait FromProperty: Sized {
+    fn from_property(value: &tiled::PropertyValue) -> Option<Self>;
+}
+
+impl FromProperty for bool {
+    fn from_property(value: &tiled::PropertyValue) -> Option<Self> {
+        Some(match value {
+            &tiled::PropertyValue::BoolValue(b) => b,
+            &tiled::PropertyValue::IntValue(i) => i != 0,
+            &tiled::PropertyValue::FloatValue(f) => f != 0.0,
+            &tiled::PropertyValue::ColorValue(c) => c != 0,
+            &tiled::PropertyValue::StringValue(ref s) => s != "false",
+        })
+    }
+}
+
+impl FromProperty for i32 {
+    fn from_property(value: &tiled::PropertyValue) -> Option<Self> {
+        match value {
+            &tiled::PropertyValue::IntValue(i) => Some(i),
+            &tiled::PropertyValue::FloatValue(f) => Some(f as i32),
+            &tiled::PropertyValue::BoolValue(b) => Some(b as i32),
+            &tiled::PropertyValue::StringValue(ref s) => s.trim().parse().ok(),
+            &tiled::PropertyValue::ColorValue(_) => None,
+        }
+    }
+}
+
+impl FromProperty for f32 {
+    fn from_property(value: &tiled::PropertyValue) -> Option<Self> {
+        match value {
+            &tiled::PropertyValue::FloatValue(f) => Some(f),
+            &tiled::PropertyValue::IntValue(i) => Some(i as f32),
+            &tiled::PropertyValue::StringValue(ref s) => s.trim().parse().ok(),
+            _ => None,
+        }
+    }
+}
+
+impl FromProperty for String {
+    fn from_property(value: &tiled::PropertyValue) -> Option<Self> {
+        Some(match value {
+            &tiled::PropertyValue::StringValue(ref s) => s.clone(),
+            &tiled::PropertyValue::BoolValue(b) => b.to_string(),
+            &tiled::PropertyValue::IntValue(i) => i.to_string(),
+            &tiled::PropertyValue::FloatValue(f) => f.to_string(),
+            &tiled::PropertyValue::ColorValue(c) => format!("#{:08x}", c),
+        })
+    }
+}
+
+impl FromProperty for Color {
+    fn from_property(value: &tiled::PropertyValue) -> Option<Self> {
+        match value {
+            &tiled::PropertyValue::ColorValue(argb) => Some(Color::from_argb(argb)),
+            &tiled::PropertyValue::StringValue(ref s) => Color::from_hex(s),
+            _ => None,
+        }
+    }
+}
+
+/// File paths are returned exactly as written in the map, i.e. relative to the TMX file.
+/// `Tilesheet::path_property` resolves them.
+impl FromProperty for path::PathBuf {
+    fn from_property(value: &tiled::PropertyValue) -> Option<Self> {
+        match value {
+            &tiled::PropertyValue::StringValue(ref s) if !s.is_empty() => {
+                Some(path::PathBuf::from(s))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Property sets searched in order, most specific first, e.g. tile, then tileset, then map.
+/// The first set that has a property decides its value.
+pub struct PropertyChain<'a> {
+    levels: Vec<&'a tiled::Properties>,
+}
+
+impl<'a> PropertyChain<'a> {
+    pub fn new() -> Self {
+        PropertyChain { levels: Vec::new() }
+    }
+
+    /// Appends `properties` as the fallback for everything already in the chain.
+    pub fn then(mut self, properties: &'a tiled::Properties) -> Self {
+        self.levels.push(properties);
+        self
+    }
+
+    pub fn value(&self, name: &str) -> Option<&'a tiled::PropertyValue> {
+        self.levels.iter().filter_map(|level| level.get(name)).next()
+    }
+
+    /// The property `name` converted to `T`, or `None` if it is missing or cannot convert.
+    pub fn get<T: FromProperty>(&self, name: &str) -> Option<T> {
+        self.value(name).and_then(T::from_property)
+    }
+
+    pub fn get_or<T: FromProperty>(&self, name: &str, default: T) -> T {
+        self.get(name).unwrap_or(default)
+    }
+
+    pub fn bool_or(&self, name: &str, default: bool) -> bool {
+        self.get_or(name, default)
+    }
+
+    pub fn int_or(&self, name: &str, default: i32) -> i32 {
+        self.get_or(name, default)
+    }
+
+    pub fn float_or(&self, name: &str, default: f32) -> f32 {
+        self.get_or(name, default)
+    }
+
+    pub fn string_or(&self, name: &str, default: &str) -> String {
+        self.get(name).unwrap_or_else(|| String::from(default))
+    }
+
+    pub fn color_or(&self, name: &str, default: Color) -> Color {
+        self.get_or(name, default)
+    }
+}
+
+fn parse_property_value(kind: &str, value: &str) -> Option<tiled::PropertyValue> {
+    match kind {
+        "bool" => Some(tiled::PropertyValue::BoolValue(value == "true")),
+        "int" => value.parse().ok().map(tiled::PropertyValue::IntValue),
+        "float" => value.parse().ok().map(tiled::PropertyValue::FloatValue),
+        "color" => {
+            u32::from_str_radix(value.trim_left_matches('#'), 16)
+                .ok()
+                .map(tiled::PropertyValue::ColorValue)
+        }
+        _ => Some(tiled::PropertyValue::StringValue(String::from(value))),
+    }
+}
+
+/// Reads the `<properties>` of every `<tileset>` in a TMX document, in tileset order.
+///
+/// The `tiled` crate keeps tile, layer, object and map properties, but drops those set on the
+/// tileset itself, so they are read here in a second pass over the file. External tilesets
+/// (`<tileset source="...">`) are read from their `.tsx` file, relative to `asset_path`; without
+/// an `asset_path`, or if the file cannot be opened, they get no properties.
+pub fn parse_tileset_properties<R: io::Read>(
+    reader: R,
+    asset_path: Option<&path::Path>,
+) -> Result<Vec<tiled::Properties>, xml::reader::Error> {
+    let mut tilesets = Vec::new();
+    let mut path = Vec::<String>::new();
+
+    for event in xml::reader::EventReader::new(reader) {
+        match event? {
+            XmlEvent::StartElement { name, attributes, .. } => {
+                let element = name.local_name;
+                if element == "tileset" {
+                    let source = attributes.iter().find(|a| a.name.local_name == "source");
+                    let external = match (source, asset_path) {
+                        (Some(source), Some(asset_path)) => {
+                            match fs::File::open(asset_path.join(&source.value)) {
+                                Ok(tsx_file) => {
+                                    parse_tileset_properties(io::BufReader::new(tsx_file), None)?
+                                        .pop()
+                                }
+                                Err(_) => None,
+                            }
+                        }
+                        _ => None,
+                    };
+                    tilesets.push(external.unwrap_or_else(HashMap::new));
+                }
+
+                let depth = path.len();
+                let in_tileset_properties = depth >= 2 && path[depth - 1] == "properties" &&
+                    path[depth - 2] == "tileset";
+                if element == "property" && in_tileset_properties {
+                    let attribute = |key: &str| {
+                        attributes
+                            .iter()
+                            .find(|a| a.name.local_name == key)
+                            .map(|a| a.value.as_str())
+                    };
+                    let name = attribute("name").unwrap_or("");
+                    let kind = attribute("type").unwrap_or("string");
+                    let value = attribute("value").unwrap_or("");
+                    if let (Some(properties), Some(value)) =
+                        (tilesets.last_mut(), parse_property_value(kind, value))
+                    {
+                        properties.insert(String::from(name), value);
+                    }
+                }
+                path.push(element);
+            }
+            XmlEvent::EndElement { .. } => {
+                path.pop();
+            }
+            _ => {}
+        }
+    }
+    Ok(tilesets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn properties(values: Vec<(&str, tiled::PropertyValue)>) -> tiled::Properties {
+        values
+            .into_iter()
+            .map(|(name, value)| (String::from(name), value))
+            .collect()
+    }
+
+    #[test]
+    fn lenient_conversions() {
+        let twelve = tiled::PropertyValue::StringValue(String::from("12"));
+        assert_eq!(Some(12), i32::from_property(&twelve));
+        assert_eq!(Some(12.0), f32::from_property(&twelve));
+        assert_eq!(Some(true), bool::from_property(&twelve));
+        assert_eq!(
+            Some(false),
+            bool::from_property(&tiled::PropertyValue::IntValue(0))
+        );
+        assert_eq!(
+            Some(String::from("3")),
+            String::from_property(&tiled::PropertyValue::IntValue(3))
+        );
+    }
+
+    #[test]
+    fn colors() {
+        let expected = Color {
+            red: 0x11,
+            green: 0x22,
+            blue: 0x33,
+            alpha: 0x80,
+        };
+        assert_eq!(
+            Some(expected),
+            Color::from_property(&tiled::PropertyValue::ColorValue(0x8011_2233))
+        );
+        assert_eq!(Some(0xff), Color::from_hex("#112233").map(|c| c.alpha));
+        assert_eq!(None, Color::from_hex("#1234"));
+    }
+
+    #[test]
+    fn chain_falls_back_in_order() {
+        let tile = properties(vec![("speed", tiled::PropertyValue::IntValue(3))]);
+        let map = properties(vec![
+            ("speed", tiled::PropertyValue::IntValue(1)),
+            ("name", tiled::PropertyValue::StringValue(String::from("town"))),
+        ]);
+        let chain = PropertyChain::new().then(&tile).then(&map);
+        assert_eq!(3, chain.int_or("speed", 0));
+        assert_eq!("town", chain.string_or("name", ""));
+        assert_eq!(2.5, chain.float_or("missing", 2.5));
+    }
+
+    #[test]
+    fn reads_only_tileset_level_properties() {
+        let tmx = r#"<map>
+            <properties><property name="m" value="map"/></properties>
+            <tileset firstgid="1" name="a">
+              <properties><property name="level" type="int" value="4"/></properties>
+              <tile id="0">
+                <properties><property name="t" value="tile"/></properties>
+              </tile>
+            </tileset>
+            <tileset firstgid="9" name="b"/>
+          </map>"#;
+        let tilesets = parse_tileset_properties(tmx.as_bytes(), None).unwrap();
+        assert_eq!(2, tilesets.len());
+        assert_eq!(1, tilesets[0].len());
+        assert_eq!(Some(4), tilesets[0].get("level").and_then(i32::from_property));
+        assert!(tilesets[1].is_empty());
+    }
+
+    #[test]
+    fn reads_external_tileset_properties() {
+        let asset_path = env::temp_dir().join("tileset_properties_test");
+        fs::create_dir_all(&asset_path).unwrap();
+        fs::write(
+            asset_path.join("walls.tsx"),
+            r#"<tileset name="walls">
+                 <properties><property name="walkable" type="bool" value="false"/></properties>
+               </tileset>"#,
+        ).unwrap();
+        let tmx = r#"<map>
+            <tileset firstgid="1" source="walls.tsx"/>
+            <tileset firstgid="9" source="missing.tsx"/>
+          </map>"#;
+        let tilesets = parse_tileset_properties(tmx.as_bytes(), Some(&asset_path)).unwrap();
+        assert_eq!(2, tilesets.len());
+        assert_eq!(
+            Some(false),
+            tilesets[0].get("walkable").and_then(bool::from_property)
+        );
+        assert!(tilesets[1].is_empty());
+    }
+}
@@ -0,0 +1,214 @@
+use std::*;
+use std::collections::HashMap;
+use coordinates::WorldPoint;
+use properties;
+use tiled;
+use tilesheet;
+use world;
+
+/// Seconds a roof takes to fade fully out or back in.
+static FADE_TIME_S: f64 = 0.3;
+
+/// Object type of building regions. The hero is inside a building while within one.
+static BUILDING_TYPE: &'static str = "building";
+
+/// Whether `layer` is a roof, i.e. has its `roof` property set. Only the layer's own properties
+/// count; a map-wide `roof` would turn the ground into a roof too.
+fn is_roof_layer(layer: &tiled::Layer) -> bool {
+    properties::PropertyChain::new().then(&layer.properties).bool_or("roof", false)
+}
+
+/// Opacity of a roof layer while the hero is inside, from its `roof_inside_opacity` property.
+fn inside_opacity(layer: &tiled::Layer) -> f64 {
+    properties::PropertyChain::new()
+        .then(&layer.properties)
+        .float_or("roof_inside_opacity", 0.0) as f64
+}
+
+/// Whether `point` lies within the polygon `points`, which are relative to `origin`.
+fn polygon_contains(origin: WorldPoint, points: &Vec<(f32, f32)>, point: WorldPoint) -> bool {
+    // Even-odd rule: count the edges a ray going right from `point` crosses.
+    let (x, y) = (point.x - origin.x, point.y - origin.y);
+    let mut inside = false;
+    let mut j = points.len().wrapping_sub(1);
+    for i in 0..points.len() {
+        let (xi, yi) = (points[i].0 as f64, points[i].1 as f64);
+        let (xj, yj) = (points[j].0 as f64, points[j].1 as f64);
+        if (yi > y) != (yj > y) && x < (xj - xi) * (y - yi) / (yj - yi) + xi {
+            inside = !inside;
+        }
+        j = i;
+    }
+    inside
+}
+
+/// Whether `point` lies within `object`'s area. Polylines have no area; rotation is ignored.
+pub fn object_contains(object: &tiled::Object, point: WorldPoint) -> bool {
+    let origin = WorldPoint::new(object.x as f64, object.y as f64);
+    match object.shape {
+        tiled::ObjectShape::Rect { width, height } => {
+            point.x >= origin.x && point.y >= origin.y && point.x < origin.x + width as f64 &&
+                point.y < origin.y + height as f64
+        }
+        tiled::ObjectShape::Ellipse { width, height } => {
+            let (rx, ry) = (width as f64 * 0.5, height as f64 * 0.5);
+            if rx <= 0.0 || ry <= 0.0 {
+                return false;
+            }
+            let dx = (point.x - origin.x - rx) / rx;
+            let dy = (point.y - origin.y - ry) / ry;
+            dx * dx + dy * dy <= 1.0
+        }
+        tiled::ObjectShape::Polygon { ref points } => polygon_contains(origin, points, point),
+        tiled::ObjectShape::Polyline { .. } => false,
+    }
+}
+
+/// Whether `point` (map-local) is inside a building that roof layer `layer_index` covers, i.e.
+/// within a `"building"` object whose `roof` property names the layer (or is unset, which covers
+/// every roof layer). Merely standing under a roof tile, e.g. behind a house, does not count.
+fn is_inside(tilesheet: &tilesheet::Tilesheet, layer_index: usize, point: WorldPoint) -> bool {
+    let layer = &tilesheet.map().layers[layer_index];
+    tilesheet.map().object_groups.iter().any(|group| {
+        group.objects.iter().any(|object| {
+            object.obj_type == BUILDING_TYPE && object_contains(object, point) && {
+                let roof = tilesheet.object_properties(object).string_or("roof", "");
+                roof.is_empty() || roof == layer.name
+            }
+        })
+    })
+}
+
+/// Fades roof layers out while the hero is inside the building they cover, and back in when
+/// they leave.
+///
+/// A roof layer is any tile layer with the `roof` property set. Its buildings are the
+/// `"building"` objects that name it (see `is_inside`). A whole layer fades at once, so give
+/// each building its own roof layer if only the one the hero enters should open up.
+pub struct Roofs {
+    /// Current opacity factor, keyed by (map placement index, layer index). Missing means 1.
+    alphas: HashMap<(usize, usize), f64>,
+}
+
+impl Roofs {
+    pub fn new() -> Self {
+        Roofs { alphas: HashMap::new() }
+    }
+
+    /// Moves every roof's opacity `dt_s` seconds further toward its target for a hero standing
+    /// at `hero`.
+    pub fn update(&mut self, dt_s: f64, world: &world::World, hero: WorldPoint) {
+        for (map_index, region) in world.regions() {
+            let tilesheet = region.tilesheet();
+            let local = region.to_local(hero);
+            for (layer_index, layer) in tilesheet.map().layers.iter().enumerate() {
+                if !is_roof_layer(layer) {
+                    continue;
+                }
+                let target = if is_inside(tilesheet, layer_index, local) {
+                    inside_opacity(layer)
+                } else {
+                    1.0
+                };
+                self.fade((map_index, layer_index), target, dt_s);
+            }
+        }
+    }
+
+    /// Moves the opacity of roof `key` `dt_s` seconds further toward `target`.
+    fn fade(&mut self, key: (usize, usize), target: f64, dt_s: f64) {
+        let step = dt_s / FADE_TIME_S;
+        let alpha = self.alphas.entry(key).or_insert(1.0);
+        *alpha = if *alpha < target {
+            (*alpha + step).min(target)
+        } else {
+            (*alpha - step).max(target)
+        };
+    }
+
+    /// Opacity factor for layer `layer_index` of the map at placement `map_index`.
+    pub fn alpha(&self, map_index: usize, layer_index: usize) -> f32 {
+        self.alphas.get(&(map_index, layer_index)).cloned().unwrap_or(1.0) as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn object(shape: tiled::ObjectShape) -> tiled::Object {
+        tiled::Object {
+            id: 1,
+            gid: 0,
+            name: String::new(),
+            obj_type: String::from(BUILDING_TYPE),
+            width: 0.0,
+            height: 0.0,
+            x: 10.0,
+            y: 20.0,
+            rotation: 0.0,
+            visible: true,
+            shape: shape,
+            properties: tiled::Properties::new(),
+        }
+    }
+
+    #[test]
+    fn object_areas() {
+        let rect = object(tiled::ObjectShape::Rect {
+            width: 30.0,
+            height: 40.0,
+        });
+        assert!(object_contains(&rect, WorldPoint::new(10.0, 20.0)));
+        assert!(!object_contains(&rect, WorldPoint::new(40.0, 30.0)));
+
+        let ellipse = object(tiled::ObjectShape::Ellipse {
+            width: 20.0,
+            height: 20.0,
+        });
+        assert!(object_contains(&ellipse, WorldPoint::new(20.0, 30.0)));
+        assert!(!object_contains(&ellipse, WorldPoint::new(11.0, 21.0)));
+
+        // An L shape: the notch at its top right is outside.
+        let polygon = object(tiled::ObjectShape::Polygon {
+            points: vec![
+                (0.0, 0.0),
+                (10.0, 0.0),
+                (10.0, 10.0),
+                (20.0, 10.0),
+                (20.0, 20.0),
+                (0.0, 20.0),
+            ],
+        });
+        assert!(object_contains(&polygon, WorldPoint::new(15.0, 25.0)));
+        assert!(object_contains(&polygon, WorldPoint::new(25.0, 35.0)));
+        assert!(!object_contains(&polygon, WorldPoint::new(25.0, 25.0)));
+    }
+
+    #[test]
+    fn only_buildings_count_as_inside() {
+        let demo = tilesheet::demo();
+        let mut map = demo.map().clone();
+        map.object_groups[0].objects.push(object(tiled::ObjectShape::Rect {
+            width: 30.0,
+            height: 40.0,
+        }));
+        let tilesheet = tilesheet::Tilesheet::from_template(&demo, map);
+        // Layer 0 has tiles everywhere, so only the building decides.
+        assert!(is_inside(&tilesheet, 0, WorldPoint::new(20.0, 30.0)));
+        assert!(!is_inside(&tilesheet, 0, WorldPoint::new(100.0, 100.0)));
+    }
+
+    #[test]
+    fn fades_take_fade_time() {
+        let mut roofs = Roofs::new();
+        roofs.fade((0, 1), 0.0, FADE_TIME_S * 0.5);
+        assert!((roofs.alpha(0, 1) - 0.5).abs() < 1e-6);
+        roofs.fade((0, 1), 0.0, FADE_TIME_S);
+        assert_eq!(0.0, roofs.alpha(0, 1));
+        // Fading back in stops at fully opaque.
+        roofs.fade((0, 1), 1.0, FADE_TIME_S * 2.0);
+        assert_eq!(1.0, roofs.alpha(0, 1));
+        assert_eq!(1.0, roofs.alpha(0, 2));
+    }
+}
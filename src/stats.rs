@@ -0,0 +1,315 @@
+//! Character stats: hit points, magic points, attack, defense, speed and level, plus temporary
+//! modifiers (buffs, debuffs, equipment) on top of them.
+
+/// Stats that modifiers can change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Stat {
+    MaxHp,
+    MaxMp,
+    Attack,
+    Defense,
+    /// Walking speed in px per second.
+    Speed,
+}
+
+/// Stats before modifiers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BaseStats {
+    pub max_hp: f64,
+    pub max_mp: f64,
+    pub attack: f64,
+    pub defense: f64,
+    pub speed: f64,
+}
+
+impl BaseStats {
+    fn get(&self, stat: Stat) -> f64 {
+        match stat {
+            Stat::MaxHp => self.max_hp,
+            Stat::MaxMp => self.max_mp,
+            Stat::Attack => self.attack,
+            Stat::Defense => self.defense,
+            Stat::Speed => self.speed,
+        }
+    }
+}
+
+impl Default for BaseStats {
+    fn default() -> Self {
+        BaseStats {
+            max_hp: 100.0,
+            max_mp: 0.0,
+            attack: 10.0,
+            defense: 5.0,
+            speed: 90.0,
+        }
+    }
+}
+
+/// Changes one stat to `(base + add) * multiply`, for `remaining_s` seconds or until removed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Modifier {
+    /// Names the modifier for `Stats::remove_modifiers`, e.g. `"defend"` or `"iron_sword"`.
+    pub name: String,
+    pub stat: Stat,
+    pub add: f64,
+    pub multiply: f64,
+    /// `None` lasts until removed.
+    pub remaining_s: Option<f64>,
+}
+
+impl Modifier {
+    pub fn add(name: &str, stat: Stat, add: f64) -> Self {
+        Modifier {
+            name: String::from(name),
+            stat: stat,
+            add: add,
+            multiply: 1.0,
+            remaining_s: None,
+        }
+    }
+
+    pub fn multiply(name: &str, stat: Stat, multiply: f64) -> Self {
+        Modifier {
+            name: String::from(name),
+            stat: stat,
+            add: 0.0,
+            multiply: multiply,
+            remaining_s: None,
+        }
+    }
+
+    /// Makes the modifier expire after `duration_s` seconds.
+    pub fn lasting(mut self, duration_s: f64) -> Self {
+        self.remaining_s = Some(duration_s);
+        self
+    }
+}
+
+/// Damage dealt by `attacker` to `defender` for an attack of strength `power`, before it is
+/// applied. Games can swap this for their own formula; see `default_damage`.
+pub type DamageFormula = fn(attacker: &Stats, defender: &Stats, power: f64) -> f64;
+
+/// `power` scaled by attack over defense, so equal attack and defense deal `power / 2`, and
+/// at least 1 so every hit counts.
+pub fn default_damage(attacker: &Stats, defender: &Stats, power: f64) -> f64 {
+    let attack = attacker.get(Stat::Attack).max(0.0);
+    let defense = defender.get(Stat::Defense).max(0.0);
+    if attack + defense <= 0.0 {
+        return power.max(1.0);
+    }
+    (power * attack / (attack + defense)).max(1.0)
+}
+
+/// The outcome of `Stats::take_damage`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DamageResult {
+    /// Hit points actually lost.
+    pub dealt: f64,
+    /// Whether this damage killed the character.
+    pub died: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stats {
+    pub base: BaseStats,
+    pub level: u32,
+    hp: f64,
+    mp: f64,
+    modifiers: Vec<Modifier>,
+}
+
+impl Stats {
+    /// Stats at level 1 with full HP and MP.
+    pub fn new(base: BaseStats) -> Self {
+        Stats {
+            hp: base.max_hp,
+            mp: base.max_mp,
+            base: base,
+            level: 1,
+            modifiers: Vec::new(),
+        }
+    }
+
+    /// `stat` with every modifier applied: all additions first, then all multipliers.
+    pub fn get(&self, stat: Stat) -> f64 {
+        let mut add = 0.0;
+        let mut multiply = 1.0;
+        for modifier in self.modifiers.iter().filter(|m| m.stat == stat) {
+            add += modifier.add;
+            multiply *= modifier.multiply;
+        }
+        ((self.base.get(stat) + add) * multiply).max(0.0)
+    }
+
+    pub fn hp(&self) -> f64 {
+        self.hp
+    }
+
+    pub fn mp(&self) -> f64 {
+        self.mp
+    }
+
+    pub fn max_hp(&self) -> f64 {
+        self.get(Stat::MaxHp)
+    }
+
+    pub fn max_mp(&self) -> f64 {
+        self.get(Stat::MaxMp)
+    }
+
+    pub fn speed(&self) -> f64 {
+        self.get(Stat::Speed)
+    }
+
+    pub fn is_dead(&self) -> bool {
+        self.hp <= 0.0
+    }
+
+    pub fn modifiers(&self) -> &[Modifier] {
+        &self.modifiers
+    }
+
+    pub fn add_modifier(&mut self, modifier: Modifier) {
+        self.modifiers.push(modifier);
+        self.clamp();
+    }
+
+    /// Removes every modifier called `name`. Returns whether there were any.
+    pub fn remove_modifiers(&mut self, name: &str) -> bool {
+        let count = self.modifiers.len();
+        self.modifiers.retain(|modifier| modifier.name != name);
+        self.clamp();
+        self.modifiers.len() != count
+    }
+
+    pub fn has_modifier(&self, name: &str) -> bool {
+        self.modifiers.iter().any(|modifier| modifier.name == name)
+    }
+
+    /// Counts down timed modifiers, removing those that ran out.
+    pub fn update(&mut self, dt_s: f64) {
+        for modifier in self.modifiers.iter_mut() {
+            if let Some(ref mut remaining_s) = modifier.remaining_s {
+                *remaining_s -= dt_s;
+            }
+        }
+        self.modifiers.retain(|modifier| match modifier.remaining_s {
+            Some(remaining_s) => remaining_s > 0.0,
+            None => true,
+        });
+        self.clamp();
+    }
+
+    /// Keeps HP and MP within their (possibly just lowered) maximums.
+    fn clamp(&mut self) {
+        self.hp = self.hp.min(self.max_hp());
+        self.mp = self.mp.min(self.max_mp());
+    }
+
+    /// Loses `amount` HP, but no more than are left. The dead take no damage.
+    pub fn take_damage(&mut self, amount: f64) -> DamageResult {
+        if self.is_dead() {
+            return DamageResult {
+                dealt: 0.0,
+                died: false,
+            };
+        }
+        let dealt = amount.max(0.0).min(self.hp);
+        self.hp -= dealt;
+        DamageResult {
+            dealt: dealt,
+            died: self.is_dead(),
+        }
+    }
+
+    /// Regains up to `amount` HP, returning how many. The dead cannot be healed; see `revive`.
+    pub fn heal(&mut self, amount: f64) -> f64 {
+        if self.is_dead() {
+            return 0.0;
+        }
+        let healed = amount.max(0.0).min(self.max_hp() - self.hp);
+        self.hp += healed;
+        healed
+    }
+
+    /// Spends `amount` MP if there are that many. Returns whether it did.
+    pub fn spend_mp(&mut self, amount: f64) -> bool {
+        if amount > self.mp {
+            return false;
+        }
+        self.mp -= amount;
+        true
+    }
+
+    /// Regains up to `amount` MP.
+    pub fn restore_mp(&mut self, amount: f64) {
+        self.mp = (self.mp + amount.max(0.0)).min(self.max_mp());
+    }
+
+    /// Brings the dead back with `hp` HP (at least 1).
+    pub fn revive(&mut self, hp: f64) {
+        if self.is_dead() {
+            self.hp = hp.max(1.0).min(self.max_hp());
+        }
+    }
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Stats::new(BaseStats::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn modifiers() {
+        let mut stats = Stats::default();
+        stats.add_modifier(Modifier::add("sword", Stat::Attack, 5.0));
+        stats.add_modifier(Modifier::multiply("rage", Stat::Attack, 2.0).lasting(1.0));
+        assert_eq!(30.0, stats.get(Stat::Attack));
+
+        stats.update(0.5);
+        assert_eq!(30.0, stats.get(Stat::Attack));
+        stats.update(0.5);
+        assert_eq!(15.0, stats.get(Stat::Attack));
+        assert!(stats.remove_modifiers("sword"));
+        assert_eq!(10.0, stats.get(Stat::Attack));
+
+        // Lowering max HP drags HP down with it.
+        stats.add_modifier(Modifier::multiply("curse", Stat::MaxHp, 0.5));
+        assert_eq!(50.0, stats.hp());
+    }
+
+    #[test]
+    fn damage_healing_and_death() {
+        let attacker = Stats::default();
+        let mut defender = Stats::default();
+        // Attack 10 against defense 5.
+        let damage = default_damage(&attacker, &defender, 30.0);
+        assert_eq!(20.0, damage);
+
+        assert_eq!(40.0, defender.take_damage(40.0).dealt);
+        assert_eq!(30.0, defender.heal(30.0));
+        assert_eq!(90.0, defender.hp());
+        assert_eq!(10.0, defender.heal(100.0));
+
+        let result = defender.take_damage(500.0);
+        assert_eq!(
+            DamageResult {
+                dealt: 100.0,
+                died: true,
+            },
+            result
+        );
+        assert!(defender.is_dead());
+        assert_eq!(0.0, defender.heal(10.0));
+        assert!(!defender.take_damage(10.0).died);
+
+        defender.revive(25.0);
+        assert_eq!(25.0, defender.hp());
+    }
+}
@@ -1,53 +1,617 @@
 use std::*;
+use coordinates::{TileIndex, TileSize, WorldPoint};
 use tiled;
 use image;
+use properties;
+use tmx;
+use xml;
 
 #[derive(Debug)]
 pub enum TilesheetError {
     IoError(io::Error),
     TiledError(tiled::TiledError),
     ImageError(image::ImageError),
+    XmlError(xml::reader::Error),
     NoSuchGid(u32),
+    NoSuchLayer(usize),
+    NoSuchTile(TileIndex),
     NoParentPath,
     NoImages,
+    /// `save` cannot write image layers, and will not silently drop them.
+    HasImageLayers,
 }
 
-pub struct Tilesheet {
-    image: image::RgbaImage,
-    map: tiled::Map,
+/// Per-tile flags precomputed from tile properties when a map is loaded, so hot queries like
+/// `is_walkable` never touch the property maps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TileFlags(u8);
+
+impl TileFlags {
+    /// Set unless the tile's `walkable` property is false.
+    pub const WALKABLE: TileFlags = TileFlags(1 << 0);
+
+    /// Flags of an empty cell (gid 0).
+    pub const EMPTY: TileFlags = TileFlags::WALKABLE;
+
+    pub fn contains(&self, other: TileFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
 }
 
-fn tileset_image(
-    tmx_path: &path::Path,
-    map: &tiled::Map,
-) -> Result<image::DynamicImage, TilesheetError> {
-    let tileset = map.get_tileset_by_gid(1).ok_or(
-        TilesheetError::NoSuchGid(1),
+/// Index of the layer whose `walkable` properties decide where actors and projectiles can go.
+pub static GROUND_LAYER_INDEX: usize = 0;
+
+/// Tiled stores horizontal, vertical and diagonal flips in the top three bits of a gid.
+pub const GID_MASK: u32 = 0x1fff_ffff;
+
+/// Border in px around each tile in the atlas, filled with copies of the tile's edge pixels.
+/// Filtering or rounding that samples slightly outside a tile then picks up the tile's own edge
+/// instead of its neighbour in the atlas.
+pub const ATLAS_PADDING: u32 = 2;
+
+/// Each flag, the property it is read from, and its value when the property is unset.
+static FLAG_PROPERTIES: [(TileFlags, &'static str, bool); 1] =
+    [(TileFlags::WALKABLE, "walkable", true)];
+
+/// How one tileset's tiles are laid out in its image, with the tileset's own tile size, spacing
+/// and margin.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct TileGrid {
+    first_gid: u32,
+    tile_width: u32,
+    tile_height: u32,
+    spacing: u32,
+    margin: u32,
+    columns: u32,
+    rows: u32,
+}
+
+impl TileGrid {
+    /// The grid `tileset` cuts from an image of `width` by `height` px. Pixels left over past
+    /// the last whole tile are ignored, as tiled does.
+    fn new(tileset: &tiled::Tileset, width: u32, height: u32) -> Self {
+        let (spacing, margin) = (tileset.spacing, tileset.margin);
+        let fit = |size: u32, tile: u32| if tile == 0 {
+            0
+        } else {
+            (size.saturating_sub(2 * margin) + spacing) / (tile + spacing)
+        };
+        TileGrid {
+            first_gid: tileset.first_gid,
+            tile_width: tileset.tile_width,
+            tile_height: tileset.tile_height,
+            spacing: spacing,
+            margin: margin,
+            columns: fit(width, tileset.tile_width),
+            rows: fit(height, tileset.tile_height),
+        }
+    }
+
+    fn tile_count(&self) -> u32 {
+        self.columns * self.rows
+    }
+
+    /// Width and height in px of the image this grid exactly covers, margins included.
+    fn image_size(&self) -> (u32, u32) {
+        let span = |count: u32, tile: u32| if count == 0 {
+            0
+        } else {
+            2 * self.margin + count * tile + (count - 1) * self.spacing
+        };
+        (span(self.columns, self.tile_width), span(self.rows, self.tile_height))
+    }
+
+    /// Whether `tile_gid` is one of this tileset's tiles.
+    fn contains(&self, tile_gid: u32) -> bool {
+        tile_gid >= self.first_gid && tile_gid - self.first_gid < self.tile_count()
+    }
+
+    /// Top-left pixel of the tile at `(column, row)` in the tileset image.
+    fn source_origin(&self, column: u32, row: u32) -> (u32, u32) {
+        (
+            self.margin + column * (self.tile_width + self.spacing),
+            self.margin + row * (self.tile_height + self.spacing),
+        )
+    }
+
+    /// Top-left pixel of `tile_gid` in the tileset image. See `contains`.
+    fn gid_origin(&self, tile_gid: u32) -> (u32, u32) {
+        let id = tile_gid - self.first_gid;
+        self.source_origin(id % self.columns, id / self.columns)
+    }
+}
+
+/// Whether an image of `width` by `height` px is cut by `tileset` into a whole number of tiles,
+/// at least one, with nothing left over.
+pub fn image_fits_tileset(tileset: &tiled::Tileset, width: u32, height: u32) -> bool {
+    let grid = TileGrid::new(tileset, width, height);
+    grid.tile_count() > 0 && grid.image_size() == (width, height)
+}
+
+/// Top-left pixel of gid `tile_gid` in an atlas `columns` cells wide, inside the cell's padding.
+/// Each cell holds a `tile_width` by `tile_height` px tile with `ATLAS_PADDING` on every side.
+fn atlas_origin(tile_gid: u32, columns: u32, tile_width: u32, tile_height: u32) -> (u32, u32) {
+    let index = tile_gid - 1; // tiled counts from 1; we want from 0
+    (
+        (index % columns) * (tile_width + 2 * ATLAS_PADDING) + ATLAS_PADDING,
+        (index / columns) * (tile_height + 2 * ATLAS_PADDING) + ATLAS_PADDING,
+    )
+}
+
+/// Copies the tiles of every tileset, cut out of `images` (parallel to `grids`) by each
+/// tileset's own geometry, into one atlas of padded `tile_width` by `tile_height` px cells in gid
+/// order, extruding each tile's edge pixels into its padding.
+fn build_atlas(
+    images: &[image::RgbaImage],
+    grids: &[TileGrid],
+    columns: u32,
+    tile_width: u32,
+    tile_height: u32,
+) -> image::RgbaImage {
+    let last_gid = grids
+        .iter()
+        .map(|grid| grid.first_gid + grid.tile_count() - 1)
+        .max()
+        .unwrap_or(0);
+    let rows = (last_gid + columns - 1) / columns;
+    let cell_width = tile_width + 2 * ATLAS_PADDING;
+    let cell_height = tile_height + 2 * ATLAS_PADDING;
+    let mut atlas = image::RgbaImage::new(columns * cell_width, rows * cell_height);
+    for (image, grid) in images.iter().zip(grids.iter()) {
+        // Tilesets of another tile size are cut to the map's; `validate` reports them.
+        let width = cmp::min(grid.tile_width, tile_width);
+        let height = cmp::min(grid.tile_height, tile_height);
+        if width == 0 || height == 0 {
+            continue;
+        }
+        for gid in grid.first_gid..grid.first_gid + grid.tile_count() {
+            let (source_x, source_y) = grid.gid_origin(gid);
+            let (atlas_x, atlas_y) = atlas_origin(gid, columns, tile_width, tile_height);
+            for y in 0..cell_height {
+                for x in 0..cell_width {
+                    // Clamping the offset into the tile repeats its nearest edge pixel.
+                    let tile_x = cmp::min(x.saturating_sub(ATLAS_PADDING), width - 1);
+                    let tile_y = cmp::min(y.saturating_sub(ATLAS_PADDING), height - 1);
+                    let pixel = *image.get_pixel(source_x + tile_x, source_y + tile_y);
+                    atlas.put_pixel(
+                        atlas_x - ATLAS_PADDING + x,
+                        atlas_y - ATLAS_PADDING + y,
+                        pixel,
+                    );
+                }
+            }
+        }
+    }
+    atlas
+}
+
+/// The `.tsx` file of each tileset of the TMX file at `tmx_path`, in tileset order, or `None`
+/// for tilesets stored in the map itself.
+fn tileset_sources(tmx_path: &path::Path) -> Result<Vec<Option<path::PathBuf>>, TilesheetError> {
+    let asset_path = tmx_path.parent().ok_or(TilesheetError::NoParentPath)?;
+    let tmx_file = fs::File::open(tmx_path).map_err(
+        |e| TilesheetError::IoError(e),
     )?;
+    let sources = tmx::parse_tileset_sources(io::BufReader::new(tmx_file)).map_err(
+        |e| TilesheetError::XmlError(e),
+    )?;
+    Ok(
+        sources
+            .into_iter()
+            .map(|source| source.map(|source| asset_path.join(source)))
+            .collect(),
+    )
+}
 
+/// The first image of every tileset of `map`, loaded from `tmx_path`, in tileset order.
+/// Images of external tilesets are relative to their `.tsx` file, the others to the TMX file.
+pub fn tileset_image_paths(
+    tmx_path: &path::Path,
+    map: &tiled::Map,
+) -> Result<Vec<path::PathBuf>, TilesheetError> {
+    if map.tilesets.is_empty() {
+        return Err(TilesheetError::NoSuchGid(1));
+    }
     let asset_path = tmx_path.parent().ok_or(TilesheetError::NoParentPath)?;
-    let first_image = tileset.images.first().ok_or(TilesheetError::NoImages)?;
-    let tilesheet_path = asset_path.join(&first_image.source);
+    let sources = tileset_sources(tmx_path)?;
+    map.tilesets
+        .iter()
+        .enumerate()
+        .map(|(index, tileset)| {
+            let first_image = tileset.images.first().ok_or(TilesheetError::NoImages)?;
+            let base_path = match sources.get(index) {
+                Some(&Some(ref tsx_path)) => tsx_path.parent().unwrap_or(asset_path),
+                _ => asset_path,
+            };
+            Ok(base_path.join(&first_image.source))
+        })
+        .collect()
+}
+
+/// Loads the images at `image_paths`, in order.
+fn load_images(image_paths: &[path::PathBuf]) -> Result<Vec<image::RgbaImage>, TilesheetError> {
+    image_paths
+        .iter()
+        .map(|image_path| {
+            let image = image::open(image_path).map_err(|e| TilesheetError::ImageError(e))?;
+            Ok(image.to_rgba())
+        })
+        .collect()
+}
+
+/// How a TMX file in `directory` refers to the `.tsx` file at `tsx_path`: relative to it if
+/// the tileset lies within, or else by its absolute path.
+fn tileset_source(tsx_path: &path::Path, directory: &path::Path) -> String {
+    let source = match tsx_path.strip_prefix(directory) {
+        Ok(relative) => relative.to_path_buf(),
+        Err(_) => fs::canonicalize(tsx_path).unwrap_or_else(|_| tsx_path.to_path_buf()),
+    };
+    source.to_string_lossy().into_owned()
+}
+
+pub struct Tilesheet {
+    /// The first tileset's image as loaded.
+    image: image::RgbaImage,
+
+    /// The tiles of every tileset in gid order, `atlas_columns` per row, without spacing or
+    /// margins, each surrounded by `ATLAS_PADDING` px of extruded edge pixels. `tile_rect`
+    /// points into this.
+    atlas: image::RgbaImage,
+    atlas_columns: u32,
+
+    /// Each tileset's layout, parallel to `map.tilesets`.
+    grids: Vec<TileGrid>,
 
-    image::open(&tilesheet_path).map_err(|e| TilesheetError::ImageError(e))
+    map: tiled::Map,
+    tmx_path: Option<path::PathBuf>,
+
+    /// Properties set on each tileset, parallel to `map.tilesets`.
+    tileset_properties: Vec<tiled::Properties>,
+
+    /// The `.tsx` file of each external tileset, parallel to `map.tilesets`.
+    tileset_sources: Vec<Option<path::PathBuf>>,
+
+    /// Incremented on every tile edit, so cached renderings know when to rebuild.
+    revision: u64,
+
+    /// `TileFlags` of every gid in use, indexed by gid.
+    gid_flags: Vec<TileFlags>,
+
+    /// `TileFlags` of every cell, per layer, row-major (`y * map.width + x`).
+    layer_flags: Vec<Vec<TileFlags>>,
 }
 
 impl Tilesheet {
+    /// Builds a tilesheet from `map` and the first image of each of its tilesets.
+    fn new(
+        map: tiled::Map,
+        images: Vec<image::RgbaImage>,
+        tmx_path: Option<path::PathBuf>,
+        tileset_properties: Vec<tiled::Properties>,
+        tileset_sources: Vec<Option<path::PathBuf>>,
+    ) -> Self {
+        let grids: Vec<TileGrid> = map.tilesets
+            .iter()
+            .zip(images.iter())
+            .map(|(tileset, image)| TileGrid::new(tileset, image.width(), image.height()))
+            .collect();
+        let atlas_columns = cmp::max(grids.first().map(|grid| grid.columns).unwrap_or(1), 1);
+        let atlas = build_atlas(&images, &grids, atlas_columns, map.tile_width, map.tile_height);
+        let mut tilesheet = Tilesheet {
+            image: images.into_iter().next().unwrap_or_else(|| image::RgbaImage::new(0, 0)),
+            atlas: atlas,
+            atlas_columns: atlas_columns,
+            grids: grids,
+            map: map,
+            tmx_path: tmx_path,
+            tileset_properties: tileset_properties,
+            tileset_sources: tileset_sources,
+            revision: 0,
+            gid_flags: Vec::new(),
+            layer_flags: Vec::new(),
+        };
+        tilesheet.build_flags();
+        tilesheet
+    }
+
     pub fn from_path(tmx_path: &path::Path) -> Result<Self, TilesheetError> {
+        // Parsing from the path lets `tiled` find external tilesets.
+        let map = tiled::parse_file(tmx_path).map_err(
+            |e| TilesheetError::TiledError(e),
+        )?;
+
+        let images = load_images(&tileset_image_paths(tmx_path, &map)?)?;
+        let tileset_sources = tileset_sources(tmx_path)?;
+
         let tmx_file = fs::File::open(tmx_path).map_err(
             |e| TilesheetError::IoError(e),
         )?;
+        let asset_path = tmx_path.parent().ok_or(TilesheetError::NoParentPath)?;
+        let tileset_properties =
+            properties::parse_tileset_properties(io::BufReader::new(tmx_file), Some(asset_path))
+                .map_err(|e| TilesheetError::XmlError(e))?;
 
-        let map = tiled::parse(tmx_file).map_err(
-            |e| TilesheetError::TiledError(e),
-        )?;
+        Ok(Tilesheet::new(
+            map,
+            images,
+            Some(tmx_path.to_path_buf()),
+            tileset_properties,
+            tileset_sources,
+        ))
+    }
 
-        let image = tileset_image(tmx_path, &map)?;
+    /// Wraps an in-memory `map` whose tilesets' images are `images`, in tileset order.
+    pub fn from_map(map: tiled::Map, images: Vec<image::RgbaImage>) -> Self {
+        let tileset_properties = map.tilesets.iter().map(|_| tiled::Properties::new()).collect();
+        let tileset_sources = map.tilesets.iter().map(|_| None).collect();
+        Tilesheet::new(map, images, None, tileset_properties, tileset_sources)
+    }
 
-        Ok(Tilesheet {
-            image: image.to_rgba(),
+    /// Wraps an in-memory `map` (e.g. one built by `generator`) that uses the same tilesets as
+    /// `template`, so it shares the template's image and tileset properties.
+    pub fn from_template(template: &Tilesheet, map: tiled::Map) -> Self {
+        let mut tilesheet = Tilesheet {
+            image: template.image.clone(),
+            atlas: template.atlas.clone(),
+            atlas_columns: template.atlas_columns,
+            grids: template.grids.clone(),
             map: map,
-        })
+            tmx_path: None,
+            tileset_properties: template.tileset_properties.clone(),
+            tileset_sources: template.tileset_sources.clone(),
+            revision: 0,
+            gid_flags: Vec::new(),
+            layer_flags: Vec::new(),
+        };
+        tilesheet.build_flags();
+        tilesheet
+    }
+
+    fn flags_from_properties(&self, tile_gid: u32) -> TileFlags {
+        let properties = self.tile_properties(tile_gid);
+        let mut flags = TileFlags(0);
+        for &(flag, name, default) in FLAG_PROPERTIES.iter() {
+            if properties.bool_or(name, default) {
+                flags.0 |= flag.0;
+            }
+        }
+        flags
+    }
+
+    /// Precomputes `gid_flags` and `layer_flags`. Cells with gids past `tile_count` get
+    /// `TileFlags::EMPTY`; `validate` reports them.
+    fn build_flags(&mut self) {
+        let mut gid_flags = vec![TileFlags::EMPTY];
+        for gid in 1..self.tile_count() + 1 {
+            gid_flags.push(self.flags_from_properties(gid));
+        }
+        self.gid_flags = gid_flags;
+
+        let width = self.map.width as usize;
+        let layer_flags = self.map
+            .layers
+            .iter()
+            .map(|layer| {
+                let mut flags = vec![TileFlags::EMPTY; width * self.map.height as usize];
+                for (y, row) in layer.tiles.iter().enumerate() {
+                    for (x, &gid) in row.iter().enumerate().take(width) {
+                        if let Some(cell) = flags.get_mut(y * width + x) {
+                            *cell = self.gid_flags(gid);
+                        }
+                    }
+                }
+                flags
+            })
+            .collect();
+
+        self.layer_flags = layer_flags;
+    }
+
+    /// The flags of the tile at `index`, or `None` if out of bounds.
+    pub fn flags(&self, layer_index: usize, index: TileIndex) -> Option<TileFlags> {
+        let (x, y) = index.to_usize()?;
+        if x >= self.map.width as usize {
+            return None;
+        }
+        let layer = self.layer_flags.get(layer_index)?;
+        layer.get(y * self.map.width as usize + x).cloned()
+    }
+
+    /// The flags of every tile with gid `tile_gid`, flipped or not. Unknown gids have
+    /// `TileFlags::EMPTY`.
+    pub fn gid_flags(&self, tile_gid: u32) -> TileFlags {
+        self.gid_flags
+            .get((tile_gid & GID_MASK) as usize)
+            .cloned()
+            .unwrap_or(TileFlags::EMPTY)
+    }
+
+    /// The path this tilesheet was loaded from, or `None` if it was built in memory.
+    pub fn tmx_path(&self) -> Option<&path::Path> {
+        self.tmx_path.as_ref().map(|p| p.as_path())
+    }
+
+    pub fn map(&self) -> &tiled::Map {
+        &self.map
+    }
+
+    /// Properties set on the map itself.
+    pub fn map_properties(&self) -> properties::PropertyChain {
+        properties::PropertyChain::new().then(&self.map.properties)
+    }
+
+    /// Properties of layer `layer_index`, falling back to the map's.
+    pub fn layer_properties(&self, layer_index: usize) -> properties::PropertyChain {
+        let chain = properties::PropertyChain::new();
+        let chain = match self.map.layers.get(layer_index) {
+            Some(layer) => chain.then(&layer.properties),
+            None => chain,
+        };
+        chain.then(&self.map.properties)
+    }
+
+    /// Properties of `object`, falling back to the map's.
+    pub fn object_properties<'a>(
+        &'a self,
+        object: &'a tiled::Object,
+    ) -> properties::PropertyChain<'a> {
+        properties::PropertyChain::new()
+            .then(&object.properties)
+            .then(&self.map.properties)
+    }
+
+    /// Properties of the tileset containing `tile_gid`, falling back to the map's.
+    pub fn tileset_properties(&self, tile_gid: u32) -> properties::PropertyChain {
+        let chain = properties::PropertyChain::new();
+        let chain = match self.tileset_index(tile_gid) {
+            Some(index) => chain.then(&self.tileset_properties[index]),
+            None => chain,
+        };
+        chain.then(&self.map.properties)
+    }
+
+    /// Properties of tile `tile_gid`, falling back to its tileset's, then to the map's.
+    pub fn tile_properties(&self, tile_gid: u32) -> properties::PropertyChain {
+        let chain = properties::PropertyChain::new();
+        let chain = match self.tile(tile_gid) {
+            Some(tile) => chain.then(&tile.properties),
+            None => chain,
+        };
+        let chain = match self.tileset_index(tile_gid) {
+            Some(index) => chain.then(&self.tileset_properties[index]),
+            None => chain,
+        };
+        chain.then(&self.map.properties)
+    }
+
+    /// `relative` resolved against the directory of the TMX file, if there is one.
+    fn resolve_path(&self, relative: &path::Path) -> path::PathBuf {
+        match self.tmx_path.as_ref().and_then(|p| p.parent()) {
+            Some(asset_path) => asset_path.join(relative),
+            None => relative.to_path_buf(),
+        }
+    }
+
+    /// Resolves a file path property, which Tiled stores relative to the TMX file. Paths set
+    /// in external `.tsx` tilesets are resolved the same way, so keep those beside their maps.
+    pub fn path_property(
+        &self,
+        chain: &properties::PropertyChain,
+        name: &str,
+    ) -> Option<path::PathBuf> {
+        let relative: path::PathBuf = chain.get(name)?;
+        Some(self.resolve_path(&relative))
+    }
+
+    /// Like `path_property`, with `default` (also relative to the TMX file) if it is unset.
+    pub fn path_property_or(
+        &self,
+        chain: &properties::PropertyChain,
+        name: &str,
+        default: &path::Path,
+    ) -> path::PathBuf {
+        match self.path_property(chain, name) {
+            Some(path) => path,
+            None => self.resolve_path(default),
+        }
+    }
+
+    fn tileset_index(&self, tile_gid: u32) -> Option<usize> {
+        let tile_gid = tile_gid & GID_MASK;
+        if tile_gid == 0 {
+            return None; // tiled counts from 1; 0 is empty
+        }
+        self.map
+            .tilesets
+            .iter()
+            .rposition(|tileset| tileset.first_gid <= tile_gid)
+            .filter(|&index| index < self.tileset_properties.len())
+    }
+
+    /// Changes whenever a tile is edited.
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// Writes the map, including any runtime tile edits, as a TMX file at `tmx_path`. Every
+    /// layer is written as CSV. External tilesets stay in their `.tsx` files, referred to from
+    /// wherever the map is saved. Maps with image layers are refused, as those would be lost.
+    pub fn save(&self, tmx_path: &path::Path) -> Result<(), TilesheetError> {
+        if !self.map.image_layers.is_empty() {
+            return Err(TilesheetError::HasImageLayers);
+        }
+        let directory = tmx_path.parent().unwrap_or(path::Path::new(""));
+        let tileset_sources = self.tileset_sources
+            .iter()
+            .map(|tsx_path| {
+                tsx_path.as_ref().map(|tsx_path| tileset_source(tsx_path, directory))
+            })
+            .collect();
+        let mut tmx_file = io::BufWriter::new(fs::File::create(tmx_path).map_err(
+            |e| TilesheetError::IoError(e),
+        )?);
+        tmx::write_map(
+            &mut tmx_file,
+            &self.map,
+            &self.tileset_properties,
+            &tileset_sources,
+        ).map_err(|e| TilesheetError::IoError(e))
+    }
+
+    pub fn layer_count(&self) -> usize {
+        self.map.layers.len()
+    }
+
+    /// Whether some tileset provides `tile_gid`, ignoring its flip bits. Gid 0 is empty, not a
+    /// tile.
+    pub fn contains_gid(&self, tile_gid: u32) -> bool {
+        let tile_gid = tile_gid & GID_MASK;
+        self.grids.iter().any(|grid| grid.contains(tile_gid))
+    }
+
+    /// The highest gid of any tileset; gids `1..=tile_count()` are valid, except in gaps
+    /// Tiled leaves between tilesets, which are empty.
+    pub fn tile_count(&self) -> u32 {
+        self.grids
+            .iter()
+            .map(|grid| grid.first_gid + grid.tile_count() - 1)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Returns the gid at `index`, or `None` if out of bounds.
+    pub fn tile_gid(&self, layer_index: usize, index: TileIndex) -> Option<u32> {
+        let (x, y) = index.to_usize()?;
+        let layer = self.map.layers.get(layer_index)?;
+        let row = layer.tiles.get(y)?;
+        row.get(x).cloned()
+    }
+
+    /// Replaces the gid at `index`. A `gid` of 0 erases the tile.
+    pub fn set_tile_gid(
+        &mut self,
+        layer_index: usize,
+        index: TileIndex,
+        gid: u32,
+    ) -> Result<(), TilesheetError> {
+        if gid > self.tile_count() {
+            return Err(TilesheetError::NoSuchGid(gid));
+        }
+        let (x, y) = index.to_usize().ok_or(TilesheetError::NoSuchTile(index))?;
+        let layer = self.map.layers.get_mut(layer_index).ok_or(
+            TilesheetError::NoSuchLayer(layer_index),
+        )?;
+        let cell = layer.tiles.get_mut(y).and_then(|row| row.get_mut(x)).ok_or(
+            TilesheetError::NoSuchTile(index),
+        )?;
+        *cell = gid;
+        let width = self.map.width as usize;
+        let flags = self.gid_flags(gid);
+        if let Some(cell) = self.layer_flags[layer_index].get_mut(y * width + x) {
+            *cell = flags;
+        }
+        self.revision += 1;
+        Ok(())
     }
 
     pub fn layer_tile_iter(&self, layer_number: usize) -> slice::Iter<Vec<u32>> {
@@ -68,10 +632,16 @@ impl Tilesheet {
         ]
     }
 
+    /// The first tileset's image as loaded. Use `atlas` with `tile_rect` to draw tiles.
     pub fn image(&self) -> &image::RgbaImage {
         &self.image
     }
 
+    /// The atlas of every tileset's tiles that `tile_rect` refers to.
+    pub fn atlas(&self) -> &image::RgbaImage {
+        &self.atlas
+    }
+
     pub fn tile_width(&self) -> u32 {
         self.map.tile_width
     }
@@ -80,69 +650,255 @@ impl Tilesheet {
         self.map.tile_height
     }
 
-    fn map_width_in_tiles(&self) -> u32 {
-        self.image.width() / self.tile_width()
+    pub fn tile_size(&self) -> TileSize {
+        TileSize {
+            width: self.tile_width() as f64,
+            height: self.tile_height() as f64,
+        }
     }
 
     /// Returns an array of 4 floats, designating `tile`s boundaries `(x,y,width,height)` within
-    /// the tileset image. Used to render a tile.
+    /// the atlas, inside its padding. Used to render a tile. Flip bits are ignored; returns
+    /// `None` for gid 0 and for gids no tileset provides.
     /// Note: This is NOT in map coordinates.
-    pub fn tile_rect(&self, tile: u32) -> [f64; 4] {
-        let tile = tile - 1; // tiled counts from 1; we want from 0
-        let x = (tile % self.map_width_in_tiles() * self.tile_width()) as f64;
-        let y = (tile / self.map_width_in_tiles() * self.tile_height()) as f64;
-        [x, y, self.tile_width() as f64, self.tile_height() as f64]
+    pub fn tile_rect(&self, tile: u32) -> Option<[f64; 4]> {
+        if !self.contains_gid(tile) {
+            return None;
+        }
+        let (width, height) = (self.tile_width(), self.tile_height());
+        let (x, y) = atlas_origin(tile & GID_MASK, self.atlas_columns, width, height);
+        Some([x as f64, y as f64, width as f64, height as f64])
     }
 
-    fn tile_id_from_map_coordinate(&self, layer_index: usize, x: f32, y: f32) -> Option<u32> {
-        let layer = self.map.layers.get(layer_index)?;
-        let row_index = (y / self.tile_height() as f32).round() as usize;
-        let row = layer.tiles.get(row_index)?;
-        let col_index = (x / self.tile_width() as f32).round() as usize;
-        match row.get(col_index) {
-            Some(value) => Some(*value),
-            None => None,
-        }
-    }
-
-    /// Robustification helper that converts any valid PropertyValue into a bool.
-    fn bool_property(value: &tiled::PropertyValue) -> bool {
-        match value {
-            &tiled::PropertyValue::BoolValue(b) => b,
-            &tiled::PropertyValue::IntValue(i) => i != 0,
-            &tiled::PropertyValue::FloatValue(f) => f != 0.0,
-            &tiled::PropertyValue::ColorValue(c) => c != 0,
-            &tiled::PropertyValue::StringValue(ref s) => s != "false",
-        }
-    }
-
-    pub fn is_walkable(&self, layer_index: usize, x: f32, y: f32) -> bool {
-        // TODO BUG property lookup seems to have an off-by-one error in (x,y)
-        // get tile ID for map(x,y)
-        // https://github.com/jakalope/roll_play_ge/issues/2
-        let tile_gid = match self.tile_id_from_map_coordinate(layer_index, x, y) {
-            Some(t) => t,
-            None => {
-                return true;
+    /// The gid of the atlas cell covering pixel `(x, y)`, padding included, or `None` if no
+    /// tile does. The editor palette shows the atlas, so every tileset's tiles can be picked.
+    pub fn gid_at_atlas_pixel(&self, x: u32, y: u32) -> Option<u32> {
+        let column = x / (self.tile_width() + 2 * ATLAS_PADDING);
+        let row = y / (self.tile_height() + 2 * ATLAS_PADDING);
+        if column >= self.atlas_columns {
+            return None;
+        }
+        let gid = row * self.atlas_columns + column + 1;
+        if self.grids.iter().any(|grid| grid.contains(gid)) {
+            Some(gid)
+        } else {
+            None
+        }
+    }
+
+    /// The tileset entry for `tile_gid`, which holds its properties.
+    /// Returns `None` if the tileset defines nothing for that tile.
+    pub fn tile(&self, tile_gid: u32) -> Option<&tiled::Tile> {
+        let tileset = &self.map.tilesets[self.tileset_index(tile_gid)?];
+        let id = (tile_gid & GID_MASK) - tileset.first_gid;
+        tileset.tiles.iter().find(|tile| tile.id == id)
+    }
+
+    /// Whether the tile under `position` can be walked on. Off-map positions are walkable.
+    pub fn is_walkable(&self, layer_index: usize, position: WorldPoint) -> bool {
+        match self.flags(layer_index, position.to_tile(self.tile_size())) {
+            Some(flags) => flags.contains(TileFlags::WALKABLE),
+            None => true,
+        }
+    }
+
+    /// Whether tiles with gid `tile_gid` have their `walkable` property set (or unset).
+    pub fn is_gid_walkable(&self, tile_gid: u32) -> bool {
+        self.gid_flags(tile_gid).contains(TileFlags::WALKABLE)
+    }
+}
+
+/// The demo map that tests across the crate load.
+#[cfg(test)]
+pub fn demo() -> Tilesheet {
+    Tilesheet::from_path(path::Path::new("assets/tiled_base64_zlib.tmx")).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn walkable_uses_tile_id_not_gid() {
+        // gid 5 is tile id 4 (not walkable); gid 6 is tile id 5 (walkable).
+        let tilesheet = demo();
+        assert!(!tilesheet.is_gid_walkable(5));
+        assert!(tilesheet.is_gid_walkable(6));
+        assert!(tilesheet.is_gid_walkable(0));
+    }
+
+    #[test]
+    fn flags_follow_tile_edits() {
+        let mut tilesheet = demo();
+        let index = TileIndex::new(3, 2);
+        let center = index.center(tilesheet.tile_size());
+
+        tilesheet.set_tile_gid(0, index, 5).unwrap();
+        assert!(!tilesheet.is_walkable(0, center));
+        tilesheet.set_tile_gid(0, index, 6).unwrap();
+        assert!(tilesheet.is_walkable(0, center));
+
+        // Off the map is open.
+        assert!(tilesheet.is_walkable(0, WorldPoint::new(-1.0, -1.0)));
+    }
+
+    #[test]
+    fn unknown_and_flipped_gids() {
+        let demo = demo();
+        let mut map = demo.map().clone();
+        map.layers[0].tiles[0][0] = u32::MAX;
+        map.layers[0].tiles[0][1] = demo.tile_count() + 1;
+        // Gid 5, flipped horizontally.
+        map.layers[0].tiles[0][2] = 0x8000_0005;
+        let tilesheet = Tilesheet::from_template(&demo, map);
+
+        let size = tilesheet.tile_size();
+        assert!(tilesheet.is_walkable(0, TileIndex::new(0, 0).center(size)));
+        assert!(tilesheet.is_walkable(0, TileIndex::new(1, 0).center(size)));
+        assert!(!tilesheet.is_walkable(0, TileIndex::new(2, 0).center(size)));
+        assert_eq!(TileFlags::EMPTY, tilesheet.gid_flags(u32::MAX - 8));
+    }
+
+    #[test]
+    fn tilesets_keep_their_own_geometry() {
+        let red = image::Rgba { data: [255, 0, 0, 255] };
+        let blue = image::Rgba { data: [0, 0, 255, 255] };
+        let green = image::Rgba { data: [0, 255, 0, 255] };
+        let fill = |image: &mut image::RgbaImage, x0: u32, y0: u32, color| {
+            for y in 0..2 {
+                for x in 0..2 {
+                    image.put_pixel(x0 + x, y0 + y, color);
+                }
             }
         };
-        // get the tileset index
-        let tileset = match self.map.get_tileset_by_gid(tile_gid) {
-            Some(value) => value,
-            None => {
-                return true;
-            }
+        // Two 2x2 tiles side by side: red and blue.
+        let mut first = image::RgbaImage::new(4, 2);
+        fill(&mut first, 0, 0, red);
+        fill(&mut first, 2, 0, blue);
+        // Two 2x2 tiles with a 1 px margin and 1 px of spacing: green and blue.
+        let mut second = image::RgbaImage::new(7, 4);
+        fill(&mut second, 1, 1, green);
+        fill(&mut second, 4, 1, blue);
+
+        let mut map = demo().map().clone();
+        map.tile_width = 2;
+        map.tile_height = 2;
+        map.tilesets.truncate(1);
+        map.tilesets[0].tile_width = 2;
+        map.tilesets[0].tile_height = 2;
+        let mut tileset = map.tilesets[0].clone();
+        tileset.first_gid = 3;
+        tileset.spacing = 1;
+        tileset.margin = 1;
+        map.tilesets.push(tileset);
+        let tilesheet = Tilesheet::from_map(map, vec![first, second]);
+
+        assert_eq!(4, tilesheet.tile_count());
+        let pixel = |gid: u32| {
+            let rect = tilesheet.tile_rect(gid).unwrap();
+            *tilesheet.atlas().get_pixel(rect[0] as u32 + 1, rect[1] as u32 + 1)
         };
-        // get property for the tile ID
-        let tile = match tileset.tiles.get(tile_gid as usize) {
-            Some(t) => t,
-            None => {
-                return true;
+        assert_eq!(red, pixel(1));
+        assert_eq!(blue, pixel(2));
+        assert_eq!(green, pixel(3));
+        assert_eq!(blue, pixel(4));
+    }
+
+    #[test]
+    fn tile_rects_point_into_atlas() {
+        let tilesheet = demo();
+        let gid = tilesheet.tile_count();
+        let rect = tilesheet.tile_rect(gid).unwrap();
+        let (x, y) = (rect[0] as u32, rect[1] as u32);
+        // The demo tileset has no margin or spacing.
+        let (tile_width, tile_height) = (tilesheet.tile_width(), tilesheet.tile_height());
+        let columns = tilesheet.image().width() / tile_width;
+        let index = gid - 1; // tiled counts from 1; we want from 0
+        let (source_x, source_y) = (index % columns * tile_width, index / columns * tile_height);
+        assert_eq!(
+            tilesheet.image().get_pixel(source_x, source_y),
+            tilesheet.atlas().get_pixel(x, y)
+        );
+        assert_eq!(Some(gid), tilesheet.gid_at_atlas_pixel(x, y));
+        assert_eq!(None, tilesheet.gid_at_atlas_pixel(tilesheet.atlas().width(), 0));
+
+        assert_eq!(Some(rect), tilesheet.tile_rect(gid | 0x8000_0000));
+        assert_eq!(None, tilesheet.tile_rect(0));
+        assert_eq!(None, tilesheet.tile_rect(gid + 1));
+        assert!(tilesheet.tile(0).is_none());
+        assert_eq!(None, tilesheet.tileset_index(0x8000_0000));
+    }
+
+    #[test]
+    fn atlas_extrudes_tile_edges() {
+        // Two 2x2 tiles side by side with one px of spacing: red and blue.
+        let red = image::Rgba { data: [255, 0, 0, 255] };
+        let blue = image::Rgba { data: [0, 0, 255, 255] };
+        let mut image = image::RgbaImage::new(5, 2);
+        for y in 0..2 {
+            for x in 0..2 {
+                image.put_pixel(x, y, red);
+                image.put_pixel(x + 3, y, blue);
             }
+        }
+        let grid = TileGrid {
+            first_gid: 1,
+            tile_width: 2,
+            tile_height: 2,
+            spacing: 1,
+            margin: 0,
+            columns: 2,
+            rows: 1,
         };
-        match tile.properties.get("walkable") {
-            Some(walkable) => Tilesheet::bool_property(walkable),
-            None => true,  // assume walkable if no such property is set
+        let atlas = build_atlas(&[image], &[grid], 2, 2, 2);
+        let cell = 2 + 2 * ATLAS_PADDING;
+        assert_eq!((2 * cell, cell), atlas.dimensions());
+        assert_eq!((ATLAS_PADDING, ATLAS_PADDING), atlas_origin(1, 2, 2, 2));
+        assert_eq!((cell + ATLAS_PADDING, ATLAS_PADDING), atlas_origin(2, 2, 2, 2));
+
+        // Every pixel of each cell, padding included, is its tile's color; nothing bleeds over.
+        for y in 0..cell {
+            for x in 0..cell {
+                assert_eq!(red, *atlas.get_pixel(x, y));
+                assert_eq!(blue, *atlas.get_pixel(cell + x, y));
+            }
+        }
+    }
+
+    #[test]
+    fn saves_external_tilesets_as_references() {
+        let directory = env::temp_dir().join("external_tileset_test");
+        let tilesets = directory.join("tilesets");
+        fs::create_dir_all(&tilesets).unwrap();
+        fs::create_dir_all(directory.join("out")).unwrap();
+        fs::copy("assets/tilesheet.png", tilesets.join("tilesheet.png")).unwrap();
+        fs::write(
+            tilesets.join("ground.tsx"),
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<tileset name="ground" tilewidth="32" tileheight="32">
+ <properties><property name="walkable" type="bool" value="false"/></properties>
+ <image source="tilesheet.png" width="448" height="192"/>
+</tileset>"#,
+        ).unwrap();
+        fs::write(
+            directory.join("map.tmx"),
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<map version="1.0" orientation="orthogonal" width="2" height="1" tilewidth="32" tileheight="32">
+ <tileset firstgid="1" source="tilesets/ground.tsx"/>
+ <layer name="ground" width="2" height="1"><data encoding="csv">1,2</data></layer>
+</map>"#,
+        ).unwrap();
+
+        let mut tilesheet = Tilesheet::from_path(&directory.join("map.tmx")).unwrap();
+        tilesheet.set_tile_gid(0, TileIndex::new(1, 0), 3).unwrap();
+        // Beside the original, and in another directory.
+        let saved = [directory.join("map.edited.tmx"), directory.join("out/map.tmx")];
+        for tmx_path in saved.iter() {
+            tilesheet.save(tmx_path).unwrap();
+            let reloaded = Tilesheet::from_path(tmx_path).unwrap();
+            assert_eq!(vec![vec![1, 3]], reloaded.map().layers[0].tiles);
+            assert_eq!(tilesheet.image().get_pixel(0, 0), reloaded.image().get_pixel(0, 0));
+            assert!(!reloaded.is_gid_walkable(1));
         }
     }
 }
@@ -0,0 +1,314 @@
+use std::*;
+use std::io::Write;
+use tiled;
+use xml;
+use xml::reader::XmlEvent;
+
+/// Escapes the characters XML treats specially inside attribute values.
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn orientation_name(orientation: &tiled::Orientation) -> &'static str {
+    match orientation {
+        &tiled::Orientation::Orthogonal => "orthogonal",
+        &tiled::Orientation::Isometric => "isometric",
+        &tiled::Orientation::Staggered => "staggered",
+        &tiled::Orientation::Hexagonal => "hexagonal",
+    }
+}
+
+fn colour_hex(colour: &tiled::Colour) -> String {
+    format!("#{:02x}{:02x}{:02x}", colour.red, colour.green, colour.blue)
+}
+
+fn write_properties<W: Write>(
+    w: &mut W,
+    indent: &str,
+    properties: &tiled::Properties,
+) -> io::Result<()> {
+    if properties.is_empty() {
+        return Ok(());
+    }
+
+    // Sort by name so that saving the same map twice produces the same file.
+    let mut names: Vec<&String> = properties.keys().collect();
+    names.sort();
+
+    writeln!(w, "{}<properties>", indent)?;
+    for name in names {
+        let (kind, value) = match properties[name] {
+            tiled::PropertyValue::BoolValue(b) => ("bool", b.to_string()),
+            tiled::PropertyValue::FloatValue(f) => ("float", f.to_string()),
+            tiled::PropertyValue::IntValue(i) => ("int", i.to_string()),
+            tiled::PropertyValue::ColorValue(c) => ("color", format!("#{:08x}", c)),
+            tiled::PropertyValue::StringValue(ref s) => ("string", s.clone()),
+        };
+        writeln!(
+            w,
+            "{} <property name=\"{}\" type=\"{}\" value=\"{}\"/>",
+            indent,
+            escape(name),
+            kind,
+            escape(&value)
+        )?;
+    }
+    writeln!(w, "{}</properties>", indent)
+}
+
+fn write_tileset<W: Write>(
+    w: &mut W,
+    tileset: &tiled::Tileset,
+    properties: Option<&tiled::Properties>,
+) -> io::Result<()> {
+    writeln!(
+        w,
+        " <tileset firstgid=\"{}\" name=\"{}\" tilewidth=\"{}\" tileheight=\"{}\" \
+         spacing=\"{}\" margin=\"{}\">",
+        tileset.first_gid,
+        escape(&tileset.name),
+        tileset.tile_width,
+        tileset.tile_height,
+        tileset.spacing,
+        tileset.margin
+    )?;
+    if let Some(properties) = properties {
+        write_properties(w, "  ", properties)?;
+    }
+    for image in tileset.images.iter() {
+        writeln!(
+            w,
+            "  <image source=\"{}\" width=\"{}\" height=\"{}\"/>",
+            escape(&image.source),
+            image.width,
+            image.height
+        )?;
+    }
+    for tile in tileset.tiles.iter() {
+        if tile.properties.is_empty() {
+            continue;
+        }
+        writeln!(w, "  <tile id=\"{}\">", tile.id)?;
+        write_properties(w, "   ", &tile.properties)?;
+        writeln!(w, "  </tile>")?;
+    }
+    writeln!(w, " </tileset>")
+}
+
+/// Writes a reference to an external tileset, leaving its `.tsx` file as it is.
+fn write_external_tileset<W: Write>(
+    w: &mut W,
+    tileset: &tiled::Tileset,
+    source: &str,
+) -> io::Result<()> {
+    writeln!(
+        w,
+        " <tileset firstgid=\"{}\" source=\"{}\"/>",
+        tileset.first_gid,
+        escape(source)
+    )
+}
+
+fn write_layer<W: Write>(w: &mut W, map: &tiled::Map, layer: &tiled::Layer) -> io::Result<()> {
+    writeln!(
+        w,
+        " <layer name=\"{}\" width=\"{}\" height=\"{}\" opacity=\"{}\" visible=\"{}\">",
+        escape(&layer.name),
+        map.width,
+        map.height,
+        layer.opacity,
+        layer.visible as u8
+    )?;
+    write_properties(w, "  ", &layer.properties)?;
+
+    // CSV keeps the output diffable; Tiled converts it back to its default encoding on save.
+    writeln!(w, "  <data encoding=\"csv\">")?;
+    let row_count = layer.tiles.len();
+    for (y, row) in layer.tiles.iter().enumerate() {
+        let cells: Vec<String> = row.iter().map(|gid| gid.to_string()).collect();
+        let separator = if y + 1 < row_count { "," } else { "" };
+        writeln!(w, "{}{}", cells.join(","), separator)?;
+    }
+    writeln!(w, "  </data>")?;
+    writeln!(w, " </layer>")
+}
+
+fn write_points<W: Write>(w: &mut W, element: &str, points: &Vec<(f32, f32)>) -> io::Result<()> {
+    let points: Vec<String> = points
+        .iter()
+        .map(|&(x, y)| format!("{},{}", x, y))
+        .collect();
+    writeln!(w, "   <{} points=\"{}\"/>", element, points.join(" "))
+}
+
+fn write_object<W: Write>(w: &mut W, object: &tiled::Object) -> io::Result<()> {
+    write!(w, "  <object id=\"{}\"", object.id)?;
+    if !object.name.is_empty() {
+        write!(w, " name=\"{}\"", escape(&object.name))?;
+    }
+    if !object.obj_type.is_empty() {
+        write!(w, " type=\"{}\"", escape(&object.obj_type))?;
+    }
+    if object.gid != 0 {
+        write!(w, " gid=\"{}\"", object.gid)?;
+    }
+    write!(w, " x=\"{}\" y=\"{}\"", object.x, object.y)?;
+    if object.width != 0.0 || object.height != 0.0 {
+        write!(w, " width=\"{}\" height=\"{}\"", object.width, object.height)?;
+    }
+    if object.rotation != 0.0 {
+        write!(w, " rotation=\"{}\"", object.rotation)?;
+    }
+    if !object.visible {
+        write!(w, " visible=\"0\"")?;
+    }
+    writeln!(w, ">")?;
+
+    write_properties(w, "   ", &object.properties)?;
+    match object.shape {
+        tiled::ObjectShape::Rect { .. } => {}
+        tiled::ObjectShape::Ellipse { .. } => {
+            writeln!(w, "   <ellipse/>")?;
+        }
+        tiled::ObjectShape::Polyline { ref points } => {
+            write_points(w, "polyline", points)?;
+        }
+        tiled::ObjectShape::Polygon { ref points } => {
+            write_points(w, "polygon", points)?;
+        }
+    }
+    writeln!(w, "  </object>")
+}
+
+fn write_object_group<W: Write>(w: &mut W, group: &tiled::ObjectGroup) -> io::Result<()> {
+    write!(w, " <objectgroup name=\"{}\"", escape(&group.name))?;
+    if let Some(ref colour) = group.colour {
+        write!(w, " color=\"{}\"", colour_hex(colour))?;
+    }
+    writeln!(
+        w,
+        " opacity=\"{}\" visible=\"{}\">",
+        group.opacity,
+        group.visible as u8
+    )?;
+    for object in group.objects.iter() {
+        write_object(w, object)?;
+    }
+    writeln!(w, " </objectgroup>")
+}
+
+/// Serializes `map` as a TMX document that Tiled and `tiled::parse` can both read back.
+/// `tileset_properties` are written on the tilesets of the same index, since `tiled::Tileset`
+/// does not carry them. Tilesets with a `tileset_sources` entry are written as references to
+/// that `.tsx` file instead, as their image paths are relative to it.
+///
+/// Image layers are not written; nothing in the engine creates or edits them.
+pub fn write_map<W: Write>(
+    w: &mut W,
+    map: &tiled::Map,
+    tileset_properties: &Vec<tiled::Properties>,
+    tileset_sources: &Vec<Option<String>>,
+) -> io::Result<()> {
+    writeln!(w, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+    write!(
+        w,
+        "<map version=\"{}\" orientation=\"{}\" renderorder=\"right-down\" width=\"{}\" \
+         height=\"{}\" tilewidth=\"{}\" tileheight=\"{}\"",
+        escape(&map.version),
+        orientation_name(&map.orientation),
+        map.width,
+        map.height,
+        map.tile_width,
+        map.tile_height
+    )?;
+    if let Some(ref colour) = map.background_colour {
+        write!(w, " backgroundcolor=\"{}\"", colour_hex(colour))?;
+    }
+    writeln!(w, ">")?;
+
+    write_properties(w, " ", &map.properties)?;
+    for (index, tileset) in map.tilesets.iter().enumerate() {
+        match tileset_sources.get(index) {
+            Some(&Some(ref source)) => write_external_tileset(w, tileset, source)?,
+            _ => write_tileset(w, tileset, tileset_properties.get(index))?,
+        }
+    }
+    for layer in map.layers.iter() {
+        write_layer(w, map, layer)?;
+    }
+    for group in map.object_groups.iter() {
+        write_object_group(w, group)?;
+    }
+    writeln!(w, "</map>")
+}
+
+/// The `source` of every `<tileset>` in a TMX document, in tileset order: the path of its
+/// `.tsx` file relative to the TMX file, or `None` for tilesets stored in the map itself.
+/// `tiled::Tileset` does not keep it.
+pub fn parse_tileset_sources<R: io::Read>(
+    reader: R,
+) -> Result<Vec<Option<String>>, xml::reader::Error> {
+    let mut sources = Vec::new();
+    for event in xml::reader::EventReader::new(reader) {
+        if let XmlEvent::StartElement { name, attributes, .. } = event? {
+            if name.local_name == "tileset" {
+                let source = attributes.into_iter().find(|a| a.name.local_name == "source");
+                sources.push(source.map(|a| a.value));
+            }
+        }
+    }
+    Ok(sources)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_map() -> tiled::Map {
+        let file = fs::File::open("assets/tiled_base64_zlib.tmx").unwrap();
+        tiled::parse(file).unwrap()
+    }
+
+    #[test]
+    fn escapes_attribute_values() {
+        assert_eq!("a &lt;b&gt; &amp; &quot;c&quot;", escape("a <b> & \"c\""));
+    }
+
+    #[test]
+    fn round_trips_through_tiled() {
+        let map = sample_map();
+        let mut buffer = Vec::<u8>::new();
+        write_map(&mut buffer, &map, &Vec::new(), &Vec::new()).unwrap();
+
+        let reparsed = tiled::parse(io::Cursor::new(buffer)).unwrap();
+        assert_eq!(map.width, reparsed.width);
+        assert_eq!(map.height, reparsed.height);
+        assert_eq!(map.layers.len(), reparsed.layers.len());
+        assert_eq!(map.layers[0].tiles, reparsed.layers[0].tiles);
+        assert_eq!(map.tilesets[0].tiles.len(), reparsed.tilesets[0].tiles.len());
+        assert_eq!(
+            map.object_groups[0].objects.len(),
+            reparsed.object_groups[0].objects.len()
+        );
+    }
+
+    #[test]
+    fn writes_external_tilesets_as_references() {
+        let map = sample_map();
+        let mut buffer = Vec::<u8>::new();
+        let sources = vec![Some(String::from("tilesets/ground.tsx"))];
+        write_map(&mut buffer, &map, &Vec::new(), &sources).unwrap();
+        assert_eq!(sources, parse_tileset_sources(&buffer[..]).unwrap());
+    }
+}
@@ -0,0 +1,471 @@
+use std::*;
+use std::collections::{BTreeSet, VecDeque};
+use coordinates;
+use tiled;
+use tilesheet;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+#[derive(Debug)]
+pub enum Problem {
+    /// The map could not be loaded at all.
+    LoadFailed(tilesheet::TilesheetError),
+    /// A tileset image file does not exist. Holds (tileset name, image path).
+    MissingImage(String, path::PathBuf),
+    /// A tileset's tile size differs from the map's. Holds (tileset name, width, height).
+    TileSizeMismatch(String, u32, u32),
+    /// A tileset image is not a whole number of tiles. Holds (tileset name, width, height).
+    ImageSizeMismatch(String, i32, i32),
+    /// A layer's dimensions differ from the map's. Holds (layer name, width, height).
+    LayerSizeMismatch(String, usize, usize),
+    /// A tile refers to a gid no tileset provides. Holds (layer name, column, row, gid).
+    GidOutOfRange(String, usize, usize, u32),
+    /// A gid used on the map has no `walkable` property, neither on the tile nor on its tileset
+    /// or the map.
+    MissingWalkable(u32),
+    /// A spawn object sits on a tile that is not walkable, or off the map.
+    SpawnNotWalkable(String, coordinates::TileIndex),
+    /// No object of type `"spawn"` exists, so reachability was not checked.
+    NoSpawn,
+    /// Walkable tiles that cannot be reached from any spawn. Holds (count, an example tile).
+    Unreachable(usize, (usize, usize)),
+}
+
+impl Problem {
+    pub fn severity(&self) -> Severity {
+        match self {
+            &Problem::NoSpawn => Severity::Warning,
+            _ => Severity::Error,
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &Problem::LoadFailed(ref e) => write!(f, "could not load map: {:?}", e),
+            &Problem::MissingImage(ref tileset, ref path) => {
+                write!(f, "tileset '{}' image {:?} does not exist", tileset, path)
+            }
+            &Problem::TileSizeMismatch(ref tileset, w, h) => {
+                write!(f, "tileset '{}' has {}x{} tiles, unlike the map", tileset, w, h)
+            }
+            &Problem::ImageSizeMismatch(ref tileset, w, h) => {
+                write!(
+                    f,
+                    "tileset '{}' image is {}x{} px, not a whole number of tiles",
+                    tileset,
+                    w,
+                    h
+                )
+            }
+            &Problem::LayerSizeMismatch(ref layer, w, h) => {
+                write!(f, "layer '{}' is {}x{} tiles, unlike the map", layer, w, h)
+            }
+            &Problem::GidOutOfRange(ref layer, x, y, gid) => {
+                write!(f, "layer '{}' tile ({}, {}) has unknown gid {}", layer, x, y, gid)
+            }
+            &Problem::MissingWalkable(gid) => {
+                write!(f, "gid {} is used but has no 'walkable' property", gid)
+            }
+            &Problem::SpawnNotWalkable(ref name, index) => {
+                write!(
+                    f,
+                    "spawn '{}' is on non-walkable tile ({}, {})",
+                    name,
+                    index.x,
+                    index.y
+                )
+            }
+            &Problem::NoSpawn => write!(f, "no 'spawn' object; reachability not checked"),
+            &Problem::Unreachable(count, (x, y)) => {
+                write!(
+                    f,
+                    "{} walkable tiles are unreachable from spawn, e.g. ({}, {})",
+                    count,
+                    x,
+                    y
+                )
+            }
+        }
+    }
+}
+
+/// Walkability of the ground layer as a `[row][column]` grid, exactly as the game sees it
+/// (see `tilesheet::Tilesheet::is_gid_walkable`). Empty tiles are walkable.
+fn walkable_grid(tilesheet: &tilesheet::Tilesheet) -> Vec<Vec<bool>> {
+    match tilesheet.map().layers.get(tilesheet::GROUND_LAYER_INDEX) {
+        Some(layer) => {
+            layer
+                .tiles
+                .iter()
+                .map(|row| {
+                    row.iter()
+                        .map(|&gid| gid == 0 || tilesheet.is_gid_walkable(gid))
+                        .collect()
+                })
+                .collect()
+        }
+        None => Vec::new(),
+    }
+}
+
+fn check_tilesets(map: &tiled::Map, problems: &mut Vec<Problem>) {
+    for tileset in map.tilesets.iter() {
+        if tileset.tile_width != map.tile_width || tileset.tile_height != map.tile_height {
+            problems.push(Problem::TileSizeMismatch(
+                tileset.name.clone(),
+                tileset.tile_width,
+                tileset.tile_height,
+            ));
+        }
+        for image in tileset.images.iter() {
+            if image.width < 0 || image.height < 0 ||
+                !tilesheet::image_fits_tileset(tileset, image.width as u32, image.height as u32)
+            {
+                problems.push(Problem::ImageSizeMismatch(
+                    tileset.name.clone(),
+                    image.width,
+                    image.height,
+                ));
+            }
+        }
+    }
+}
+
+/// Tileset images of the map at `tmx_path` that do not exist. `Tilesheet` would only report
+/// the first of them, as an image error.
+fn check_images(tmx_path: &path::Path, problems: &mut Vec<Problem>) {
+    let map = match tiled::parse_file(tmx_path) {
+        Ok(map) => map,
+        Err(_) => return, // loading the tilesheet reports this
+    };
+    if let Ok(image_paths) = tilesheet::tileset_image_paths(tmx_path, &map) {
+        for (tileset, image_path) in map.tilesets.iter().zip(image_paths) {
+            if !image_path.exists() {
+                problems.push(Problem::MissingImage(tileset.name.clone(), image_path));
+            }
+        }
+    }
+}
+
+fn check_layers(tilesheet: &tilesheet::Tilesheet, problems: &mut Vec<Problem>) {
+    let map = tilesheet.map();
+    let mut used = BTreeSet::new();
+    for layer in map.layers.iter() {
+        let width = layer.tiles.first().map(|row| row.len()).unwrap_or(0);
+        if layer.tiles.len() != map.height as usize || width != map.width as usize ||
+            layer.tiles.iter().any(|row| row.len() != width)
+        {
+            problems.push(Problem::LayerSizeMismatch(
+                layer.name.clone(),
+                width,
+                layer.tiles.len(),
+            ));
+        }
+        for (y, row) in layer.tiles.iter().enumerate() {
+            for (x, &gid) in row.iter().enumerate() {
+                if gid == 0 {
+                    continue; // tiled counts from 1; 0 is empty
+                }
+                if tilesheet.contains_gid(gid) {
+                    used.insert(gid & tilesheet::GID_MASK);
+                } else {
+                    problems.push(Problem::GidOutOfRange(layer.name.clone(), x, y, gid));
+                }
+            }
+        }
+    }
+
+    for gid in used {
+        if tilesheet.tile_properties(gid).value("walkable").is_none() {
+            problems.push(Problem::MissingWalkable(gid));
+        }
+    }
+}
+
+fn check_spawns(tilesheet: &tilesheet::Tilesheet, problems: &mut Vec<Problem>) {
+    let map = tilesheet.map();
+    let walkable = walkable_grid(tilesheet);
+    let mut frontier = VecDeque::new();
+    let mut reached = vec![vec![false; map.width as usize]; map.height as usize];
+    let tile_size = coordinates::TileSize {
+        width: map.tile_width as f64,
+        height: map.tile_height as f64,
+    };
+
+    for group in map.object_groups.iter() {
+        for object in group.objects.iter().filter(|o| o.obj_type == "spawn") {
+            let index = coordinates::WorldPoint::new(object.x as f64, object.y as f64)
+                .to_tile(tile_size);
+            let open = index.to_usize().and_then(|(x, y)| {
+                walkable.get(y).and_then(|row| row.get(x))
+            });
+            match (open, index.to_usize()) {
+                (Some(&true), Some((x, y))) => {
+                    if !reached[y][x] {
+                        reached[y][x] = true;
+                        frontier.push_back((x, y));
+                    }
+                }
+                _ => problems.push(Problem::SpawnNotWalkable(object.name.clone(), index)),
+            }
+        }
+    }
+
+    if frontier.is_empty() {
+        if !problems.iter().any(|p| match p {
+            &Problem::SpawnNotWalkable(..) => true,
+            _ => false,
+        })
+        {
+            problems.push(Problem::NoSpawn);
+        }
+        return;
+    }
+
+    while let Some((x, y)) = frontier.pop_front() {
+        let mut neighbours = vec![(x + 1, y), (x, y + 1)];
+        if x > 0 {
+            neighbours.push((x - 1, y));
+        }
+        if y > 0 {
+            neighbours.push((x, y - 1));
+        }
+        for (nx, ny) in neighbours {
+            let open = walkable.get(ny).and_then(|row| row.get(nx)) == Some(&true);
+            if open && !reached[ny][nx] {
+                reached[ny][nx] = true;
+                frontier.push_back((nx, ny));
+            }
+        }
+    }
+
+    let mut unreachable = 0;
+    let mut example = None;
+    for (y, row) in walkable.iter().enumerate() {
+        for (x, &open) in row.iter().enumerate() {
+            if open && !reached[y][x] {
+                unreachable += 1;
+                example = example.or(Some((x, y)));
+            }
+        }
+    }
+    if let Some(example) = example {
+        problems.push(Problem::Unreachable(unreachable, example));
+    }
+}
+
+/// Checks an already loaded map.
+pub fn validate_tilesheet(tilesheet: &tilesheet::Tilesheet) -> Vec<Problem> {
+    let map = tilesheet.map();
+    let mut problems = Vec::new();
+    check_tilesets(map, &mut problems);
+    check_layers(tilesheet, &mut problems);
+
+    // Reachability is meaningless on a map of the wrong shape.
+    let shape_ok = !problems.iter().any(|p| match p {
+        &Problem::LayerSizeMismatch(..) => true,
+        _ => false,
+    });
+    if shape_ok {
+        check_spawns(tilesheet, &mut problems);
+    }
+    problems
+}
+
+/// Loads the map at `tmx_path` through `Tilesheet` and reports every problem found.
+pub fn validate_path(tmx_path: &path::Path) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    check_images(tmx_path, &mut problems);
+    if !problems.is_empty() {
+        return problems;
+    }
+    match tilesheet::Tilesheet::from_path(tmx_path) {
+        Ok(tilesheet) => validate_tilesheet(&tilesheet),
+        Err(e) => vec![Problem::LoadFailed(e)],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    /// The demo map, with a spawn object at the center of tile `(x, y)` if given.
+    fn demo_map(spawn: Option<(usize, usize)>) -> (tilesheet::Tilesheet, tiled::Map) {
+        let demo = tilesheet::demo();
+        let mut map = demo.map().clone();
+        if let Some((x, y)) = spawn {
+            let position = coordinates::TileIndex::from_usize(x, y).center(demo.tile_size());
+            map.object_groups[0].objects.push(tiled::Object {
+                id: 5,
+                gid: 0,
+                name: String::from("spawn"),
+                obj_type: String::from("spawn"),
+                width: 0.0,
+                height: 0.0,
+                x: position.x as f32,
+                y: position.y as f32,
+                rotation: 0.0,
+                visible: true,
+                shape: tiled::ObjectShape::Rect {
+                    width: 0.0,
+                    height: 0.0,
+                },
+                properties: HashMap::new(),
+            });
+        }
+        (demo, map)
+    }
+
+    /// Walkable ground (gid 6) split by a wall (gid 5) down column 10.
+    fn walled_ground(map: &mut tiled::Map) {
+        for row in map.layers[tilesheet::GROUND_LAYER_INDEX].tiles.iter_mut() {
+            for (x, gid) in row.iter_mut().enumerate() {
+                *gid = if x == 10 { 5 } else { 6 };
+            }
+        }
+    }
+
+    fn validate_map(demo: &tilesheet::Tilesheet, map: tiled::Map) -> Vec<Problem> {
+        validate_tilesheet(&tilesheet::Tilesheet::from_template(demo, map))
+    }
+
+    #[test]
+    fn demo_map_has_no_errors() {
+        let problems = validate_path(path::Path::new("assets/tiled_base64_zlib.tmx"));
+        for problem in problems.iter() {
+            assert_eq!(Severity::Warning, problem.severity(), "{}", problem);
+        }
+    }
+
+    #[test]
+    fn walkability_falls_back_like_the_game() {
+        let demo = tilesheet::demo();
+        let mut map = demo.map().clone();
+        for tile in map.tilesets[0].tiles.iter_mut() {
+            tile.properties.remove("walkable");
+        }
+        map.properties.insert(
+            String::from("walkable"),
+            tiled::PropertyValue::BoolValue(false),
+        );
+        let tilesheet = tilesheet::Tilesheet::from_template(&demo, map);
+
+        let problems = validate_tilesheet(&tilesheet);
+        assert!(!problems.iter().any(|p| match p {
+            &Problem::MissingWalkable(..) => true,
+            _ => false,
+        }));
+        let ground = &tilesheet.map().layers[tilesheet::GROUND_LAYER_INDEX].tiles;
+        for (row, walkable_row) in ground.iter().zip(walkable_grid(&tilesheet)) {
+            for (&gid, walkable) in row.iter().zip(walkable_row) {
+                assert_eq!(gid == 0, walkable);
+            }
+        }
+    }
+
+    #[test]
+    fn missing_map_fails_to_load() {
+        let problems = validate_path(path::Path::new("assets/no_such_map.tmx"));
+        assert_eq!(1, problems.len());
+        assert_eq!(Severity::Error, problems[0].severity());
+    }
+
+    #[test]
+    fn unknown_gids_are_out_of_range() {
+        let (demo, mut map) = demo_map(None);
+        let unknown = demo.tile_count() + 1;
+        map.layers[0].tiles[0][0] = unknown;
+        map.layers[0].tiles[0][1] = 0x8000_0005; // flipped, but a known tile
+        let out_of_range: Vec<(usize, usize, u32)> = validate_map(&demo, map)
+            .into_iter()
+            .filter_map(|p| match p {
+                Problem::GidOutOfRange(_, x, y, gid) => Some((x, y, gid)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(vec![(0, 0, unknown)], out_of_range);
+    }
+
+    #[test]
+    fn spawn_on_a_wall_is_not_walkable() {
+        let (demo, mut map) = demo_map(Some((10, 5)));
+        walled_ground(&mut map);
+        let problems = validate_map(&demo, map);
+        assert!(problems.iter().any(|p| match p {
+            &Problem::SpawnNotWalkable(_, index) => (index.x, index.y) == (10, 5),
+            _ => false,
+        }));
+    }
+
+    #[test]
+    fn tiles_behind_a_wall_are_unreachable() {
+        let (demo, mut map) = demo_map(Some((1, 1)));
+        walled_ground(&mut map);
+        let (width, height) = (map.width as usize, map.height as usize);
+        let problems = validate_map(&demo, map);
+        assert!(problems.iter().any(|p| match p {
+            &Problem::Unreachable(count, example) => {
+                count == (width - 11) * height && example == (11, 0)
+            }
+            _ => false,
+        }));
+    }
+
+    #[test]
+    fn short_layers_are_reported() {
+        let (demo, mut map) = demo_map(None);
+        map.layers[0].tiles.pop();
+        let problems = validate_map(&demo, map);
+        assert!(problems.iter().any(|p| match p {
+            &Problem::LayerSizeMismatch(_, width, height) => (width, height) == (100, 99),
+            _ => false,
+        }));
+        assert!(!problems.iter().any(|p| match p {
+            &Problem::NoSpawn => true,
+            _ => false,
+        }));
+    }
+
+    #[test]
+    fn image_size_allows_spacing_and_margin() {
+        let (_, mut map) = demo_map(None);
+        map.tilesets[0].spacing = 2;
+        map.tilesets[0].margin = 1;
+        // 14 by 6 tiles of 32 px, 2 px apart, inside a 1 px margin.
+        map.tilesets[0].images[0].width = 2 * 1 + 14 * 32 + 13 * 2;
+        map.tilesets[0].images[0].height = 2 * 1 + 6 * 32 + 5 * 2;
+        let mut problems = Vec::new();
+        check_tilesets(&map, &mut problems);
+        assert!(problems.is_empty(), "{:?}", problems);
+
+        map.tilesets[0].images[0].width += 1;
+        check_tilesets(&map, &mut problems);
+        assert_eq!(1, problems.len());
+    }
+
+    #[test]
+    fn missing_images_are_reported() {
+        let directory = env::temp_dir().join("validate_missing_image_test");
+        fs::create_dir_all(&directory).unwrap();
+        let tmx_path = directory.join("map.tmx");
+        fs::copy("assets/tiled_base64_zlib.tmx", &tmx_path).unwrap();
+        let _ = fs::remove_file(directory.join("tilesheet.png"));
+
+        let problems = validate_path(&tmx_path);
+        assert_eq!(1, problems.len());
+        match problems[0] {
+            Problem::MissingImage(ref tileset, ref image_path) => {
+                assert_eq!("tilesheet", tileset);
+                assert_eq!(&directory.join("tilesheet.png"), image_path);
+            }
+            ref problem => panic!("unexpected problem: {}", problem),
+        }
+    }
+}
@@ -0,0 +1,472 @@
+//! Several maps placed side by side in one coordinate space, as described by a Tiled `.world`
+//! file. Maps near a point of interest (usually the hero) are loaded in the background and maps
+//! far from it are unloaded, so the player can walk across map borders without a loading screen.
+//!
+//! World coordinates are `WorldPoint`s with (0, 0) at the world's origin. Each loaded map is a
+//! `Region` whose tilesheet keeps its own, map-local coordinates; `Region::to_local` converts.
+
+use std::*;
+use std::sync::mpsc;
+use chunk_cache;
+use coordinates::{Camera, WorldPoint};
+use gfx_core;
+use gfx_device_gl;
+use piston_window;
+use piston_window::*;
+use roof;
+use serde_json;
+use tilesheet;
+
+/// Maps closer than this many world px to the focus are loaded.
+static LOAD_DISTANCE: f64 = 512.0;
+
+/// Maps farther than this many world px from the focus are unloaded. Larger than
+/// `LOAD_DISTANCE` so that walking back and forth along the boundary does not reload a map.
+static UNLOAD_DISTANCE: f64 = 1024.0;
+
+#[derive(Debug)]
+pub enum WorldError {
+    IoError(io::Error),
+    JsonError(serde_json::Error),
+    NoParentPath,
+}
+
+/// Why `World::update` could not bring a map up to date.
+#[derive(Debug)]
+pub enum MapError {
+    /// Holds the map's TMX file.
+    LoadFailed(path::PathBuf, tilesheet::TilesheetError),
+    /// The thread loading the map panicked. Holds the map's TMX file.
+    LoaderPanicked(path::PathBuf),
+    TextureError(gfx_core::factory::CombinedError),
+}
+
+#[derive(Deserialize)]
+struct WorldFileMap {
+    #[serde(rename = "fileName")]
+    file_name: String,
+    x: f64,
+    y: f64,
+    width: f64,
+    height: f64,
+}
+
+#[derive(Deserialize)]
+struct WorldFile {
+    maps: Vec<WorldFileMap>,
+}
+
+/// Where a map sits in the world.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Placement {
+    /// The map's TMX file, or `None` for a map that only exists in memory.
+    pub tmx_path: Option<path::PathBuf>,
+    /// World position of the map's top-left corner.
+    pub origin: WorldPoint,
+    /// Size in world px.
+    pub size: [f64; 2],
+}
+
+impl Placement {
+    pub fn contains(&self, point: WorldPoint) -> bool {
+        point.x >= self.origin.x && point.y >= self.origin.y &&
+            point.x < self.origin.x + self.size[0] && point.y < self.origin.y + self.size[1]
+    }
+
+    /// Distance from `point` to the nearest point of the map; 0 if `point` is on it.
+    pub fn distance(&self, point: WorldPoint) -> f64 {
+        let dx = (self.origin.x - point.x)
+            .max(point.x - (self.origin.x + self.size[0]))
+            .max(0.0);
+        let dy = (self.origin.y - point.y)
+            .max(point.y - (self.origin.y + self.size[1]))
+            .max(0.0);
+        dx.hypot(dy)
+    }
+}
+
+/// Reads the map placements of a `.world` file. Map paths are resolved against `base_path`,
+/// the directory holding the `.world` file. Pattern-based worlds (`"patterns"`) are not
+/// supported; only maps listed under `"maps"` are placed.
+pub fn parse_world<R: io::Read>(
+    reader: R,
+    base_path: &path::Path,
+) -> Result<Vec<Placement>, WorldError> {
+    let world: WorldFile = serde_json::from_reader(reader).map_err(
+        |e| WorldError::JsonError(e),
+    )?;
+    Ok(
+        world
+            .maps
+            .into_iter()
+            .map(|map| {
+                Placement {
+                    tmx_path: Some(base_path.join(map.file_name)),
+                    origin: WorldPoint::new(map.x, map.y),
+                    size: [map.width, map.height],
+                }
+            })
+            .collect(),
+    )
+}
+
+/// A loaded map and everything needed to draw it.
+pub struct Region {
+    origin: WorldPoint,
+    tilesheet: tilesheet::Tilesheet,
+    chunk_cache: chunk_cache::ChunkCache,
+
+    /// The tilesheet's atlas, for the editor palette.
+    tiles_texture: G2dTexture,
+}
+
+impl Region {
+    fn new(
+        factory: &mut gfx_device_gl::Factory,
+        tilesheet: tilesheet::Tilesheet,
+        origin: WorldPoint,
+    ) -> Result<Self, gfx_core::factory::CombinedError> {
+        let tiles_texture =
+            Texture::from_image(factory, tilesheet.atlas(), &TextureSettings::new())?;
+        let mut chunk_cache = chunk_cache::ChunkCache::new();
+        chunk_cache.update(factory, &tilesheet)?;
+        Ok(Region {
+            origin: origin,
+            tilesheet: tilesheet,
+            chunk_cache: chunk_cache,
+            tiles_texture: tiles_texture,
+        })
+    }
+
+    pub fn origin(&self) -> WorldPoint {
+        self.origin
+    }
+
+    pub fn tilesheet(&self) -> &tilesheet::Tilesheet {
+        &self.tilesheet
+    }
+
+    pub fn tilesheet_mut(&mut self) -> &mut tilesheet::Tilesheet {
+        &mut self.tilesheet
+    }
+
+    pub fn tiles_texture(&self) -> &G2dTexture {
+        &self.tiles_texture
+    }
+
+    /// `point` in the map's own coordinates.
+    pub fn to_local(&self, point: WorldPoint) -> WorldPoint {
+        WorldPoint::new(point.x - self.origin.x, point.y - self.origin.y)
+    }
+
+    /// `camera` moved into the map's own coordinates.
+    pub fn local_camera(&self, camera: &Camera) -> Camera {
+        Camera::new(self.to_local(camera.center), camera.viewport)
+    }
+}
+
+enum Slot {
+    Unloaded,
+    Loading(mpsc::Receiver<Result<tilesheet::Tilesheet, tilesheet::TilesheetError>>),
+    /// Waiting for its textures, which must be created on the render thread.
+    Loaded(tilesheet::Tilesheet),
+    Ready(Region),
+    /// Loading failed; not retried until the map is unloaded again.
+    Failed,
+}
+
+impl Slot {
+    /// Whether the slot holds a map with tile edits. The editor saves next to the TMX file, not
+    /// over it, so unloading such a map would lose its edits; it stays loaded instead.
+    fn is_edited(&self) -> bool {
+        match *self {
+            Slot::Loaded(ref tilesheet) => tilesheet.revision() > 0,
+            Slot::Ready(ref region) => region.tilesheet.revision() > 0,
+            _ => false,
+        }
+    }
+}
+
+/// A set of maps in one coordinate space. See the module docs.
+pub struct World {
+    placements: Vec<Placement>,
+    slots: Vec<Slot>,
+
+    /// Placement indices of maps that became ready since the last `take_newly_loaded`.
+    newly_loaded: Vec<usize>,
+}
+
+impl World {
+    /// Reads a Tiled `.world` file. No map is loaded until the first `update`.
+    pub fn from_path(world_path: &path::Path) -> Result<Self, WorldError> {
+        let world_file = fs::File::open(world_path).map_err(|e| WorldError::IoError(e))?;
+        let base_path = world_path.parent().ok_or(WorldError::NoParentPath)?;
+        let placements = parse_world(io::BufReader::new(world_file), base_path)?;
+        let slots = placements.iter().map(|_| Slot::Unloaded).collect();
+        Ok(World {
+            placements: placements,
+            slots: slots,
+            newly_loaded: Vec::new(),
+        })
+    }
+
+    /// A world of just `tilesheet`, placed at the origin. It is never unloaded.
+    pub fn from_tilesheet(tilesheet: tilesheet::Tilesheet) -> Self {
+        let map = tilesheet.map();
+        let placement = Placement {
+            tmx_path: None,
+            origin: WorldPoint::new(0.0, 0.0),
+            size: [
+                (map.width * map.tile_width) as f64,
+                (map.height * map.tile_height) as f64,
+            ],
+        };
+        World {
+            placements: vec![placement],
+            slots: vec![Slot::Loaded(tilesheet)],
+            newly_loaded: Vec::new(),
+        }
+    }
+
+    pub fn placements(&self) -> &Vec<Placement> {
+        &self.placements
+    }
+
+    /// Starts loading maps near `focus`, unloads maps far from it, and brings the textures of
+    /// loaded maps up to date. Loading happens on background threads; a map shows up in a later
+    /// call once it is ready. Edited maps are never unloaded. A map that cannot be loaded or
+    /// whose textures cannot be created is marked failed, and the first such error is returned
+    /// once every map is updated.
+    pub fn update(
+        &mut self,
+        factory: &mut gfx_device_gl::Factory,
+        focus: WorldPoint,
+    ) -> Result<(), MapError> {
+        // An error only affects its own map; the rest still update, and the first error is
+        // returned at the end.
+        let mut first_error = None;
+        for (placement, slot) in self.placements.iter().zip(self.slots.iter_mut()) {
+            let distance = placement.distance(focus);
+            let tmx_path = match placement.tmx_path {
+                Some(ref tmx_path) => tmx_path,
+                None => continue, // In-memory maps cannot be reloaded, so they stay.
+            };
+            if distance > UNLOAD_DISTANCE && !slot.is_edited() {
+                *slot = Slot::Unloaded;
+            } else if distance <= LOAD_DISTANCE {
+                if let Slot::Unloaded = *slot {
+                    let (sender, receiver) = mpsc::channel();
+                    let tmx_path = tmx_path.clone();
+                    thread::spawn(move || {
+                        // The receiver is gone if the map was unloaded meanwhile.
+                        let _ = sender.send(tilesheet::Tilesheet::from_path(&tmx_path));
+                    });
+                    *slot = Slot::Loading(receiver);
+                }
+            }
+
+            let received = match *slot {
+                Slot::Loading(ref receiver) => {
+                    match receiver.try_recv() {
+                        Ok(result) => {
+                            Some(result.map_err(|e| MapError::LoadFailed(tmx_path.clone(), e)))
+                        }
+                        Err(mpsc::TryRecvError::Empty) => None,
+                        Err(mpsc::TryRecvError::Disconnected) => {
+                            Some(Err(MapError::LoaderPanicked(tmx_path.clone())))
+                        }
+                    }
+                }
+                _ => None,
+            };
+            match received {
+                Some(Ok(tilesheet)) => *slot = Slot::Loaded(tilesheet),
+                Some(Err(e)) => {
+                    first_error = first_error.or(Some(e));
+                    *slot = Slot::Failed;
+                }
+                None => {}
+            }
+        }
+
+        let slots = self.placements.iter().zip(self.slots.iter_mut()).enumerate();
+        for (index, (placement, slot)) in slots {
+            if let Slot::Ready(ref mut region) = *slot {
+                if let Err(e) = region.chunk_cache.update(factory, &region.tilesheet) {
+                    first_error = first_error.or(Some(MapError::TextureError(e)));
+                }
+                continue;
+            }
+            if let Slot::Loaded(_) = *slot {
+                let tilesheet = match mem::replace(slot, Slot::Failed) {
+                    Slot::Loaded(tilesheet) => tilesheet,
+                    _ => unreachable!(),
+                };
+                // On error the slot stays `Failed` instead of reloading on every update.
+                match Region::new(factory, tilesheet, placement.origin) {
+                    Ok(region) => {
+                        *slot = Slot::Ready(region);
+                        self.newly_loaded.push(index);
+                    }
+                    Err(e) => first_error = first_error.or(Some(MapError::TextureError(e))),
+                }
+            }
+        }
+        match first_error {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    /// Placement indices of the maps that finished loading since the last call, e.g. to spawn
+    /// their objects.
+    pub fn take_newly_loaded(&mut self) -> Vec<usize> {
+        mem::replace(&mut self.newly_loaded, Vec::new())
+    }
+
+    /// Index of the map containing `point`, loaded or not.
+    pub fn placement_index_at(&self, point: WorldPoint) -> Option<usize> {
+        self.placements.iter().position(|p| p.contains(point))
+    }
+
+    pub fn region(&self, index: usize) -> Option<&Region> {
+        match self.slots.get(index) {
+            Some(&Slot::Ready(ref region)) => Some(region),
+            _ => None,
+        }
+    }
+
+    pub fn region_mut(&mut self, index: usize) -> Option<&mut Region> {
+        match self.slots.get_mut(index) {
+            Some(&mut Slot::Ready(ref mut region)) => Some(region),
+            _ => None,
+        }
+    }
+
+    /// The loaded map containing `point`, if any.
+    pub fn region_at(&self, point: WorldPoint) -> Option<&Region> {
+        self.placement_index_at(point).and_then(
+            |index| self.region(index),
+        )
+    }
+
+    pub fn region_at_mut(&mut self, point: WorldPoint) -> Option<&mut Region> {
+        match self.placement_index_at(point) {
+            Some(index) => self.region_mut(index),
+            None => None,
+        }
+    }
+
+    /// Loaded maps and their placement indices, in placement order.
+    pub fn regions(&self) -> Vec<(usize, &Region)> {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter_map(|(index, slot)| match *slot {
+                Slot::Ready(ref region) => Some((index, region)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Whether the tile of layer `layer_index` under `point` can be walked on. Space between
+    /// maps is walkable, like the space around a single map; a map that is still loading is not,
+    /// so nothing walks onto tiles it cannot see.
+    pub fn is_walkable(&self, layer_index: usize, point: WorldPoint) -> bool {
+        match self.placement_index_at(point) {
+            Some(index) => {
+                match self.region(index) {
+                    Some(region) => {
+                        region.tilesheet.is_walkable(layer_index, region.to_local(point))
+                    }
+                    None => false,
+                }
+            }
+            None => true,
+        }
+    }
+
+    /// Background color of the map under `point`, or of the first loaded map.
+    pub fn background_color(&self, point: WorldPoint) -> [f32; 4] {
+        let first = self.regions().first().map(|&(_, region)| region);
+        match self.region_at(point).or(first) {
+            Some(region) => region.tilesheet.background_color(),
+            None => [0.5, 0.5, 0.5, 1.0],
+        }
+    }
+
+    /// Draws the visible tile layers of every loaded map, as seen by `camera`, with roofs
+    /// faded by `roofs`.
+    pub fn render(
+        &self,
+        context: piston_window::Context,
+        renderer: &mut G2d,
+        camera: &Camera,
+        roofs: &roof::Roofs,
+    ) {
+        for (map_index, region) in self.regions() {
+            let local_camera = region.local_camera(camera);
+            for (layer_index, layer) in region.tilesheet.map().layers.iter().enumerate() {
+                if !layer.visible {
+                    continue;
+                }
+                region.chunk_cache.render_layer(
+                    context,
+                    renderer,
+                    &region.tilesheet,
+                    &local_camera,
+                    layer_index,
+                    layer.opacity * roofs.alpha(map_index, layer_index),
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_world_file() {
+        let json = r#"{
+            "maps": [
+                {"fileName": "town.tmx", "x": 0, "y": 0, "width": 640, "height": 480},
+                {"fileName": "forest.tmx", "x": 640, "y": -160, "width": 320, "height": 640}
+            ],
+            "onlyShowAdjacentMaps": false,
+            "type": "world"
+        }"#;
+        let placements = parse_world(json.as_bytes(), path::Path::new("assets")).unwrap();
+        assert_eq!(2, placements.len());
+        assert_eq!(
+            Some(path::PathBuf::from("assets/forest.tmx")),
+            placements[1].tmx_path
+        );
+        assert_eq!(WorldPoint::new(640.0, -160.0), placements[1].origin);
+        assert!(placements[1].contains(WorldPoint::new(700.0, -100.0)));
+        assert!(!placements[0].contains(WorldPoint::new(640.0, 0.0)));
+    }
+
+    #[test]
+    fn distance_to_placement() {
+        let placement = Placement {
+            tmx_path: None,
+            origin: WorldPoint::new(100.0, 100.0),
+            size: [100.0, 50.0],
+        };
+        assert_eq!(0.0, placement.distance(WorldPoint::new(150.0, 120.0)));
+        assert_eq!(30.0, placement.distance(WorldPoint::new(230.0, 120.0)));
+        assert_eq!(50.0, placement.distance(WorldPoint::new(70.0, 190.0)));
+    }
+
+    #[test]
+    fn maps_block_movement_until_ready() {
+        let tilesheet = tilesheet::demo();
+        let world = World::from_tilesheet(tilesheet);
+        // Not yet turned into a region, so the map counts as still loading.
+        assert!(!world.is_walkable(0, WorldPoint::new(1.0, 1.0)));
+        assert!(world.is_walkable(0, WorldPoint::new(-1.0, -1.0)));
+        assert_eq!(Some(0), world.placement_index_at(WorldPoint::new(1.0, 1.0)));
+    }
+}
@@ -0,0 +1,37 @@
+extern crate roll_play_ge;
+
+use roll_play_ge::validate;
+use std::path::Path;
+use std::process;
+
+// Usage: validate_map <map.tmx>...
+//
+// Prints every problem found in each map and exits with status 1 if any of them is an error,
+// so broken maps can be caught before playtest (e.g. from CI or a pre-commit hook).
+
+fn main() {
+    let paths: Vec<String> = std::env::args().skip(1).collect();
+    if paths.is_empty() {
+        eprintln!("usage: validate_map <map.tmx>...");
+        process::exit(2);
+    }
+
+    let mut error_count = 0;
+    for tmx_path in paths.iter() {
+        for problem in validate::validate_path(Path::new(tmx_path)) {
+            let label = match problem.severity() {
+                validate::Severity::Warning => "warning",
+                validate::Severity::Error => {
+                    error_count += 1;
+                    "error"
+                }
+            };
+            println!("{}: {}: {}", tmx_path, label, problem);
+        }
+    }
+
+    if error_count > 0 {
+        println!("{} error(s)", error_count);
+        process::exit(1);
+    }
+}